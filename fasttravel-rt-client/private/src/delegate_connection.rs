@@ -1,14 +1,24 @@
-use log::{error, trace};
+use log::{error, info, trace};
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
-use fasttravel_rt_proto::{helpers as proto_helpers, realtime, RealtimeService};
+use fasttravel_rt_proto::{
+    helpers as proto_helpers,
+    realtime::{self, connection::Feature},
+    RealtimeService,
+};
 
 use crate::{
     message_broker::RealtimeMessageBroker, realtime_module::ServiceDelegatePrivate,
     MessageDispatcher,
 };
 
+/// Version of the client-sdk sent on the ticket handshake.
+const SDK_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Optional features of the realtime protocol the client-sdk supports.
+const SDK_FEATURES: [Feature; 1] = [Feature::Batching];
+
 /// Wrapper around the ConnectionServiceKernel. We send this wrapper to JS
 /// that it uses to communicate wwith the kernel.
 #[wasm_bindgen]
@@ -46,7 +56,11 @@ impl ConnectionServiceKernel {
     pub(crate) async fn perform_websocket_ticket_handshake(&self, ticket: String) -> bool {
         trace!("perform_websocket_ticket_handshake_ticket {}", ticket);
 
-        let req = proto_helpers::connection::create_ticket_handshake_request(ticket);
+        let req = proto_helpers::connection::create_ticket_handshake_request(
+            ticket,
+            SDK_VERSION.to_string(),
+            &SDK_FEATURES,
+        );
         trace!("perform_websocket_ticket_handshake_req_size {}", req.len());
 
         self.broker
@@ -55,18 +69,30 @@ impl ConnectionServiceKernel {
             .map(|res| {
                 trace!("send_proto_request_to_server_res_size: {}", res.len());
 
-                if let Some(realtime::connection::message::Payload::HandshakeRes(res)) =
-                    proto_helpers::connection::decode_connection_message_and_extract_payload(res)
-                {
-                    return res.success;
-                } else {
-                    error!("send_proto_request_to_server_res_decode_error");
-                }
+                let payload =
+                    proto_helpers::connection::decode_connection_message_and_extract_payload(res);
 
-                false
+                match payload {
+                    Ok(realtime::connection::message::Payload::HandshakeRes(res)) => {
+                        let features =
+                            proto_helpers::connection::features_from_handshake(&res.features);
+                        info!("websocket_ticket_handshake_protocol: {}", res.protocol);
+                        info!("websocket_ticket_handshake_features: {:?}", features);
+
+                        res.success
+                    }
+                    Ok(_) => {
+                        error!("send_proto_request_to_server_res_unexpected_payload");
+                        false
+                    }
+                    Err(e) => {
+                        error!("send_proto_request_to_server_res_decode_error: {}", e);
+                        false
+                    }
+                }
             })
-            .unwrap_or_else(|| {
-                error!("send_proto_request_to_server_error");
+            .unwrap_or_else(|e| {
+                error!("send_proto_request_to_server_error: {}", e);
                 false
             })
     }
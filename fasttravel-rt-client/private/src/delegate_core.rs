@@ -1,10 +1,17 @@
 use log::{error, trace};
+use std::cell::Cell;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
+use fasttravel_rt_proto::{
+    helpers::core as proto_core,
+    realtime::core::{message::Payload, SubscriptionStatus},
+    RealtimeService,
+};
+
 use crate::{
     message_broker::RealtimeMessageBroker, realtime_module::ServiceDelegatePrivate,
-    CoreEventMessage, MessageDispatcher, EventEnvelope,
+    CoreEventMessage, MessageDispatcher, EventEnvelope, TopicEventMessage,
 };
 
 /// Wrapper around the CoreServiceKernel. We send this wrapper to JS.
@@ -20,6 +27,36 @@ impl CoreServiceDelegate {
     pub async fn send_text_message(&self, msg: &str) {
         self.private.send_text_message_to_server(msg).await
     }
+
+    /// Synchronize the local clock with the server clock.
+    pub async fn sync_clock(&self) -> Result<(), JsValue> {
+        self.private.sync_clock().await
+    }
+
+    /// Current server time in milliseconds since the unix epoch.
+    pub fn server_now(&self) -> f64 {
+        self.private.server_now()
+    }
+
+    /// Round trip time in milliseconds of the last clock synchronization.
+    pub fn round_trip_time(&self) -> Option<f64> {
+        self.private.clock_sync().map(|sync| sync.rtt)
+    }
+
+    /// Subscribe to the named topic of the cospace.
+    pub async fn subscribe_topic(&self, topic: &str) -> Result<(), JsValue> {
+        self.private.update_subscription(topic, true).await
+    }
+
+    /// Unsubscribe from the named topic of the cospace.
+    pub async fn unsubscribe_topic(&self, topic: &str) -> Result<(), JsValue> {
+        self.private.update_subscription(topic, false).await
+    }
+
+    /// Publish to the clients subscribed to the named topic of the cospace.
+    pub async fn publish_to_topic(&self, topic: &str, payload: &[u8]) {
+        self.private.publish_to_topic(topic, payload).await
+    }
 }
 
 impl ServiceDelegatePrivate<CoreServiceKernel> for CoreServiceDelegate {
@@ -38,6 +75,20 @@ impl ServiceDelegatePrivate<CoreServiceKernel> for CoreServiceDelegate {
 pub(crate) struct CoreServiceKernel {
     broker: Rc<RealtimeMessageBroker>,
     js_dispatcher: MessageDispatcher,
+    clock: Cell<Option<ClockSync>>,
+}
+
+/// Number of round trips of a clock synchronization.
+const CLOCK_SYNC_SAMPLES: u32 = 5;
+
+/// Estimate of the server clock from a server-time round trip.
+/// All values are in milliseconds.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClockSync {
+    /// round trip time of the request.
+    pub(crate) rtt: f64,
+    /// offset to add to the local clock to get the server clock.
+    pub(crate) offset: f64,
 }
 
 impl CoreServiceKernel {
@@ -45,9 +96,122 @@ impl CoreServiceKernel {
         Self {
             broker,
             js_dispatcher,
+            clock: Cell::new(None),
         }
     }
 
+    /// NTP-style clock synchronization: sample the server time in multiple round
+    /// trips and keep the sample with the shortest round trip, as it has the
+    /// smallest error (half of the round trip at most).
+    pub(crate) async fn sync_clock(&self) -> Result<(), JsValue> {
+        let mut best: Option<ClockSync> = None;
+
+        for _ in 0..CLOCK_SYNC_SAMPLES {
+            if let Some(sample) = self.sample_server_time().await {
+                if best.map(|best| sample.rtt < best.rtt).unwrap_or(true) {
+                    best = Some(sample);
+                }
+            }
+        }
+
+        let best = best.ok_or_else(|| JsValue::from_str("core_clock_sync_failed"))?;
+        trace!("core_clock_sync RTT: {} OFFSET: {}", best.rtt, best.offset);
+
+        self.clock.set(Some(best));
+        Ok(())
+    }
+
+    /// Current server time in milliseconds since the unix epoch,
+    /// the local time till the clock is synchronized.
+    pub(crate) fn server_now(&self) -> f64 {
+        let offset = self.clock.get().map(|sync| sync.offset).unwrap_or(0.0);
+        js_sys::Date::now() + offset
+    }
+
+    pub(crate) fn clock_sync(&self) -> Option<ClockSync> {
+        self.clock.get()
+    }
+
+    /// Single server-time round trip. The server time is assumed to be
+    /// read at the midpoint of the round trip.
+    async fn sample_server_time(&self) -> Option<ClockSync> {
+        let req = proto_core::create_core_server_time_request();
+
+        let sent = js_sys::Date::now();
+        let res = self
+            .broker
+            .send_proto_request_to_server(&RealtimeService::Core, req)
+            .await
+            .map_err(|e| error!("core_server_time_request_error: {}", e))
+            .ok()?;
+        let received = js_sys::Date::now();
+
+        match proto_core::decode_core_message_and_extract_payload(res) {
+            Ok(Payload::ServerTimeRes(res)) => {
+                let server_time = res.server_time?;
+                let server_ms =
+                    server_time.seconds as f64 * 1000.0 + server_time.nanos as f64 / 1_000_000.0;
+
+                Some(ClockSync {
+                    rtt: received - sent,
+                    offset: server_ms - (sent + received) / 2.0,
+                })
+            }
+            Ok(_) => {
+                error!("core_server_time_unexpected_response");
+                None
+            }
+            Err(e) => {
+                error!("core_server_time_decode_error: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Subscribe to (or unsubscribe from) the named topic of the cospace.
+    pub(crate) async fn update_subscription(
+        &self,
+        topic: &str,
+        subscribe: bool,
+    ) -> Result<(), JsValue> {
+        let req = if subscribe {
+            proto_core::create_core_subscribe_request(topic.to_string())
+        } else {
+            proto_core::create_core_unsubscribe_request(topic.to_string())
+        };
+
+        let res = self
+            .broker
+            .send_proto_request_to_server(&RealtimeService::Core, req)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("core_subscription_request_error: {}", e)))?;
+
+        match proto_core::decode_core_message_and_extract_payload(res) {
+            Ok(Payload::SubscriptionRes(res)) if res.status == SubscriptionStatus::Ok as i32 => {
+                trace!(
+                    "core_subscription TOPIC: {} SUBSCRIBED: {}",
+                    res.topic,
+                    res.subscribed
+                );
+                Ok(())
+            }
+            Ok(Payload::SubscriptionRes(_)) => {
+                Err(JsValue::from_str("core_subscription_invalid_topic"))
+            }
+            Ok(_) => Err(JsValue::from_str("core_subscription_unexpected_response")),
+            Err(e) => Err(JsValue::from_str(&e.to_string())),
+        }
+    }
+
+    /// Publish to the subscribers of the named topic of the cospace.
+    pub(crate) async fn publish_to_topic(&self, topic: &str, payload: &[u8]) {
+        let msg = proto_core::create_core_topic_message(topic.to_string(), payload.to_vec());
+
+        self.broker
+            .send_proto_message_to_server(&RealtimeService::Core, msg)
+            .await
+    }
+
     /// Send a text message to the server.
     pub async fn send_text_message_to_server(&self, msg: &str) {
         self.broker.send_text_message_to_server(msg).await
@@ -63,8 +227,19 @@ impl CoreServiceKernel {
             .err();
     }
 
-    pub(crate) async fn recv_proto_message_from_server(&self, _service_payload: Vec<u8>) {
+    pub(crate) async fn recv_proto_message_from_server(&self, service_payload: Vec<u8>) {
         trace!("CoreServiceKernel_recv_proto_message_from_server");
+
+        if let Ok(Payload::Relayed(msg)) =
+            proto_core::decode_core_message_and_extract_payload(service_payload)
+        {
+            let msg = TopicEventMessage::new(&msg.topic, msg.client_id, &msg.payload);
+            let env = EventEnvelope::new(msg.into());
+
+            self.js_dispatcher.recv_message(env).await
+                .map_err(|e| error!("js_dispatcher_recv_message_error {:#?}", e))
+                .err();
+        }
     }
 
     pub(crate) async fn answer_proto_req_from_server(
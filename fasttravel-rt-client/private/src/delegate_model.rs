@@ -0,0 +1,304 @@
+use log::{error, trace};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use fasttravel_rt_crdt::{CrdtError, Object, Replica};
+use fasttravel_rt_proto::{
+    helpers::model as proto_model,
+    realtime::model::{message::Payload, value::Kind, CrdtObject, CrdtOp, Mode, Value},
+    RealtimeService,
+};
+
+use crate::{
+    message_broker::RealtimeMessageBroker, realtime_module::ServiceDelegatePrivate, EventEnvelope,
+    MessageDispatcher, ModelEventMessage,
+};
+
+/// Wrapper around the ModelServiceKernel. We send this wrapper to JS.
+#[wasm_bindgen]
+pub struct ModelServiceDelegate {
+    private: Rc<ModelServiceKernel>,
+}
+
+/// All communications from JS are through the delegate.
+#[wasm_bindgen]
+impl ModelServiceDelegate {
+    /// Open the model of the cospace in the CRDT mode and load the local replica.
+    pub async fn open_crdt(&self) -> Result<(), JsValue> {
+        self.private.open_crdt().await
+    }
+
+    /// Set the value of the register.
+    pub async fn register_set(&self, target: &str, value: JsValue) -> Result<(), JsValue> {
+        let value = value_from_js(&value)?;
+        self.private
+            .apply_local(|replica| replica.register_set(target, value))
+            .await
+    }
+
+    /// Put the value of the key of the map.
+    pub async fn map_put(&self, target: &str, key: &str, value: JsValue) -> Result<(), JsValue> {
+        let value = value_from_js(&value)?;
+        self.private
+            .apply_local(|replica| replica.map_put(target, key, value))
+            .await
+    }
+
+    /// Remove the key of the map.
+    pub async fn map_remove(&self, target: &str, key: &str) -> Result<(), JsValue> {
+        self.private
+            .apply_local(|replica| replica.map_remove(target, key))
+            .await
+    }
+
+    /// Add the element to the set.
+    pub async fn set_add(&self, target: &str, element: &str) -> Result<(), JsValue> {
+        self.private
+            .apply_local(|replica| replica.set_add(target, element))
+            .await
+    }
+
+    /// Remove the element from the set.
+    pub async fn set_remove(&self, target: &str, element: &str) -> Result<(), JsValue> {
+        self.private
+            .apply_local(|replica| replica.set_remove(target, element))
+            .await
+    }
+
+    /// Insert the value at the index of the sequence.
+    pub async fn sequence_insert(
+        &self,
+        target: &str,
+        index: usize,
+        value: JsValue,
+    ) -> Result<(), JsValue> {
+        let value = value_from_js(&value)?;
+        self.private
+            .apply_local(|replica| replica.sequence_insert(target, index, value))
+            .await
+    }
+
+    /// Remove the value at the index of the sequence.
+    pub async fn sequence_remove(&self, target: &str, index: usize) -> Result<(), JsValue> {
+        self.private
+            .apply_local(|replica| replica.sequence_remove(target, index))
+            .await
+    }
+
+    /// Read the current value of the named object from the local replica.
+    pub fn value(&self, target: &str) -> JsValue {
+        self.private.value(target)
+    }
+}
+
+impl ServiceDelegatePrivate<ModelServiceKernel> for ModelServiceDelegate {
+    fn new(private: Rc<ModelServiceKernel>) -> Self {
+        Self { private }
+    }
+}
+
+///
+/// Kernel responsible for the functionalities of the model-service on the client side.
+/// In the CRDT mode the kernel holds the local replica of the model: local changes are
+/// applied to the replica and sent to the server, the operations relayed by the server
+/// are merged into the replica and the changed objects are published to JS.
+///
+pub(crate) struct ModelServiceKernel {
+    broker: Rc<RealtimeMessageBroker>,
+    js_dispatcher: MessageDispatcher,
+    replica: RefCell<Option<Replica>>,
+}
+
+impl ModelServiceKernel {
+    pub(crate) fn new(broker: Rc<RealtimeMessageBroker>, js_dispatcher: MessageDispatcher) -> Self {
+        Self {
+            broker,
+            js_dispatcher,
+            replica: RefCell::new(None),
+        }
+    }
+
+    /// Request the CRDT snapshot of the model and load the local replica.
+    pub(crate) async fn open_crdt(&self) -> Result<(), JsValue> {
+        let req = proto_model::create_model_snapshot_request(Mode::Crdt);
+
+        let res = self
+            .broker
+            .send_proto_request_to_server(&RealtimeService::Model, req)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("model_snapshot_request_failed: {}", e)))?;
+
+        match proto_model::decode_model_message_and_extract_payload(&res) {
+            Ok(Payload::CrdtSnapshot(snapshot)) => {
+                self.load_replica(snapshot.replica, &snapshot.objects).await;
+                Ok(())
+            }
+            Ok(_) => Err(JsValue::from_str("model_mode_not_crdt")),
+            Err(e) => Err(JsValue::from_str(&e.to_string())),
+        }
+    }
+
+    /// Apply a local change to the replica and send the operation to the server.
+    pub(crate) async fn apply_local(
+        &self,
+        change: impl FnOnce(&mut Replica) -> Result<CrdtOp, CrdtError>,
+    ) -> Result<(), JsValue> {
+        let op = {
+            let mut replica = self.replica.borrow_mut();
+            let replica = replica
+                .as_mut()
+                .ok_or_else(|| JsValue::from_str("model_replica_not_opened"))?;
+
+            change(replica).map_err(|e| JsValue::from_str(&e.to_string()))?
+        };
+
+        let update = proto_model::create_model_crdt_update(vec![op]);
+        self.broker
+            .send_proto_message_to_server(&RealtimeService::Model, update)
+            .await;
+
+        Ok(())
+    }
+
+    pub(crate) fn value(&self, target: &str) -> JsValue {
+        self.replica
+            .borrow()
+            .as_ref()
+            .and_then(|replica| replica.object(target))
+            .map(object_to_js)
+            .unwrap_or(JsValue::UNDEFINED)
+    }
+
+    pub(crate) async fn recv_proto_message_from_server(&self, bytes: Vec<u8>) {
+        trace!("ModelServiceKernel_recv_proto_message_from_server");
+
+        match proto_model::decode_model_message_and_extract_payload(&bytes) {
+            Ok(Payload::CrdtSnapshot(snapshot)) => {
+                self.load_replica(snapshot.replica, &snapshot.objects).await;
+            }
+            Ok(Payload::CrdtRelay(update)) => {
+                let targets = {
+                    let mut replica = self.replica.borrow_mut();
+                    let replica = match replica.as_mut() {
+                        Some(replica) => replica,
+                        None => return,
+                    };
+
+                    // own operations are already applied to the local replica.
+                    if update.client_id == replica.replica() {
+                        return;
+                    }
+
+                    update
+                        .ops
+                        .iter()
+                        .filter(|op| {
+                            replica
+                                .apply(op)
+                                .map_err(|e| error!("model_crdt_relay_apply_error {}", e))
+                                .is_ok()
+                        })
+                        .map(|op| op.target.clone())
+                        .collect::<Vec<_>>()
+                };
+
+                self.publish_changes(targets).await;
+            }
+            Ok(_) => {}
+            Err(e) => error!("model_message_decode_error {}", e),
+        }
+    }
+
+    pub(crate) async fn answer_proto_req_from_server(
+        &self,
+        _service_payload: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        trace!("ModelServiceKernel_answer_proto_req_from_server");
+        None
+    }
+
+    async fn load_replica(&self, replica_id: u32, objects: &[CrdtObject]) {
+        match Replica::load(replica_id, objects) {
+            Ok(replica) => {
+                let targets = replica.objects().map(|(name, _)| name.clone()).collect();
+                self.replica.replace(Some(replica));
+                self.publish_changes(targets).await;
+            }
+            Err(e) => error!("model_crdt_snapshot_load_error {}", e),
+        }
+    }
+
+    /// Publish the names of the changed objects to JS.
+    async fn publish_changes(&self, targets: Vec<String>) {
+        if targets.is_empty() {
+            return;
+        }
+
+        let targets: js_sys::Array = targets.iter().map(|t| JsValue::from_str(t)).collect();
+        let msg = ModelEventMessage::new(targets);
+        let env = EventEnvelope::new(msg.into());
+
+        self.js_dispatcher
+            .recv_message(env)
+            .await
+            .map_err(|e| error!("js_dispatcher_recv_message_error {:#?}", e))
+            .err();
+    }
+}
+
+fn value_from_js(value: &JsValue) -> Result<Value, JsValue> {
+    let kind = if let Some(b) = value.as_bool() {
+        Kind::BoolValue(b)
+    } else if let Some(n) = value.as_f64() {
+        Kind::DoubleValue(n)
+    } else if let Some(s) = value.as_string() {
+        Kind::StringValue(s)
+    } else if let Some(bytes) = value.dyn_ref::<js_sys::Uint8Array>() {
+        Kind::BytesValue(bytes.to_vec())
+    } else {
+        return Err(JsValue::from_str("model_value_type_not_supported"));
+    };
+
+    Ok(Value { kind: Some(kind) })
+}
+
+fn value_to_js(value: &Value) -> JsValue {
+    match &value.kind {
+        Some(Kind::BoolValue(b)) => JsValue::from_bool(*b),
+        Some(Kind::IntValue(i)) => JsValue::from_f64(*i as f64),
+        Some(Kind::DoubleValue(n)) => JsValue::from_f64(*n),
+        Some(Kind::StringValue(s)) => JsValue::from_str(s),
+        Some(Kind::BytesValue(bytes)) => js_sys::Uint8Array::from(&bytes[..]).into(),
+        None => JsValue::UNDEFINED,
+    }
+}
+
+fn object_to_js(object: &Object) -> JsValue {
+    match object {
+        Object::Register(register) => register
+            .value()
+            .map(value_to_js)
+            .unwrap_or(JsValue::UNDEFINED),
+        Object::Map(map) => {
+            let obj = js_sys::Object::new();
+            for (key, value) in map.iter() {
+                js_sys::Reflect::set(&obj, &JsValue::from_str(key), &value_to_js(value))
+                    .map_err(|e| error!("model_map_to_js_error {:#?}", e))
+                    .ok();
+            }
+            obj.into()
+        }
+        Object::Set(set) => set
+            .iter()
+            .map(|element| JsValue::from_str(element))
+            .collect::<js_sys::Array>()
+            .into(),
+        Object::Sequence(sequence) => sequence
+            .iter()
+            .map(value_to_js)
+            .collect::<js_sys::Array>()
+            .into(),
+    }
+}
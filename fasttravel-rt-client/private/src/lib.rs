@@ -2,12 +2,14 @@ use wasm_bindgen::prelude::*;
 
 mod delegate_connection;
 mod delegate_core;
+mod delegate_model;
 mod message_broker;
 mod realtime_module;
 mod websocket_connection;
 
 pub use delegate_connection::ConnectionServiceDelegate;
 pub use delegate_core::CoreServiceDelegate;
+pub use delegate_model::ModelServiceDelegate;
 pub use realtime_module::{RealtimeModule, RealtimeModuleConfig, SessionModelRoot};
 
 // Import development and debug helpers.
@@ -34,12 +36,24 @@ extern "C" {
     #[wasm_bindgen(extends = EventMessage)]
     pub type CoreEventMessage;
 
+    #[wasm_bindgen(extends = EventMessage)]
+    pub type TopicEventMessage;
+
+    #[wasm_bindgen(extends = EventMessage)]
+    pub type ModelEventMessage;
+
     #[wasm_bindgen(constructor)]
     pub(crate) fn new(payload: EventMessage) -> EventEnvelope;
 
     #[wasm_bindgen(constructor)]
     pub(crate) fn new(text: &str) -> CoreEventMessage;
 
+    #[wasm_bindgen(constructor)]
+    pub(crate) fn new(topic: &str, client_id: u32, payload: &[u8]) -> TopicEventMessage;
+
+    #[wasm_bindgen(constructor)]
+    pub(crate) fn new(targets: js_sys::Array) -> ModelEventMessage;
+
     pub type MessageDispatcher;
     #[wasm_bindgen(method, catch)]
     pub(crate) async fn recv_message(this: &MessageDispatcher, env: EventEnvelope) -> Result<(), JsValue>;
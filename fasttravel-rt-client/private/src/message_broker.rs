@@ -1,31 +1,82 @@
-use futures::channel::oneshot;
+use futures::{channel::oneshot, future};
 use log::{error, trace};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
-use wasm_bindgen_futures::spawn_local;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
 
 use fasttravel_rt_proto::{
     helpers::{
-        self as proto_helpers, ProtoMessage, ProtoPayloadRequest, ProtoPayloadResponse,
-        ProtoPayloadTell,
+        self as proto_helpers, ProtoMessage, ProtoPayloadError, ProtoPayloadRequest,
+        ProtoPayloadResponse, ProtoPayloadTell,
     },
+    realtime::StatusCode,
     RealtimeService,
 };
 
 use crate::delegate_connection::ConnectionServiceKernel;
 use crate::delegate_core::CoreServiceKernel;
+use crate::delegate_model::ModelServiceKernel;
 use crate::websocket_connection::{SocketMessage, WebSocketConnection};
 
 /// Response promise which sends the response on complete.
 struct ResponsePromise {
-    tx: oneshot::Sender<Vec<u8>>,
+    tx: oneshot::Sender<Result<Vec<u8>, RequestError>>,
 }
 
 impl ResponsePromise {
     fn complete(self, encoded_bytes: Vec<u8>) {
-        let _ = self.tx.send(encoded_bytes);
+        let _ = self.tx.send(Ok(encoded_bytes));
     }
+
+    fn reject(self, error: RequestError) {
+        let _ = self.tx.send(Err(error));
+    }
+}
+
+/// Failure of a request sent to the server.
+#[derive(Debug)]
+pub(crate) enum RequestError {
+    /// the request could not be created or sent.
+    Failed,
+    /// no response within the request timeout.
+    Timeout,
+    /// the connection closed before the response.
+    Disconnected,
+    /// the server responded with an error.
+    Rejected(StatusCode, String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Failed => write!(f, "request_failed"),
+            RequestError::Timeout => write!(f, "request_timeout"),
+            RequestError::Disconnected => write!(f, "request_disconnected"),
+            RequestError::Rejected(code, message) => {
+                write!(f, "request_rejected: {:?} {}", code, message)
+            }
+        }
+    }
+}
+
+/// Resolve after the duration in milliseconds.
+async fn delay(millis: u32) {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        web_sys::window()
+            .and_then(|window| {
+                window
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis as i32)
+                    .ok()
+            })
+            .or_else(|| {
+                error!("delay_set_timeout_error");
+                None
+            });
+    });
+
+    let _ = JsFuture::from(promise).await;
 }
 
 /// The realtime message broker that brokers messages between the
@@ -36,21 +87,25 @@ pub(crate) struct RealtimeMessageBroker {
     connection: RefCell<Option<Rc<WebSocketConnection>>>,
     kernel_core: RefCell<Option<Rc<CoreServiceKernel>>>,
     kernel_connection: RefCell<Option<Rc<ConnectionServiceKernel>>>,
+    kernel_model: RefCell<Option<Rc<ModelServiceKernel>>>,
     service_promises: RefCell<HashMap<u32, ResponsePromise>>,
     request_id_counter: RefCell<u32>,
+    request_timeout_ms: u32,
 }
 
 // The realtime message broker is referenced through multiple Rc(s) by
 // the realtime module and the service delegates. Instead of a single
 // RefCell<Inner>, we use granular internal mutability.
 impl RealtimeMessageBroker {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(request_timeout_ms: u32) -> Self {
         Self {
             connection: RefCell::new(None),
             kernel_core: RefCell::new(None),
             kernel_connection: RefCell::new(None),
+            kernel_model: RefCell::new(None),
             service_promises: RefCell::new(HashMap::new()),
             request_id_counter: RefCell::new(0),
+            request_timeout_ms,
         }
     }
 
@@ -66,6 +121,10 @@ impl RealtimeMessageBroker {
         self.kernel_connection.replace(Some(kernel_connection));
     }
 
+    pub(crate) fn set_kernel_model(&self, kernel_model: Rc<ModelServiceKernel>) {
+        self.kernel_model.replace(Some(kernel_model));
+    }
+
     // internal method, panic on misuse (unwrap) is intentional.
     fn get_connection(&self) -> Rc<WebSocketConnection> {
         self.connection.borrow().as_ref().unwrap().clone()
@@ -81,6 +140,11 @@ impl RealtimeMessageBroker {
         self.kernel_connection.borrow().as_ref().unwrap().clone()
     }
 
+    // internal method, panic on misuse (unwrap) is intentional.
+    fn get_kernel_model(&self) -> Rc<ModelServiceKernel> {
+        self.kernel_model.borrow().as_ref().unwrap().clone()
+    }
+
     pub(crate) fn try_get_kernel_connection(&self) -> Option<Rc<ConnectionServiceKernel>> {
         if let Ok(cnx) = self.kernel_connection.try_borrow() {
             return cnx.as_ref().map(|kernel| kernel.clone());
@@ -101,7 +165,7 @@ impl RealtimeMessageBroker {
             .ok();
     }
 
-    pub(crate) async fn _send_proto_message_to_server(
+    pub(crate) async fn send_proto_message_to_server(
         &self,
         service: &RealtimeService,
         payload: Vec<u8>,
@@ -111,68 +175,95 @@ impl RealtimeMessageBroker {
         // REF: https://developer.mozilla.org/en-US/docs/Web/API/WebSocket/send
 
         proto_helpers::create_tell_message_from_service_payload(service, payload)
-            .and_then(|rt_msg| {
+            .map(|rt_msg| {
                 self.get_connection()
                     .socket
                     .send_with_u8_array(&rt_msg[..])
                     .map_err(|e| error!("send_proto_message_to_server_error {:#?}", e))
                     .ok();
-
-                Some(rt_msg)
             })
-            .or_else(|| {
-                error!("create_tell_message_from_service_payload_error");
-                None
-            });
+            .map_err(|e| error!("create_tell_message_from_service_payload_error: {}", e))
+            .ok();
     }
 
     pub(crate) async fn send_proto_request_to_server(
         &self,
         service: &RealtimeService,
         payload: Vec<u8>,
-    ) -> Option<Vec<u8>> {
-        // increment the request counter.
-        let mut req_id_counter = self.request_id_counter.borrow_mut();
-        let next_id = *req_id_counter + 1;
-        *req_id_counter = next_id;
+    ) -> Result<Vec<u8>, RequestError> {
+        // increment the request counter, the borrow must not be held across await.
+        let next_id = {
+            let mut req_id_counter = self.request_id_counter.borrow_mut();
+            *req_id_counter += 1;
+            *req_id_counter
+        };
 
         trace!("send_proto_request_to_server_req_id: {}", next_id);
 
-        if let Some(rt_msg) =
+        let rt_msg =
             proto_helpers::create_request_message_from_service_payload(next_id, service, payload)
-        {
-            // send the request message
-            if let Err(e) = self.get_connection().socket.send_with_u8_array(&rt_msg) {
-                error!("socket.send_with_u8_array_error {:#?}", e);
-            } else {
-                // create the response promise.
-                let (tx, rx) = oneshot::channel::<Vec<u8>>();
-                let promise_fut = async move {
-                    if let Ok(encoded_bytes) = rx.await {
-                        return Some(encoded_bytes);
-                    }
-                    None
-                };
-
-                // [todo] schedule a task that will invalidate the promise after a duration.
-                let promise = ResponsePromise { tx };
-                self.service_promises.borrow_mut().insert(next_id, promise);
+                .map_err(|e| {
+                error!("create_request_message_from_service_payload_error: {}", e);
+                RequestError::Failed
+            })?;
+
+        // send the request message
+        if let Err(e) = self.get_connection().socket.send_with_u8_array(&rt_msg) {
+            error!("socket.send_with_u8_array_error {:#?}", e);
+            return Err(RequestError::Failed);
+        }
 
-                return promise_fut.await;
+        // create the response promise, the promise is dropped (rx canceled)
+        // if the connection closes.
+        let (tx, rx) = oneshot::channel::<Result<Vec<u8>, RequestError>>();
+        let promise = ResponsePromise { tx };
+        self.service_promises.borrow_mut().insert(next_id, promise);
+
+        // wait for the response or the request timeout.
+        let timeout = delay(self.request_timeout_ms);
+        match future::select(rx, Box::pin(timeout)).await {
+            future::Either::Left((Ok(response), _)) => response,
+            future::Either::Left((Err(_canceled), _)) => Err(RequestError::Disconnected),
+            future::Either::Right(_) => {
+                error!("send_proto_request_to_server_timeout_req_id: {}", next_id);
+                self.service_promises.borrow_mut().remove(&next_id);
+                Err(RequestError::Timeout)
             }
         }
+    }
 
-        None
+    /// Drop all the pending request promises once the connection is closed,
+    /// the waiting requests fail with RequestError::Disconnected.
+    pub(crate) fn recv_connection_closed(&self) {
+        trace!("recv_connection_closed");
+
+        self.service_promises.borrow_mut().clear();
     }
 
     #[inline(always)]
     pub(crate) fn recv_msg_from_server(&self, msg: SocketMessage) {
         match msg {
             SocketMessage::Text(text) => self.recv_text_msg_from_server(text),
+            SocketMessage::Binary(bytes) if proto_helpers::is_realtime_batch(&bytes) => {
+                self.recv_proto_batch_from_server(bytes)
+            }
             SocketMessage::Binary(bytes) => self.recv_proto_msg_from_server(bytes),
         }
     }
 
+    #[inline(always)]
+    fn recv_proto_batch_from_server(&self, batch: Vec<u8>) {
+        trace!("recv_proto_batch_from_server_batch_len: {}", batch.len());
+
+        // the messages of the batch are processed in order, as if received one by one.
+        match proto_helpers::split_realtime_batch(&batch) {
+            Ok(messages) => messages
+                .into_iter()
+                .for_each(|bytes| self.recv_proto_msg_from_server(bytes)),
+            Err(e) => error!("recv_proto_batch_from_server_error: {}", e),
+        }
+    }
+
     #[inline(always)]
     fn recv_text_msg_from_server(&self, msg: String) {
         trace!("recv_text_msg_from_server");
@@ -193,10 +284,11 @@ impl RealtimeMessageBroker {
         let proto_msg = proto_helpers::process_realtime_message(bytes);
 
         match proto_msg {
-            ProtoMessage::Tell(payload) => self.recv_proto_tell_from_server(payload),
-            ProtoMessage::Request(payload) => self.recv_proto_req_from_server(payload),
-            ProtoMessage::Response(payload) => self.recv_proto_res_from_server(payload),
-            _ => {}
+            Ok(ProtoMessage::Tell(payload)) => self.recv_proto_tell_from_server(payload),
+            Ok(ProtoMessage::Request(payload)) => self.recv_proto_req_from_server(payload),
+            Ok(ProtoMessage::Response(payload)) => self.recv_proto_res_from_server(payload),
+            Ok(ProtoMessage::Error(payload)) => self.recv_proto_error_from_server(payload),
+            Err(e) => error!("recv_proto_msg_from_server_error: {}", e),
         }
     }
 
@@ -207,9 +299,11 @@ impl RealtimeMessageBroker {
         let connection = self.get_connection();
         let kernel_core = self.get_kernel_core();
         let kernel_connection = self.get_kernel_connection();
+        let kernel_model = self.get_kernel_model();
 
         let task = async move {
             let mut response = None;
+            let mut code = StatusCode::Failed;
             match payload.service {
                 RealtimeService::Connection => {
                     response = kernel_connection
@@ -221,27 +315,44 @@ impl RealtimeMessageBroker {
                         .answer_proto_req_from_server(payload.bytes)
                         .await;
                 }
+                RealtimeService::Model => {
+                    response = kernel_model
+                        .answer_proto_req_from_server(payload.bytes)
+                        .await;
+                }
                 _ => {
-                    error!("recv_proto_req_from_server_error_service_not_handled")
+                    error!("recv_proto_req_from_server_error_service_not_handled");
+                    code = StatusCode::Unavailable;
                 }
             }
 
-            response.map(|bytes| {
-                proto_helpers::create_response_message_from_service_payload(
-                    payload.request_id,
-                    &payload.service,
-                    bytes,
-                )
-                .map(|rt_msg| {
-                    connection
-                        .socket
-                        .send_with_u8_array(&rt_msg[..])
-                        .map_err(|e| {
-                            error!("recv_proto_req_from_server_error_on_res_send {:#?}", e)
-                        })
-                        .ok();
+            // respond with the service response, or reject the request.
+            let rt_msg = response
+                .and_then(|bytes| {
+                    proto_helpers::create_response_message_from_service_payload(
+                        payload.request_id,
+                        &payload.service,
+                        bytes,
+                    )
+                    .map_err(|e| {
+                        error!("create_response_message_from_service_payload_error: {}", e)
+                    })
+                    .ok()
+                })
+                .unwrap_or_else(|| {
+                    proto_helpers::create_error_response_message(
+                        payload.request_id,
+                        &payload.service,
+                        code,
+                        "client_request_failed",
+                    )
                 });
-            });
+
+            connection
+                .socket
+                .send_with_u8_array(&rt_msg[..])
+                .map_err(|e| error!("recv_proto_req_from_server_error_on_res_send {:#?}", e))
+                .ok();
         };
 
         spawn_local(task); // future will run on the next microtask tick.
@@ -253,6 +364,7 @@ impl RealtimeMessageBroker {
 
         let kernel_core = self.get_kernel_core();
         let kernel_connection = self.get_kernel_connection();
+        let kernel_model = self.get_kernel_model();
 
         match &payload.service {
             RealtimeService::Connection => {
@@ -273,6 +385,15 @@ impl RealtimeMessageBroker {
 
                 spawn_local(task); // future will run on the next microtask tick.
             }
+            RealtimeService::Model => {
+                let task = async move {
+                    kernel_model
+                        .recv_proto_message_from_server(payload.bytes)
+                        .await;
+                };
+
+                spawn_local(task); // future will run on the next microtask tick.
+            }
             _ => {
                 error!("recv_proto_tell_from_server_error_service_not_handled")
             }
@@ -286,6 +407,19 @@ impl RealtimeMessageBroker {
         let mut map = self.service_promises.borrow_mut();
         if let Some(promise) = map.remove(&payload.response_id) {
             promise.complete(payload.bytes);
+        } else {
+            // the request timed out or the id is unknown.
+            error!("req_promise_already_dropped_req_id {}", payload.response_id);
+        }
+    }
+
+    #[inline(always)]
+    fn recv_proto_error_from_server(&self, payload: ProtoPayloadError) {
+        trace!("recv_proto_error_from_server");
+
+        let mut map = self.service_promises.borrow_mut();
+        if let Some(promise) = map.remove(&payload.response_id) {
+            promise.reject(RequestError::Rejected(payload.code, payload.message));
         } else {
             error!("req_promise_already_dropped_req_id {}", payload.response_id);
         }
@@ -11,6 +11,7 @@ use crate::{
         ConnectionServiceDelegate, ConnectionServiceKernel,
     },
     delegate_core::{CoreServiceDelegate, CoreServiceKernel},
+    delegate_model::{ModelServiceDelegate, ModelServiceKernel},
     message_broker::RealtimeMessageBroker,
     websocket_connection::WebSocketConnection,
     MessageDispatcher,
@@ -33,10 +34,12 @@ pub struct RealtimeModule {
 #[wasm_bindgen]
 impl RealtimeModule {
     pub fn new(config: RealtimeModuleConfig) -> RealtimeModule {
+        let broker = Rc::new(RealtimeMessageBroker::new(config.request_timeout_ms));
+
         Self {
             status: 0,
             config,
-            broker: Rc::new(RealtimeMessageBroker::new()),
+            broker,
         }
     }
 
@@ -71,6 +74,16 @@ impl RealtimeModule {
         ConnectionServiceDelegate::new(kernel)
     }
 
+    /// Create the model service delegate/kernel.
+    pub fn init_model(&self, dispatcher: MessageDispatcher) -> ModelServiceDelegate {
+        trace!("RealtimeModule_init_model");
+
+        let kernel = Rc::new(ModelServiceKernel::new(self.broker.clone(), dispatcher));
+        self.broker.set_kernel_model(kernel.clone());
+
+        ModelServiceDelegate::new(kernel)
+    }
+
     /// Request to join a session of a collaboration space.
     /// 1. Use accessToken and sessionUrl to send a host-cospace request.
     /// 2. Use statusTicket and statusUrl to check cospace hosting status.
@@ -96,6 +109,11 @@ impl RealtimeModule {
                     .await;
 
                 info!("perform_websocket_ticket_handshake_status: {}", success);
+
+                // the server closes the connection if the handshake fails.
+                if !success {
+                    return Err(JsValue::from_str("websocket_ticket_handshake_failed"));
+                }
             }
         }
 
@@ -182,6 +200,9 @@ struct SessionJoinResponseBody {
     token_type: String,
 }
 
+/// Default duration to wait for the response of a request.
+const DEFAULT_REQUEST_TIMEOUT_MS: u32 = 10_000;
+
 /// Configuration settings for the realtime module.
 #[allow(dead_code)]
 #[wasm_bindgen]
@@ -190,6 +211,7 @@ pub struct RealtimeModuleConfig {
     rt_session_url: String,
     rt_status_url: String,
     rt_connect_url: String,
+    request_timeout_ms: u32,
 }
 
 #[wasm_bindgen]
@@ -205,8 +227,14 @@ impl RealtimeModuleConfig {
             rt_session_url,
             rt_status_url,
             rt_connect_url,
+            request_timeout_ms: DEFAULT_REQUEST_TIMEOUT_MS,
         }
     }
+
+    /// Set the duration to wait for the response of a request sent to the server.
+    pub fn set_request_timeout_ms(&mut self, request_timeout_ms: u32) {
+        self.request_timeout_ms = request_timeout_ms;
+    }
 }
 
 /// The modelroot of the realtime session.
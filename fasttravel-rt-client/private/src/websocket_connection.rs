@@ -4,10 +4,9 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{ErrorEvent, MessageEvent, WebSocket};
 
-use crate::message_broker::RealtimeMessageBroker;
+use fasttravel_rt_proto::helpers::connection::WS_PROTOCOLS;
 
-// [todo] get this from config.
-const WS_PROTOCOL: &str = "realtime-proto-v01";
+use crate::message_broker::RealtimeMessageBroker;
 
 pub(crate) enum SocketMessage {
     Binary(Vec<u8>),
@@ -25,19 +24,33 @@ pub(crate) struct WebSocketConnection {
     on_message: Closure<dyn FnMut(MessageEvent)>,
     on_open: Closure<dyn FnMut()>,
     on_error: Closure<dyn FnMut(ErrorEvent)>,
+    on_close: Closure<dyn FnMut()>,
 }
 
 impl WebSocketConnection {
     pub(crate) async fn new(url: &str, broker: Rc<RealtimeMessageBroker>) -> Result<Self, JsValue> {
         trace!("WebSocketConnection_new_url: {}", url);
 
-        let socket = WebSocket::new_with_str(url, WS_PROTOCOL).map_err(|err| {
+        // offer all the supported subprotocols, the server selects one of them.
+        let protocols = WS_PROTOCOLS
+            .iter()
+            .map(|p| JsValue::from_str(p))
+            .collect::<js_sys::Array>();
+
+        let socket = WebSocket::new_with_str_sequence(url, &protocols).map_err(|err| {
             error!("websocket_creation_error: {:?}", err);
             err
         })?;
 
         socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
+        // onclose handler
+        let broker_moved = broker.clone();
+        let on_close = Closure::<dyn FnMut()>::new(move || {
+            info!("websocket_connection_closed");
+            broker_moved.recv_connection_closed();
+        });
+
         // onmessage handler
         let on_message = Closure::<dyn FnMut(_)>::wrap(Box::new(move |e: MessageEvent| {
             e.data()
@@ -77,16 +90,19 @@ impl WebSocketConnection {
         socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
         socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
         socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
 
         // wait for the onopen event and then return the connection object
         rx.await
             .map(|status| {
                 info!("websocket_connection_open_status: {}", status);
+                info!("websocket_connection_protocol: {}", socket.protocol());
                 WebSocketConnection {
                     socket,
                     on_message,
                     on_open,
                     on_error,
+                    on_close,
                 }
             })
             .map_err(|e| {
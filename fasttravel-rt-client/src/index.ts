@@ -1,7 +1,8 @@
 import { FasttravelClient } from "./FasttravelClient"
 import { RealtimeService, ServiceUrls, SessionOptions } from "./lib/RealtimeService"
 import { CoreService } from "./lib/CoreService"
-import { CoreEventMessage } from "./lib/Events"
+import { ModelService, ModelValue } from "./lib/ModelService"
+import { CoreEventMessage, ModelEventMessage, TopicEventMessage } from "./lib/Events"
 
 
 const createClient = (
@@ -13,7 +14,9 @@ const createClient = (
 }
 
 export type {
-    CoreService
+    CoreService,
+    ModelService,
+    ModelValue
 }
 
 export {
@@ -22,5 +25,7 @@ export {
     RealtimeService,
     ServiceUrls,
     SessionOptions,
-    CoreEventMessage
+    CoreEventMessage,
+    ModelEventMessage,
+    TopicEventMessage
 }
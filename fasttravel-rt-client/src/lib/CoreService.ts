@@ -1,17 +1,23 @@
 
 import { PubSub, Observable } from "./PubSub"
-import { MessageDispatcher, EventEnvelope, Publisher, CoreEventMessage } from "./Events"
+import { MessageDispatcher, EventEnvelope, Publisher, CoreEventMessage, TopicEventMessage } from "./Events"
 import { RealtimeModule, CoreServiceDelegate } from "../../pkg/fasttravel_rt_client_private"
 
 
 export type CoreService = {
     sendTextMessage(msg: string): void;
-    events(): Observable<CoreEventMessage>;
+    syncClock(): Promise<void>;
+    serverNow(): number;
+    roundTripTime(): number | undefined;
+    subscribeTopic(topic: string): Promise<void>;
+    unsubscribeTopic(topic: string): Promise<void>;
+    publishToTopic(topic: string, payload: Uint8Array): Promise<void>;
+    events(): Observable<CoreEventMessage | TopicEventMessage>;
 }
 
 export class CoreServiceImpl implements Publisher, CoreService {
 
-    protected pubsub: PubSub<CoreEventMessage>;
+    protected pubsub: PubSub<CoreEventMessage | TopicEventMessage>;
     protected delegate: CoreServiceDelegate;
 
     constructor(protected rtModule: RealtimeModule) {
@@ -26,7 +32,39 @@ export class CoreServiceImpl implements Publisher, CoreService {
         this.delegate.send_text_message(msg);
     }
 
-    public events(): Observable<CoreEventMessage> {
+    // Synchronize the local clock with the server clock, in multiple
+    // round trips to the server. Re-sync to account for clock drift.
+    public syncClock(): Promise<void> {
+        return this.delegate.sync_clock();
+    }
+
+    // Current server time in milliseconds since the unix epoch.
+    // Returns the local time till the clock is synchronized.
+    public serverNow(): number {
+        return this.delegate.server_now();
+    }
+
+    // Round trip time in milliseconds measured by the last clock sync.
+    public roundTripTime(): number | undefined {
+        return this.delegate.round_trip_time();
+    }
+
+    // Subscribe to the named topic of the session, the messages published
+    // to the topic are received as TopicEventMessage events.
+    public subscribeTopic(topic: string): Promise<void> {
+        return this.delegate.subscribe_topic(topic);
+    }
+
+    public unsubscribeTopic(topic: string): Promise<void> {
+        return this.delegate.unsubscribe_topic(topic);
+    }
+
+    // Publish to the clients subscribed to the named topic of the session.
+    public publishToTopic(topic: string, payload: Uint8Array): Promise<void> {
+        return this.delegate.publish_to_topic(topic, payload);
+    }
+
+    public events(): Observable<CoreEventMessage | TopicEventMessage> {
         return this.pubsub;
     }
 
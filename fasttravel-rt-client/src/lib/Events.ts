@@ -54,3 +54,22 @@ export class CoreEventMessage extends EventMessage {
     }
 }
 
+export class TopicEventMessage extends EventMessage {
+
+    static type: string = "TopicEventMessage";
+
+    // message published to a subscribed topic by the client with the id.
+    constructor(public topic: string, public clientId: number, public payload: Uint8Array) {
+        super(TopicEventMessage.type);
+    }
+}
+
+export class ModelEventMessage extends EventMessage {
+
+    static type: string = "ModelEventMessage";
+
+    // names of the model objects changed by the event.
+    constructor(public targets: Array<string>) {
+        super(ModelEventMessage.type);
+    }
+}
@@ -0,0 +1,80 @@
+
+import { PubSub, Observable } from "./PubSub"
+import { MessageDispatcher, EventEnvelope, Publisher, ModelEventMessage } from "./Events"
+import { RealtimeModule, ModelServiceDelegate } from "../../pkg/fasttravel_rt_client_private"
+
+
+export type ModelValue = boolean | number | string | Uint8Array;
+
+export type ModelService = {
+    openCrdt(): Promise<void>;
+    registerSet(target: string, value: ModelValue): Promise<void>;
+    mapPut(target: string, key: string, value: ModelValue): Promise<void>;
+    mapRemove(target: string, key: string): Promise<void>;
+    setAdd(target: string, element: string): Promise<void>;
+    setRemove(target: string, element: string): Promise<void>;
+    sequenceInsert(target: string, index: number, value: ModelValue): Promise<void>;
+    sequenceRemove(target: string, index: number): Promise<void>;
+    value(target: string): any;
+    events(): Observable<ModelEventMessage>;
+}
+
+export class ModelServiceImpl implements Publisher, ModelService {
+
+    protected pubsub: PubSub<ModelEventMessage>;
+    protected delegate: ModelServiceDelegate;
+
+    constructor(protected rtModule: RealtimeModule) {
+        const dispatcher = new MessageDispatcher(this);
+        this.delegate = rtModule.init_model(dispatcher);
+        this.pubsub = new PubSub();
+    }
+
+    // Open the model of the session in the CRDT mode, the local
+    // replica is loaded from the snapshot of the model.
+    public openCrdt(): Promise<void> {
+        return this.delegate.open_crdt();
+    }
+
+    public registerSet(target: string, value: ModelValue): Promise<void> {
+        return this.delegate.register_set(target, value);
+    }
+
+    public mapPut(target: string, key: string, value: ModelValue): Promise<void> {
+        return this.delegate.map_put(target, key, value);
+    }
+
+    public mapRemove(target: string, key: string): Promise<void> {
+        return this.delegate.map_remove(target, key);
+    }
+
+    public setAdd(target: string, element: string): Promise<void> {
+        return this.delegate.set_add(target, element);
+    }
+
+    public setRemove(target: string, element: string): Promise<void> {
+        return this.delegate.set_remove(target, element);
+    }
+
+    public sequenceInsert(target: string, index: number, value: ModelValue): Promise<void> {
+        return this.delegate.sequence_insert(target, index, value);
+    }
+
+    public sequenceRemove(target: string, index: number): Promise<void> {
+        return this.delegate.sequence_remove(target, index);
+    }
+
+    // Current value of the object in the local replica.
+    public value(target: string): any {
+        return this.delegate.value(target);
+    }
+
+    public events(): Observable<ModelEventMessage> {
+        return this.pubsub;
+    }
+
+    public publish(env: EventEnvelope): Promise<void> {
+        // Add message transformations as needed and publish to subscribers
+        return this.pubsub.publish(env);
+    }
+}
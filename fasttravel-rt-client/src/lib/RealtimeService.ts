@@ -1,5 +1,6 @@
 import { CoreService, CoreServiceImpl } from './CoreService'
 import { ConnectionService, ConnectionServiceImpl } from './ConnectionService'
+import { ModelService, ModelServiceImpl } from './ModelService'
 import { RealtimeModule, SessionModelRoot, RealtimeModuleConfig } from "../../pkg/fasttravel_rt_client_private"
 
 export class SessionOptions {
@@ -25,10 +26,13 @@ export class RealtimeService {
   private realtimeModule: RealtimeModule;
   private connection: ConnectionService;
   readonly core: CoreService;
+  readonly model: ModelService;
 
   constructor(
     private accessToken: string,
     private serviceUrls?: ServiceUrls,
+    // duration to wait for the response of a request to the server.
+    private requestTimeoutMs?: number,
   ) {
 
     // set the default hardcoded values.
@@ -36,9 +40,11 @@ export class RealtimeService {
 
     // start the WASM kernels.
     let config = RealtimeModuleConfig.new(this.accessToken, serviceUrls.rtSessionUrl, serviceUrls.rtStatusUrl, serviceUrls.rtConnectUrl);
+    if (this.requestTimeoutMs !== undefined) config.set_request_timeout_ms(this.requestTimeoutMs);
     this.realtimeModule = RealtimeModule.new(config);
     this.connection = new ConnectionServiceImpl(this.realtimeModule);
     this.core = new CoreServiceImpl(this.realtimeModule);
+    this.model = new ModelServiceImpl(this.realtimeModule);
   }
 
   public async join_session(option: SessionOptions) {
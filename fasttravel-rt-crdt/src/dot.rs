@@ -0,0 +1,69 @@
+use fasttravel_rt_proto::realtime::model as proto;
+
+use crate::CrdtError;
+
+/// Unique tag of an operation, also used as the lamport timestamp.
+///
+/// Dots are ordered by the counter first and the replica next, so the
+/// order is total and consistent with the causal order of the operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dot {
+    pub counter: u64,
+    pub replica: u32,
+}
+
+impl Dot {
+    pub fn new(replica: u32, counter: u64) -> Self {
+        Self { counter, replica }
+    }
+}
+
+impl From<Dot> for proto::Dot {
+    fn from(dot: Dot) -> Self {
+        proto::Dot {
+            replica: dot.replica,
+            counter: dot.counter,
+        }
+    }
+}
+
+impl From<&proto::Dot> for Dot {
+    fn from(dot: &proto::Dot) -> Self {
+        Dot::new(dot.replica, dot.counter)
+    }
+}
+
+/// Lamport clock of a replica.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    replica: u32,
+    counter: u64,
+}
+
+impl Clock {
+    pub fn new(replica: u32) -> Self {
+        Self {
+            replica,
+            counter: 0,
+        }
+    }
+
+    pub fn replica(&self) -> u32 {
+        self.replica
+    }
+
+    /// Create the dot of the next local operation, fails once an observed
+    /// dot has exhausted the counter.
+    pub fn tick(&mut self) -> Result<Dot, CrdtError> {
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(CrdtError::ClockOverflow)?;
+        Ok(Dot::new(self.replica, self.counter))
+    }
+
+    /// Observe the dot of an operation, local dots always follow observed dots.
+    pub fn observe(&mut self, dot: &Dot) {
+        self.counter = self.counter.max(dot.counter);
+    }
+}
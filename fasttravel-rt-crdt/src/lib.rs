@@ -0,0 +1,70 @@
+#![forbid(unsafe_code)]
+
+//!
+//! Package contains the conflict-free replicated data types used by the
+//! model service in the CRDT mode, by both the server and the client-sdk.
+//!
+//! The CRDTs are operation-based: every local change creates an operation
+//! tagged with a unique [`Dot`], the operations are relayed to the other
+//! replicas through the model service. The operations are not buffered
+//! until their dependencies arrive, an operation referencing an object or
+//! a sequence node the replica has not observed is rejected.
+//!
+//! WARNING: The current version of this repository is 0.0.1-dev0 and is
+//! undergoing development for the first release client 0.1.0-rc0, which
+//! means that both the public interfaces and internal module structures
+//! may change significantly.
+//!
+
+use std::fmt;
+
+mod dot;
+mod map;
+mod register;
+mod replica;
+mod sequence;
+mod set;
+
+pub use dot::{Clock, Dot};
+pub use map::OrMap;
+pub use register::LwwRegister;
+pub use replica::{Object, Replica};
+pub use sequence::Rga;
+pub use set::OrSet;
+
+/// Errors of applying CRDT operations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CrdtError {
+    /// Operation is missing required fields.
+    InvalidOp,
+    /// Operation targets an object of a different CRDT type.
+    TypeMismatch,
+    /// Operation references a dot that the replica has not observed.
+    MissingDot,
+    /// Key or element not present in the object.
+    NotFound,
+    /// Index out of the bounds of the sequence.
+    IndexOutOfBounds,
+    /// Operation is tagged with a dot of another replica than its sender.
+    ForeignDot,
+    /// Counter of the clock is exhausted, no more local operations.
+    ClockOverflow,
+}
+
+impl fmt::Display for CrdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            CrdtError::InvalidOp => "crdt_op_invalid",
+            CrdtError::TypeMismatch => "crdt_object_type_mismatch",
+            CrdtError::MissingDot => "crdt_dot_missing",
+            CrdtError::NotFound => "crdt_entry_not_found",
+            CrdtError::IndexOutOfBounds => "crdt_index_out_of_bounds",
+            CrdtError::ForeignDot => "crdt_dot_foreign",
+            CrdtError::ClockOverflow => "crdt_clock_overflow",
+        };
+
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for CrdtError {}
@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::Dot;
+
+/// Observed-remove map.
+///
+/// Every put is tagged with a dot and removes the dots it has observed for
+/// the key, so a remove never affects a concurrent put. Concurrent puts of
+/// the same key are all kept and the value of the greatest dot is read.
+/// The removed dots are kept, so a redelivered put never restores them.
+#[derive(Clone, Debug)]
+pub struct OrMap<K, V> {
+    entries: HashMap<K, BTreeMap<Dot, V>>,
+    removed: HashSet<Dot>,
+}
+
+impl<K, V> Default for OrMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            removed: HashSet::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> OrMap<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries
+            .get(key)
+            .and_then(|dots| dots.values().next_back())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate the keys with their current values.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, dots)| dots.values().next_back().map(|value| (key, value)))
+    }
+
+    /// Iterate all the tagged values, used to serialize the state.
+    pub fn entries(&self) -> impl Iterator<Item = (&K, &Dot, &V)> {
+        self.entries
+            .iter()
+            .flat_map(|(key, dots)| dots.iter().map(move |(dot, value)| (key, dot, value)))
+    }
+
+    /// Iterate the removed dots, used to serialize the state.
+    pub fn removed(&self) -> impl Iterator<Item = &Dot> {
+        self.removed.iter()
+    }
+
+    /// Dots of the key observed by the replica.
+    pub fn dots(&self, key: &K) -> Vec<Dot> {
+        self.entries
+            .get(key)
+            .map(|dots| dots.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Put the value tagged with the dot, replacing the removed dots of the key.
+    /// The put of an already removed dot only removes the dots it has observed.
+    pub fn put(&mut self, key: K, dot: Dot, value: V, removed: &[Dot]) {
+        self.remove(&key, removed);
+
+        if !self.removed.contains(&dot) {
+            self.entries.entry(key).or_default().insert(dot, value);
+        }
+    }
+
+    /// Remove the observed dots of the key, the key is removed with its last dot.
+    pub fn remove(&mut self, key: &K, removed: &[Dot]) {
+        self.observe_removed(removed);
+
+        if let Some(dots) = self.entries.get_mut(key) {
+            for removed_dot in removed {
+                dots.remove(removed_dot);
+            }
+
+            if dots.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    /// Record the removed dots without a key, used to load the state.
+    pub fn observe_removed(&mut self, removed: &[Dot]) {
+        self.removed.extend(removed.iter().copied());
+    }
+}
@@ -0,0 +1,40 @@
+use crate::Dot;
+
+/// Last-writer-wins register, the write with the greatest dot wins.
+#[derive(Clone, Debug)]
+pub struct LwwRegister<V> {
+    entry: Option<(Dot, V)>,
+}
+
+impl<V> Default for LwwRegister<V> {
+    fn default() -> Self {
+        Self { entry: None }
+    }
+}
+
+impl<V: Clone> LwwRegister<V> {
+    pub fn value(&self) -> Option<&V> {
+        self.entry.as_ref().map(|(_, value)| value)
+    }
+
+    pub fn stamp(&self) -> Option<Dot> {
+        self.entry.as_ref().map(|(stamp, _)| *stamp)
+    }
+
+    /// Write the value, returns false if the register holds a later write.
+    pub fn set(&mut self, stamp: Dot, value: V) -> bool {
+        if !matches!(self.stamp(), Some(current) if current >= stamp) {
+            self.entry = Some((stamp, value));
+            return true;
+        }
+
+        false
+    }
+
+    /// Merge the state of another replica of the register.
+    pub fn merge(&mut self, other: &Self) {
+        if let Some((stamp, value)) = &other.entry {
+            self.set(*stamp, value.clone());
+        }
+    }
+}
@@ -0,0 +1,672 @@
+use std::collections::HashMap;
+
+use fasttravel_rt_proto::realtime::model::{
+    self as proto, crdt_object::Kind, crdt_op::Op, CrdtOp, Value,
+};
+
+use crate::{Clock, CrdtError, Dot, LwwRegister, OrMap, OrSet, Rga};
+
+/// Named CRDT object of the replicated model.
+#[derive(Clone, Debug)]
+pub enum Object {
+    Register(LwwRegister<Value>),
+    Map(OrMap<String, Value>),
+    Set(OrSet<String>),
+    Sequence(Rga<Value>),
+}
+
+/// Replica of the model in the CRDT mode.
+///
+/// The replica holds the named CRDT objects of the model, applies the
+/// operations of the other replicas and creates the operations of the
+/// local changes. An object is created by the first operation adding to it,
+/// the remove operations on an unknown object are rejected.
+#[derive(Clone, Debug)]
+pub struct Replica {
+    clock: Clock,
+    objects: HashMap<String, Object>,
+}
+
+impl Replica {
+    pub fn new(replica: u32) -> Self {
+        Self {
+            clock: Clock::new(replica),
+            objects: HashMap::new(),
+        }
+    }
+
+    /// Load the replica from the snapshot of the model.
+    pub fn load(replica: u32, snapshot: &[proto::CrdtObject]) -> Result<Self, CrdtError> {
+        let mut this = Self::new(replica);
+
+        for object in snapshot {
+            let loaded = match object.kind.as_ref().ok_or(CrdtError::InvalidOp)? {
+                Kind::Register(state) => {
+                    let stamp = to_dot(&state.stamp)?;
+                    let mut register = LwwRegister::default();
+                    register.set(stamp, to_value(&state.value)?);
+                    this.clock.observe(&stamp);
+                    Object::Register(register)
+                }
+                Kind::Map(state) => {
+                    let mut map = OrMap::default();
+                    for entry in &state.entries {
+                        let dot = to_dot(&entry.dot)?;
+                        map.put(entry.key.clone(), dot, to_value(&entry.value)?, &[]);
+                        this.clock.observe(&dot);
+                    }
+                    map.observe_removed(&to_dots(&state.removed));
+                    Object::Map(map)
+                }
+                Kind::Set(state) => {
+                    let mut set = OrSet::default();
+                    for entry in &state.entries {
+                        let dot = to_dot(&entry.dot)?;
+                        set.add(entry.key.clone(), dot);
+                        this.clock.observe(&dot);
+                    }
+                    set.observe_removed(&to_dots(&state.removed));
+                    Object::Set(set)
+                }
+                Kind::Sequence(state) => {
+                    let mut sequence = Rga::default();
+                    for node in &state.nodes {
+                        let id = to_dot(&node.id)?;
+                        let origin = node.origin.as_ref().map(Dot::from);
+                        let value = node.value.clone().filter(|value| value.kind.is_some());
+                        sequence.push_node(id, origin, value);
+                        this.clock.observe(&id);
+                    }
+                    Object::Sequence(sequence)
+                }
+            };
+
+            this.objects.insert(object.name.clone(), loaded);
+        }
+
+        Ok(this)
+    }
+
+    pub fn replica(&self) -> u32 {
+        self.clock.replica()
+    }
+
+    pub fn object(&self, name: &str) -> Option<&Object> {
+        self.objects.get(name)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = (&String, &Object)> {
+        self.objects.iter()
+    }
+
+    /// Snapshot of the model, sent to the replicas joining late.
+    pub fn snapshot(&self) -> Vec<proto::CrdtObject> {
+        self.objects
+            .iter()
+            .map(|(name, object)| {
+                let kind = match object {
+                    Object::Register(register) => Kind::Register(proto::LwwRegister {
+                        stamp: register.stamp().map(Into::into),
+                        value: register.value().cloned(),
+                    }),
+                    Object::Map(map) => Kind::Map(proto::OrMap {
+                        entries: map
+                            .entries()
+                            .map(|(key, dot, value)| proto::OrMapEntry {
+                                key: key.clone(),
+                                dot: Some((*dot).into()),
+                                value: Some(value.clone()),
+                            })
+                            .collect(),
+                        removed: map.removed().map(|dot| (*dot).into()).collect(),
+                    }),
+                    Object::Set(set) => Kind::Set(proto::OrSet {
+                        entries: set
+                            .entries()
+                            .map(|(element, dot)| proto::OrMapEntry {
+                                key: element.clone(),
+                                dot: Some((*dot).into()),
+                                value: None,
+                            })
+                            .collect(),
+                        removed: set.removed().map(|dot| (*dot).into()).collect(),
+                    }),
+                    Object::Sequence(sequence) => Kind::Sequence(proto::Sequence {
+                        nodes: sequence
+                            .nodes()
+                            .map(|(id, origin, value)| proto::SequenceNode {
+                                id: Some((*id).into()),
+                                origin: origin.map(|origin| (*origin).into()),
+                                value: value.cloned(),
+                            })
+                            .collect(),
+                    }),
+                };
+
+                proto::CrdtObject {
+                    name: name.clone(),
+                    kind: Some(kind),
+                }
+            })
+            .collect()
+    }
+
+    /// Apply the operation of a replica (local or remote).
+    pub fn apply(&mut self, op: &CrdtOp) -> Result<(), CrdtError> {
+        if op.target.is_empty() {
+            return Err(CrdtError::InvalidOp);
+        }
+
+        match op.op.as_ref().ok_or(CrdtError::InvalidOp)? {
+            Op::RegisterSet(set) => {
+                let stamp = to_dot(&set.stamp)?;
+                let value = to_value(&set.value)?;
+
+                match self.object_mut(&op.target, || Object::Register(Default::default())) {
+                    Object::Register(register) => register.set(stamp, value),
+                    _ => return Err(CrdtError::TypeMismatch),
+                };
+                self.clock.observe(&stamp);
+            }
+            Op::MapPut(put) => {
+                let dot = to_dot(&put.dot)?;
+                let value = to_value(&put.value)?;
+                let removed = to_dots(&put.removed);
+
+                match self.object_mut(&op.target, || Object::Map(Default::default())) {
+                    Object::Map(map) => map.put(put.key.clone(), dot, value, &removed),
+                    _ => return Err(CrdtError::TypeMismatch),
+                }
+                self.clock.observe(&dot);
+            }
+            Op::MapRemove(remove) => {
+                let removed = to_dots(&remove.removed);
+
+                match self.existing_mut(&op.target)? {
+                    Object::Map(map) => map.remove(&remove.key, &removed),
+                    _ => return Err(CrdtError::TypeMismatch),
+                }
+            }
+            Op::SetAdd(add) => {
+                let dot = to_dot(&add.dot)?;
+
+                match self.object_mut(&op.target, || Object::Set(Default::default())) {
+                    Object::Set(set) => set.add(add.element.clone(), dot),
+                    _ => return Err(CrdtError::TypeMismatch),
+                }
+                self.clock.observe(&dot);
+            }
+            Op::SetRemove(remove) => {
+                let removed = to_dots(&remove.removed);
+
+                match self.existing_mut(&op.target)? {
+                    Object::Set(set) => set.remove(&remove.element, &removed),
+                    _ => return Err(CrdtError::TypeMismatch),
+                }
+            }
+            Op::SequenceInsert(insert) => {
+                let id = to_dot(&insert.id)?;
+                let origin = insert.origin.as_ref().map(Dot::from);
+                let value = to_value(&insert.value)?;
+
+                match self.object_mut(&op.target, || Object::Sequence(Default::default())) {
+                    Object::Sequence(sequence) => sequence.insert(id, origin, value)?,
+                    _ => return Err(CrdtError::TypeMismatch),
+                }
+                self.clock.observe(&id);
+            }
+            Op::SequenceRemove(remove) => {
+                let id = to_dot(&remove.id)?;
+
+                match self.existing_mut(&op.target)? {
+                    Object::Sequence(sequence) => sequence.remove(&id)?,
+                    _ => return Err(CrdtError::TypeMismatch),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply the operation received from the sender, the new dot of the
+    /// operation must belong to the sender so replicas can't forge the
+    /// operations of the others.
+    pub fn apply_from(&mut self, sender: u32, op: &CrdtOp) -> Result<(), CrdtError> {
+        let dot = match op.op.as_ref().ok_or(CrdtError::InvalidOp)? {
+            Op::RegisterSet(set) => Some(&set.stamp),
+            Op::MapPut(put) => Some(&put.dot),
+            Op::SetAdd(add) => Some(&add.dot),
+            Op::SequenceInsert(insert) => Some(&insert.id),
+            Op::MapRemove(_) | Op::SetRemove(_) | Op::SequenceRemove(_) => None,
+        };
+
+        if let Some(dot) = dot {
+            if to_dot(dot)?.replica != sender {
+                return Err(CrdtError::ForeignDot);
+            }
+        }
+
+        self.apply(op)
+    }
+
+    /// Set the value of the register.
+    pub fn register_set(&mut self, target: &str, value: Value) -> Result<CrdtOp, CrdtError> {
+        let set = proto::RegisterSet {
+            stamp: Some(self.clock.tick()?.into()),
+            value: Some(value),
+        };
+
+        self.apply_local(target, Op::RegisterSet(set))
+    }
+
+    /// Put the value of the key of the map.
+    pub fn map_put(&mut self, target: &str, key: &str, value: Value) -> Result<CrdtOp, CrdtError> {
+        let removed = match self.objects.get(target) {
+            Some(Object::Map(map)) => map.dots(&key.to_string()),
+            _ => Vec::new(),
+        };
+
+        let put = proto::MapPut {
+            key: key.to_string(),
+            dot: Some(self.clock.tick()?.into()),
+            value: Some(value),
+            removed: removed.into_iter().map(Into::into).collect(),
+        };
+
+        self.apply_local(target, Op::MapPut(put))
+    }
+
+    /// Remove the key of the map.
+    pub fn map_remove(&mut self, target: &str, key: &str) -> Result<CrdtOp, CrdtError> {
+        let removed = match self.objects.get(target) {
+            Some(Object::Map(map)) => map.dots(&key.to_string()),
+            Some(_) => return Err(CrdtError::TypeMismatch),
+            None => Vec::new(),
+        };
+
+        if removed.is_empty() {
+            return Err(CrdtError::NotFound);
+        }
+
+        let remove = proto::MapRemove {
+            key: key.to_string(),
+            removed: removed.into_iter().map(Into::into).collect(),
+        };
+
+        self.apply_local(target, Op::MapRemove(remove))
+    }
+
+    /// Add the element to the set.
+    pub fn set_add(&mut self, target: &str, element: &str) -> Result<CrdtOp, CrdtError> {
+        let add = proto::SetAdd {
+            element: element.to_string(),
+            dot: Some(self.clock.tick()?.into()),
+        };
+
+        self.apply_local(target, Op::SetAdd(add))
+    }
+
+    /// Remove the element from the set.
+    pub fn set_remove(&mut self, target: &str, element: &str) -> Result<CrdtOp, CrdtError> {
+        let removed = match self.objects.get(target) {
+            Some(Object::Set(set)) => set.dots(&element.to_string()),
+            Some(_) => return Err(CrdtError::TypeMismatch),
+            None => Vec::new(),
+        };
+
+        if removed.is_empty() {
+            return Err(CrdtError::NotFound);
+        }
+
+        let remove = proto::SetRemove {
+            element: element.to_string(),
+            removed: removed.into_iter().map(Into::into).collect(),
+        };
+
+        self.apply_local(target, Op::SetRemove(remove))
+    }
+
+    /// Insert the value at the index of the sequence.
+    pub fn sequence_insert(
+        &mut self,
+        target: &str,
+        index: usize,
+        value: Value,
+    ) -> Result<CrdtOp, CrdtError> {
+        let origin = match self.objects.get(target) {
+            Some(Object::Sequence(sequence)) => sequence.origin_for_insert(index)?,
+            Some(_) => return Err(CrdtError::TypeMismatch),
+            None if index == 0 => None,
+            None => return Err(CrdtError::IndexOutOfBounds),
+        };
+
+        let insert = proto::SequenceInsert {
+            id: Some(self.clock.tick()?.into()),
+            origin: origin.map(Into::into),
+            value: Some(value),
+        };
+
+        self.apply_local(target, Op::SequenceInsert(insert))
+    }
+
+    /// Remove the value at the index of the sequence.
+    pub fn sequence_remove(&mut self, target: &str, index: usize) -> Result<CrdtOp, CrdtError> {
+        let id = match self.objects.get(target) {
+            Some(Object::Sequence(sequence)) => sequence.id_at(index),
+            Some(_) => return Err(CrdtError::TypeMismatch),
+            None => None,
+        };
+
+        let remove = proto::SequenceRemove {
+            id: Some(id.ok_or(CrdtError::IndexOutOfBounds)?.into()),
+        };
+
+        self.apply_local(target, Op::SequenceRemove(remove))
+    }
+
+    fn apply_local(&mut self, target: &str, op: Op) -> Result<CrdtOp, CrdtError> {
+        let op = CrdtOp {
+            target: target.to_string(),
+            op: Some(op),
+        };
+
+        self.apply(&op)?;
+        Ok(op)
+    }
+
+    fn object_mut(&mut self, target: &str, create: fn() -> Object) -> &mut Object {
+        self.objects
+            .entry(target.to_string())
+            .or_insert_with(create)
+    }
+
+    fn existing_mut(&mut self, target: &str) -> Result<&mut Object, CrdtError> {
+        self.objects.get_mut(target).ok_or(CrdtError::NotFound)
+    }
+}
+
+fn to_dot(dot: &Option<proto::Dot>) -> Result<Dot, CrdtError> {
+    dot.as_ref().map(Dot::from).ok_or(CrdtError::InvalidOp)
+}
+
+fn to_dots(dots: &[proto::Dot]) -> Vec<Dot> {
+    dots.iter().map(Dot::from).collect()
+}
+
+fn to_value(value: &Option<Value>) -> Result<Value, CrdtError> {
+    value
+        .clone()
+        .filter(|value| value.kind.is_some())
+        .ok_or(CrdtError::InvalidOp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fasttravel_rt_proto::realtime::model::value;
+
+    fn string(s: &str) -> Value {
+        Value {
+            kind: Some(value::Kind::StringValue(s.to_string())),
+        }
+    }
+
+    /// Full state of the objects including the dots and the removed dots,
+    /// independent of the iteration order.
+    fn state(replica: &Replica) -> Vec<String> {
+        let sort_dots =
+            |dots: &mut Vec<proto::Dot>| dots.sort_by_key(|dot| (dot.counter, dot.replica));
+        let sort_entries = |entries: &mut Vec<proto::OrMapEntry>| {
+            entries.sort_by_key(|entry| {
+                let dot = entry.dot.clone().unwrap_or_default();
+                (entry.key.clone(), dot.counter, dot.replica)
+            })
+        };
+
+        let mut objects: Vec<String> = replica
+            .snapshot()
+            .into_iter()
+            .map(|mut object| {
+                match object.kind.as_mut() {
+                    Some(Kind::Map(map)) => {
+                        sort_entries(&mut map.entries);
+                        sort_dots(&mut map.removed);
+                    }
+                    Some(Kind::Set(set)) => {
+                        sort_entries(&mut set.entries);
+                        sort_dots(&mut set.removed);
+                    }
+                    _ => {}
+                }
+                format!("{:?}", object)
+            })
+            .collect();
+
+        objects.sort();
+        objects
+    }
+
+    /// All the orders of the ops that keep the order of the ops of each replica.
+    fn interleavings(lists: &[Vec<CrdtOp>]) -> Vec<Vec<CrdtOp>> {
+        if lists.iter().all(|list| list.is_empty()) {
+            return vec![Vec::new()];
+        }
+
+        let mut orders = Vec::new();
+        for (i, list) in lists.iter().enumerate() {
+            if let Some((first, rest)) = list.split_first() {
+                let mut remaining = lists.to_vec();
+                remaining[i] = rest.to_vec();
+
+                for mut order in interleavings(&remaining) {
+                    order.insert(0, first.clone());
+                    orders.push(order);
+                }
+            }
+        }
+
+        orders
+    }
+
+    /// Ops shared by all the replicas, and the concurrent ops of three replicas.
+    fn concurrent_ops() -> (Vec<CrdtOp>, Vec<Vec<CrdtOp>>) {
+        let mut r1 = Replica::new(1);
+        let base = vec![
+            r1.register_set("title", string("base")).unwrap(),
+            r1.map_put("props", "k1", string("base")).unwrap(),
+            r1.set_add("tags", "a").unwrap(),
+            r1.sequence_insert("list", 0, string("x")).unwrap(),
+            r1.sequence_insert("list", 1, string("y")).unwrap(),
+        ];
+
+        let mut r2 = Replica::new(2);
+        let mut r3 = Replica::new(3);
+        for op in &base {
+            r2.apply(op).unwrap();
+            r3.apply(op).unwrap();
+        }
+
+        let ops1 = vec![
+            r1.register_set("title", string("r1")).unwrap(),
+            r1.map_put("props", "k1", string("r1")).unwrap(),
+            r1.set_remove("tags", "a").unwrap(),
+            r1.sequence_insert("list", 1, string("p")).unwrap(),
+            r1.sequence_remove("list", 0).unwrap(),
+        ];
+        let ops2 = vec![
+            r2.register_set("title", string("r2")).unwrap(),
+            r2.map_remove("props", "k1").unwrap(),
+            r2.set_add("tags", "a").unwrap(),
+            r2.sequence_insert("list", 1, string("q")).unwrap(),
+        ];
+        let ops3 = vec![
+            r3.sequence_insert("list", 1, string("s")).unwrap(),
+            r3.set_add("tags", "b").unwrap(),
+            r3.register_set("title", string("r3")).unwrap(),
+        ];
+
+        (base, vec![ops1, ops2, ops3])
+    }
+
+    fn replay(base: &[CrdtOp], order: &[CrdtOp], duplicate: bool) -> Replica {
+        let mut replica = Replica::new(9);
+        for op in base.iter().chain(order) {
+            replica.apply(op).unwrap();
+            if duplicate {
+                replica.apply(op).unwrap();
+            }
+        }
+
+        replica
+    }
+
+    #[test]
+    fn converges_in_any_order() {
+        let (base, concurrent) = concurrent_ops();
+        let orders = interleavings(&concurrent);
+        let expected = state(&replay(&base, &orders[0], false));
+
+        for order in &orders {
+            assert_eq!(state(&replay(&base, order, false)), expected);
+        }
+
+        let replica = replay(&base, &orders[0], false);
+
+        // the register write with the greatest dot wins.
+        match replica.object("title") {
+            Some(Object::Register(register)) => assert_eq!(register.value(), Some(&string("r3"))),
+            _ => panic!("register_missing"),
+        }
+
+        // the remove only removes the observed put, the concurrent put is kept.
+        match replica.object("props") {
+            Some(Object::Map(map)) => assert_eq!(map.get(&"k1".to_string()), Some(&string("r1"))),
+            _ => panic!("map_missing"),
+        }
+
+        // the concurrent add wins over the remove.
+        match replica.object("tags") {
+            Some(Object::Set(set)) => assert!(set.contains(&"a".to_string())),
+            _ => panic!("set_missing"),
+        }
+
+        // concurrent inserts after the same origin are ordered by their dots.
+        match replica.object("list") {
+            Some(Object::Sequence(sequence)) => {
+                let values: Vec<_> = sequence.iter().cloned().collect();
+                let expected = ["q", "p", "s", "y"].map(string);
+                assert_eq!(values, expected);
+            }
+            _ => panic!("sequence_missing"),
+        }
+    }
+
+    #[test]
+    fn converges_with_duplicated_ops() {
+        let (base, concurrent) = concurrent_ops();
+        let orders = interleavings(&concurrent);
+        let expected = state(&replay(&base, &orders[0], false));
+
+        for order in orders.iter().step_by(97) {
+            assert_eq!(state(&replay(&base, order, true)), expected);
+        }
+
+        // the whole history delivered again in reverse, the redelivered
+        // puts follow the removes of their dots.
+        let mut replica = replay(&base, &orders[0], false);
+        for op in base.iter().chain(&orders[orders.len() - 1]).rev() {
+            replica.apply(op).unwrap();
+        }
+        assert_eq!(state(&replica), expected);
+    }
+
+    #[test]
+    fn remove_on_unknown_object_is_rejected() {
+        let (_, concurrent) = concurrent_ops();
+        let mut replica = Replica::new(9);
+
+        // the removes reference objects the replica has not observed.
+        for op in concurrent.concat() {
+            let rejected = matches!(
+                op.op,
+                Some(Op::MapRemove(_)) | Some(Op::SetRemove(_)) | Some(Op::SequenceRemove(_))
+            );
+            if rejected {
+                assert_eq!(replica.apply(&op), Err(CrdtError::NotFound));
+                assert!(replica.object(&op.target).is_none());
+            }
+        }
+
+        assert!(replica.snapshot().is_empty());
+    }
+
+    #[test]
+    fn foreign_dots_are_rejected() {
+        let (base, concurrent) = concurrent_ops();
+        let mut forged = replay(&base, &[], false);
+        let mut sent = replay(&base, &[], false);
+
+        // the ops of replica 2 sent by replica 3, the removes carry no dot
+        // of the sender and are accepted.
+        for op in &concurrent[1] {
+            let result = forged.apply_from(3, op);
+            if matches!(op.op, Some(Op::MapRemove(_))) {
+                assert_eq!(result, Ok(()));
+            } else {
+                assert_eq!(result, Err(CrdtError::ForeignDot));
+            }
+
+            sent.apply_from(2, op).unwrap();
+        }
+    }
+
+    #[test]
+    fn exhausted_clock_fails_the_local_ops() {
+        let mut remote = Replica::new(1);
+        let forged = CrdtOp {
+            target: "title".to_string(),
+            op: Some(Op::RegisterSet(proto::RegisterSet {
+                stamp: Some(Dot::new(1, u64::MAX).into()),
+                value: Some(string("forged")),
+            })),
+        };
+        remote.apply(&forged).unwrap();
+
+        assert_eq!(
+            remote.register_set("title", string("local")),
+            Err(CrdtError::ClockOverflow)
+        );
+        assert_eq!(
+            remote.map_put("props", "k1", string("local")),
+            Err(CrdtError::ClockOverflow)
+        );
+        assert!(remote.object("props").is_none());
+    }
+
+    #[test]
+    fn redelivered_put_after_remove_is_ignored() {
+        let mut r1 = Replica::new(1);
+        let put = r1.map_put("props", "k1", string("v")).unwrap();
+        let add = r1.set_add("tags", "a").unwrap();
+        r1.map_remove("props", "k1").unwrap();
+        r1.set_remove("tags", "a").unwrap();
+
+        // the snapshot keeps the removed dots of the loaded replica.
+        let mut loaded = Replica::load(2, &r1.snapshot()).unwrap();
+        assert_eq!(state(&loaded), state(&r1));
+
+        for replica in [&mut r1, &mut loaded] {
+            replica.apply(&put).unwrap();
+            replica.apply(&add).unwrap();
+
+            match replica.object("props") {
+                Some(Object::Map(map)) => assert!(map.is_empty()),
+                _ => panic!("map_missing"),
+            }
+            match replica.object("tags") {
+                Some(Object::Set(set)) => assert!(set.is_empty()),
+                _ => panic!("set_missing"),
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{CrdtError, Dot};
+
+/// Node of the sequence, a removed node is kept as a tombstone
+/// as later inserts could reference it as their origin.
+#[derive(Clone, Debug)]
+struct Node<V> {
+    id: Dot,
+    origin: Option<Dot>,
+    value: Option<V>,
+}
+
+/// Replicated growable array (RGA).
+///
+/// Every node is inserted after its origin node, concurrent inserts after
+/// the same origin are ordered by their dots (greatest first). As a dot is
+/// always greater than the dot of its origin, skipping the greater dots
+/// also skips their descendants.
+#[derive(Clone, Debug)]
+pub struct Rga<V> {
+    nodes: Vec<Node<V>>,
+}
+
+impl<V> Default for Rga<V> {
+    fn default() -> Self {
+        Self { nodes: Vec::new() }
+    }
+}
+
+impl<V: Clone> Rga<V> {
+    /// Number of visible (not removed) values.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Iterate the visible values in sequence order.
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.nodes.iter().filter_map(|node| node.value.as_ref())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.iter().nth(index)
+    }
+
+    /// Iterate all the nodes in sequence order, used to serialize the state.
+    pub fn nodes(&self) -> impl Iterator<Item = (&Dot, Option<&Dot>, Option<&V>)> {
+        self.nodes
+            .iter()
+            .map(|node| (&node.id, node.origin.as_ref(), node.value.as_ref()))
+    }
+
+    /// Dot of the visible value at the index.
+    pub fn id_at(&self, index: usize) -> Option<Dot> {
+        self.nodes
+            .iter()
+            .filter(|node| node.value.is_some())
+            .nth(index)
+            .map(|node| node.id)
+    }
+
+    /// Origin of a local insert at the index, None inserts at the head.
+    pub fn origin_for_insert(&self, index: usize) -> Result<Option<Dot>, CrdtError> {
+        match index {
+            0 => Ok(None),
+            _ => self
+                .id_at(index - 1)
+                .map(Some)
+                .ok_or(CrdtError::IndexOutOfBounds),
+        }
+    }
+
+    /// Insert the value after the origin, inserting an observed id is a no-op.
+    pub fn insert(&mut self, id: Dot, origin: Option<Dot>, value: V) -> Result<(), CrdtError> {
+        if self.position(&id).is_some() {
+            return Ok(());
+        }
+
+        let mut index = match &origin {
+            Some(origin) => self.position(origin).ok_or(CrdtError::MissingDot)? + 1,
+            None => 0,
+        };
+
+        while index < self.nodes.len() && self.nodes[index].id > id {
+            index += 1;
+        }
+
+        let node = Node {
+            id,
+            origin,
+            value: Some(value),
+        };
+        self.nodes.insert(index, node);
+
+        Ok(())
+    }
+
+    /// Remove the value of the node, the node is kept as a tombstone.
+    pub fn remove(&mut self, id: &Dot) -> Result<(), CrdtError> {
+        let index = self.position(id).ok_or(CrdtError::MissingDot)?;
+        self.nodes[index].value = None;
+
+        Ok(())
+    }
+
+    /// Append a node in sequence order, used to load a serialized state.
+    pub fn push_node(&mut self, id: Dot, origin: Option<Dot>, value: Option<V>) {
+        self.nodes.push(Node { id, origin, value });
+    }
+
+    fn position(&self, id: &Dot) -> Option<usize> {
+        self.nodes.iter().position(|node| &node.id == id)
+    }
+}
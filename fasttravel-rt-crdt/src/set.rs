@@ -0,0 +1,64 @@
+use std::hash::Hash;
+
+use crate::{Dot, OrMap};
+
+/// Observed-remove set, add wins over a concurrent remove.
+#[derive(Clone, Debug)]
+pub struct OrSet<T> {
+    inner: OrMap<T, ()>,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            inner: OrMap::default(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> OrSet<T> {
+    pub fn contains(&self, element: &T) -> bool {
+        self.inner.contains_key(element)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.inner.iter().map(|(element, _)| element)
+    }
+
+    /// Iterate all the tagged elements, used to serialize the state.
+    pub fn entries(&self) -> impl Iterator<Item = (&T, &Dot)> {
+        self.inner.entries().map(|(element, dot, _)| (element, dot))
+    }
+
+    /// Iterate the removed dots, used to serialize the state.
+    pub fn removed(&self) -> impl Iterator<Item = &Dot> {
+        self.inner.removed()
+    }
+
+    /// Dots of the element observed by the replica.
+    pub fn dots(&self, element: &T) -> Vec<Dot> {
+        self.inner.dots(element)
+    }
+
+    pub fn add(&mut self, element: T, dot: Dot) {
+        self.inner.put(element, dot, (), &[]);
+    }
+
+    /// Remove the observed dots of the element.
+    pub fn remove(&mut self, element: &T, removed: &[Dot]) {
+        self.inner.remove(element, removed);
+    }
+
+    /// Record the removed dots without an element, used to load the state.
+    pub fn observe_removed(&mut self, removed: &[Dot]) {
+        self.inner.observe_removed(removed);
+    }
+}
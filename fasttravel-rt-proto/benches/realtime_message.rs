@@ -0,0 +1,121 @@
+//!
+//! Benchmarks of the realtime message helpers: the header-peeking decoder and
+//! the encoder appending the pre-encoded service payload, compared with the
+//! full decode and re-encode of the service message.
+//!
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use prost::Message;
+
+use fasttravel_rt_proto::{
+    helpers::{self, core as proto_core, model as proto_model, ProtoBytes, ProtoMessage},
+    realtime::{
+        self,
+        model::{operation, value, Operation, SetProperty, Value},
+        realtime_message::{Body, Header},
+    },
+    RealtimeService,
+};
+
+/// Model commit with the number of set property operations.
+fn model_payload(ops: usize) -> ProtoBytes {
+    let ops = (0..ops)
+        .map(|i| {
+            let position = format!("{},{},{}", i, i * 2, i * 3);
+
+            Operation {
+                op: Some(operation::Op::SetProperty(SetProperty {
+                    id: format!("element-{}", i),
+                    key: "position".to_string(),
+                    value: Some(Value {
+                        kind: Some(value::Kind::StringValue(position)),
+                    }),
+                })),
+            }
+        })
+        .collect();
+
+    proto_model::create_model_committed(42, 7, ops)
+}
+
+/// Topic message relaying the model commit as the opaque topic payload.
+fn topic_payload(ops: usize) -> ProtoBytes {
+    proto_core::create_core_topic_relayed("model".to_string(), 7, model_payload(ops))
+}
+
+/// Decode the header of the realtime message and slice the service message.
+fn process_peek(msg: ProtoBytes) -> ProtoBytes {
+    match helpers::process_realtime_message(msg) {
+        Ok(ProtoMessage::Tell(tell)) => tell.bytes,
+        _ => unreachable!(),
+    }
+}
+
+/// Decode the whole realtime message and re-encode the service message.
+fn process_reencode(msg: &[u8]) -> Option<ProtoBytes> {
+    let rt_msg = realtime::RealtimeMessage::decode(msg).ok()?;
+
+    match rt_msg.body? {
+        Body::ModelMsg(service_msg) => Some(service_msg.encode_to_vec()),
+        _ => None,
+    }
+}
+
+/// Decode the service payload into the body and encode the realtime message.
+fn create_reencode(payload: &[u8]) -> Option<ProtoBytes> {
+    let service_msg = realtime::core::Message::decode(payload).ok()?;
+    let rt_msg = realtime::RealtimeMessage {
+        header: Some(Header::default()),
+        body: Some(Body::CoreMsg(service_msg)),
+    };
+
+    Some(rt_msg.encode_to_vec())
+}
+
+fn bench_process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_realtime_message");
+
+    for ops in [1, 16, 256] {
+        let payload = model_payload(ops);
+        let msg =
+            helpers::create_tell_message_from_service_payload(&RealtimeService::Model, payload)
+                .unwrap();
+
+        group.bench_with_input(BenchmarkId::new("peek", ops), &msg, |b, msg| {
+            b.iter(|| process_peek(black_box(msg.clone())))
+        });
+
+        group.bench_with_input(BenchmarkId::new("reencode", ops), &msg, |b, msg| {
+            b.iter(|| process_reencode(black_box(&msg.clone())).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_create(c: &mut Criterion) {
+    let mut group = c.benchmark_group("create_tell_message");
+
+    for ops in [1, 16, 256] {
+        let payload = topic_payload(ops);
+
+        group.bench_with_input(BenchmarkId::new("append", ops), &payload, |b, payload| {
+            b.iter(|| {
+                helpers::create_tell_message_from_service_payload(
+                    &RealtimeService::Core,
+                    black_box(payload.clone()),
+                )
+                .unwrap()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("reencode", ops), &payload, |b, payload| {
+            b.iter(|| create_reencode(black_box(&payload.clone())).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_process, bench_create);
+criterion_main!(benches);
@@ -4,35 +4,109 @@
 
 #![allow(dead_code)]
 
+pub mod activity;
 pub mod connection;
 pub mod core;
-
-use log::{error, trace};
-use prost::Message;
+pub mod model;
+pub mod presence;
+
+use log::trace;
+use prost::{
+    bytes::Buf,
+    encoding::{self, DecodeContext, WireType},
+    DecodeError, Message,
+};
+use std::ops::Range;
 
 use crate::{
-    realtime::{self, realtime_message, realtime_message::Body, RealtimeMessage},
+    realtime::{self, realtime_message, realtime_message::Body, DeliveryClass, StatusCode},
     RealtimeService,
 };
 
+// Field tags of the RealtimeMessage, refer to realtime.proto.
+const TAG_HEADER: u32 = 1;
+const TAG_CONNECTION_MSG: u32 = 2;
+const TAG_CORE_MSG: u32 = 3;
+const TAG_PRESENCE_MSG: u32 = 4;
+const TAG_ACTIVITY_MSG: u32 = 5;
+const TAG_MODEL_MSG: u32 = 6;
+const TAG_ERROR_RES: u32 = 7;
+
+// First byte of a batch of realtime messages, a realtime message never starts
+// with a zero byte as zero is not a valid field key.
+const BATCH_MARKER: u8 = 0;
+
 /// Message types based on the RealtimeMessage.header request_id and response_id fields.
 /// The payload is the service specific body extracted after striping the header.
 pub enum ProtoMessage {
-    /// undefined message type, either uninitialized or error.
-    Undefined,
     /// a tell message
     Tell(ProtoPayloadTell),
     /// a request initiated through an ask.
     Request(ProtoPayloadRequest),
     /// the response to a previous ask request.
     Response(ProtoPayloadResponse),
+    /// the error response to a previous ask request.
+    Error(ProtoPayloadError),
 }
 
 pub type ProtoBytes = Vec<u8>;
 
+/// Errors of the protocol buffer helpers, for both the realtime messages
+/// and the service messages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProtoError {
+    /// the bytes are not a valid encoding of the message.
+    Decode(DecodeError),
+    /// the realtime message has no header.
+    MissingHeader,
+    /// the message has no body, or the body of an unknown service.
+    MissingBody,
+    /// the request_id and response_id of the header conflict, both are set
+    /// or an error response has no response_id.
+    ConflictingIds { request_id: u32, response_id: u32 },
+    /// the service has no message in the realtime message body, e.g. the
+    /// undefined service.
+    UnknownService(RealtimeService),
+}
+
+impl std::fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "proto_decode_failed: {}", e),
+            Self::MissingHeader => write!(f, "proto_missing_header"),
+            Self::MissingBody => write!(f, "proto_missing_body"),
+            Self::ConflictingIds {
+                request_id,
+                response_id,
+            } => write!(
+                f,
+                "proto_conflicting_ids: request_id {} response_id {}",
+                request_id, response_id
+            ),
+            Self::UnknownService(service) => write!(f, "proto_unknown_service: {:?}", service),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for ProtoError {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
 /// Extracted payload of a TELL message.
 pub struct ProtoPayloadTell {
     pub service: RealtimeService,
+    pub delivery: DeliveryClass,
     pub bytes: ProtoBytes,
 }
 
@@ -50,32 +124,104 @@ pub struct ProtoPayloadResponse {
     pub bytes: ProtoBytes,
 }
 
+/// Extracted payload of an ERROR RESPONSE message.
+pub struct ProtoPayloadError {
+    pub response_id: u32,
+    pub service: RealtimeService,
+    pub code: StatusCode,
+    pub message: String,
+}
+
 ///
 /// Process the protocol buffers received over the socket.
 /// 1. Decode the bytes to RealtimeMessage.
 /// 2. Check header and body fields of the message.
-/// 3. Determine whether the message is TELL, REQUEST, or RESPONSE.
+/// 3. Determine whether the message is TELL, REQUEST, RESPONSE, or ERROR.
 /// 4. Extract service specific payload.
-/// 5. Return a ProtoMessage with appropriate payload.
+/// 5. Return a ProtoMessage with appropriate payload, or the ProtoError.
 ///
-pub fn process_realtime_message(msg: ProtoBytes) -> ProtoMessage {
+/// The service payload is not decoded, its encoded bytes are moved out of the
+/// message buffer.
+///
+pub fn process_realtime_message(msg: ProtoBytes) -> Result<ProtoMessage, ProtoError> {
     trace!("process_realtime_message");
 
-    realtime::RealtimeMessage::decode(&msg[..])
-        .map(|rt_msg| process_header_and_message(rt_msg))
-        .map_err(|e| error!("process_realtime_message_ERROR: {}", e))
-        .unwrap_or(ProtoMessage::Undefined)
+    let rt_msg = peek_realtime_message(&msg[..])?;
+    process_header_and_message(msg, rt_msg)
+}
+
+/// Create a batch of realtime messages, sent in a single websocket message to
+/// the clients that negotiated the batching feature. The batch is the marker
+/// byte followed by the length-delimited messages.
+pub fn create_realtime_batch(messages: &[ProtoBytes]) -> ProtoBytes {
+    trace!("create_realtime_batch MESSAGES: {}", messages.len());
+
+    let len = messages
+        .iter()
+        .map(|msg| encoding::encoded_len_varint(msg.len() as u64) + msg.len())
+        .sum::<usize>();
+
+    let mut batch = Vec::with_capacity(1 + len);
+    batch.push(BATCH_MARKER);
+    for msg in messages {
+        encoding::encode_varint(msg.len() as u64, &mut batch);
+        batch.extend_from_slice(msg);
+    }
+
+    batch
+}
+
+/// Whether the bytes received over the socket are a batch of realtime messages.
+pub fn is_realtime_batch(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&BATCH_MARKER)
+}
+
+/// Split the batch into its realtime messages, each message is then processed
+/// with process_realtime_message.
+pub fn split_realtime_batch(batch: &[u8]) -> Result<Vec<ProtoBytes>, ProtoError> {
+    trace!("split_realtime_batch");
+
+    if !is_realtime_batch(batch) {
+        return Err(DecodeError::new("invalid batch marker").into());
+    }
+
+    let mut messages = Vec::new();
+    let mut buf = &batch[1..];
+    while buf.has_remaining() {
+        let len = encoding::decode_varint(&mut buf)?;
+        if len > buf.remaining() as u64 {
+            return Err(DecodeError::new("buffer underflow").into());
+        }
+
+        messages.push(buf[..len as usize].to_vec());
+        buf.advance(len as usize);
+    }
+
+    Ok(messages)
 }
 
 /// Create a TELL message from a service payload.
 pub fn create_tell_message_from_service_payload(
     service: &RealtimeService,
     payload: ProtoBytes,
-) -> Option<ProtoBytes> {
+) -> Result<ProtoBytes, ProtoError> {
     trace!("create_tell_message_from_service_payload");
 
-    create_rt_message_from_service_payload(service, payload)
-        .and_then(|rt_msg| Some(rt_msg.encode_to_vec()))
+    let tell_header = realtime_message::Header::default();
+    create_rt_message_from_service_payload(&tell_header, service, &payload)
+}
+
+/// Create a TELL message from a service payload with the delivery class of the message.
+pub fn create_tell_message_with_delivery(
+    service: &RealtimeService,
+    delivery: DeliveryClass,
+    payload: ProtoBytes,
+) -> Result<ProtoBytes, ProtoError> {
+    trace!("create_tell_message_with_delivery");
+
+    let mut tell_header = realtime_message::Header::default();
+    tell_header.set_delivery(delivery);
+    create_rt_message_from_service_payload(&tell_header, service, &payload)
 }
 
 /// Create a RESPONSE message from a service payload and the RESPONSE_ID
@@ -83,16 +229,14 @@ pub fn create_response_message_from_service_payload(
     response_id: u32,
     service: &RealtimeService,
     payload: ProtoBytes,
-) -> Option<ProtoBytes> {
+) -> Result<ProtoBytes, ProtoError> {
     trace!("create_response_message_from_service_payload");
 
-    create_rt_message_from_service_payload(service, payload).and_then(|mut rt_msg| {
-        let mut response_header = realtime_message::Header::default();
-        response_header.response_id = response_id;
-        rt_msg.header = Some(response_header);
-
-        Some(rt_msg.encode_to_vec())
-    })
+    let response_header = realtime_message::Header {
+        response_id,
+        ..Default::default()
+    };
+    create_rt_message_from_service_payload(&response_header, service, &payload)
 }
 
 /// Create a REQUEST message from a service payload and the REQUEST_ID
@@ -100,150 +244,276 @@ pub fn create_request_message_from_service_payload(
     request_id: u32,
     service: &RealtimeService,
     payload: ProtoBytes,
-) -> Option<ProtoBytes> {
+) -> Result<ProtoBytes, ProtoError> {
     trace!("create_request_message_from_service_payload");
 
-    create_rt_message_from_service_payload(service, payload).and_then(|mut rt_msg| {
-        let mut request_header = realtime_message::Header::default();
-        request_header.request_id = request_id;
-        rt_msg.header = Some(request_header);
-
-        Some(rt_msg.encode_to_vec())
-    })
+    let request_header = realtime_message::Header {
+        request_id,
+        ..Default::default()
+    };
+    create_rt_message_from_service_payload(&request_header, service, &payload)
 }
 
-fn create_rt_message_from_service_payload(
+/// Create an ERROR RESPONSE message for the request with the RESPONSE_ID.
+pub fn create_error_response_message(
+    response_id: u32,
     service: &RealtimeService,
-    payload: ProtoBytes,
-) -> Option<RealtimeMessage> {
-    let mut body = None;
+    code: StatusCode,
+    message: &str,
+) -> ProtoBytes {
+    trace!("create_error_response_message");
+
+    let error_res = realtime::ErrorResponse {
+        code: code as i32,
+        service: proto_service_from_realtime_service(service) as i32,
+        message: message.to_string(),
+    };
+
+    let response_header = realtime_message::Header {
+        request_id: 0,
+        response_id,
+        delivery: DeliveryClass::Reliable as i32,
+    };
+
+    let rt_msg = realtime::RealtimeMessage {
+        header: Some(response_header),
+        body: Some(Body::ErrorRes(error_res)),
+    };
+
+    rt_msg.encode_to_vec()
+}
 
+fn proto_service_from_realtime_service(service: &RealtimeService) -> realtime::Service {
     match service {
-        RealtimeService::Connection => {
-            let _ = realtime::connection::Message::decode(&payload[..])
-                .map(|msg| body = Some(Body::ConnectionMsg(msg)))
-                .map_err(|e| error!("realtime_connection_Message_decode {}", e));
-        }
-        RealtimeService::Core => {
-            let _ = realtime::core::Message::decode(&payload[..])
-                .map(|msg| body = Some(Body::CoreMsg(msg)))
-                .map_err(|e| error!("realtime_core_Message_decode {}", e));
-        }
-        _ => {
-            error!("create_rt_message_from_service_payload_ERROR_service_not_supported_yet");
-        }
+        RealtimeService::Undefined => realtime::Service::Undefined,
+        RealtimeService::Connection => realtime::Service::Connection,
+        RealtimeService::Core => realtime::Service::Core,
+        RealtimeService::Presence => realtime::Service::Presence,
+        RealtimeService::Activity => realtime::Service::Activity,
+        RealtimeService::Model => realtime::Service::Model,
     }
+}
 
-    body.and_then(|b| {
-        let mut rt_msg = realtime::RealtimeMessage::default();
-        rt_msg.body = Some(b);
+fn realtime_service_from_proto_service(service: realtime::Service) -> RealtimeService {
+    match service {
+        realtime::Service::Undefined => RealtimeService::Undefined,
+        realtime::Service::Connection => RealtimeService::Connection,
+        realtime::Service::Core => RealtimeService::Core,
+        realtime::Service::Presence => RealtimeService::Presence,
+        realtime::Service::Activity => RealtimeService::Activity,
+        realtime::Service::Model => RealtimeService::Model,
+    }
+}
+
+fn body_tag_from_realtime_service(service: &RealtimeService) -> Option<u32> {
+    match service {
+        RealtimeService::Undefined => None,
+        RealtimeService::Connection => Some(TAG_CONNECTION_MSG),
+        RealtimeService::Core => Some(TAG_CORE_MSG),
+        RealtimeService::Presence => Some(TAG_PRESENCE_MSG),
+        RealtimeService::Activity => Some(TAG_ACTIVITY_MSG),
+        RealtimeService::Model => Some(TAG_MODEL_MSG),
+    }
+}
 
-        Some(rt_msg)
-    })
+fn realtime_service_from_body_tag(tag: u32) -> Option<RealtimeService> {
+    match tag {
+        TAG_CONNECTION_MSG => Some(RealtimeService::Connection),
+        TAG_CORE_MSG => Some(RealtimeService::Core),
+        TAG_PRESENCE_MSG => Some(RealtimeService::Presence),
+        TAG_ACTIVITY_MSG => Some(RealtimeService::Activity),
+        TAG_MODEL_MSG => Some(RealtimeService::Model),
+        _ => None,
+    }
 }
 
 ///
-/// Extracts the service specific payload from the realtime message.
-/// 1. Check if payload is valid.
-/// 2. Check the audience or issuer service.
-/// 3. get the service specifc payload.
-/// 4. Return service and service-payload tuple.
+/// Encode the realtime message with the header and the service payload.
+/// The payload is already encoded by the service, it is appended as the
+/// body field of the service without being decoded and re-encoded.
 ///
-fn extract_service_payload_from_rt_message(
-    body: realtime_message::Body,
-) -> Option<(RealtimeService, ProtoBytes)> {
-    trace!("extract_service_payload_from_rt_message");
-
-    let service;
-    let proto_bytes;
-
-    // This does a re-encoding, as we want the services to handle their own
-    // payload and to easily move encoded-bytes instead of structs.
-    // [todo] performance tests (for realtime message sizes this may not be an issue).
-    match body {
-        Body::ConnectionMsg(service_msg) => {
-            service = RealtimeService::Connection;
-            proto_bytes = Some(service_msg.encode_to_vec());
-        }
-        Body::CoreMsg(service_msg) => {
-            service = RealtimeService::Core;
-            proto_bytes = Some(service_msg.encode_to_vec());
-        }
-        Body::PresenceMsg(service_msg) => {
-            service = RealtimeService::Presence;
-            proto_bytes = Some(service_msg.encode_to_vec());
-        }
-        Body::ActivityMsg(service_msg) => {
-            service = RealtimeService::Activity;
-            proto_bytes = Some(service_msg.encode_to_vec());
-        }
-        Body::ModelMsg(service_msg) => {
-            service = RealtimeService::Model;
-            proto_bytes = Some(service_msg.encode_to_vec());
+fn create_rt_message_from_service_payload(
+    header: &realtime_message::Header,
+    service: &RealtimeService,
+    payload: &[u8],
+) -> Result<ProtoBytes, ProtoError> {
+    let tag = body_tag_from_realtime_service(service)
+        .ok_or_else(|| ProtoError::UnknownService(service.clone()))?;
+
+    let len = encoding::message::encoded_len(TAG_HEADER, header)
+        + encoding::key_len(tag)
+        + encoding::encoded_len_varint(payload.len() as u64)
+        + payload.len();
+
+    let mut rt_msg = Vec::with_capacity(len);
+    encoding::message::encode(TAG_HEADER, header, &mut rt_msg);
+    encoding::encode_key(tag, WireType::LengthDelimited, &mut rt_msg);
+    encoding::encode_varint(payload.len() as u64, &mut rt_msg);
+    rt_msg.extend_from_slice(payload);
+
+    Ok(rt_msg)
+}
+
+/// Realtime message with the header decoded and the service payload
+/// left encoded in the message buffer.
+struct PeekedRealtimeMessage {
+    header: Option<realtime_message::Header>,
+    body: Option<PeekedBody>,
+}
+
+enum PeekedBody {
+    /// the service and the range of its encoded payload in the message.
+    Service(RealtimeService, Range<usize>),
+    /// the service and its payload merged from the repeated body fields.
+    Merged(RealtimeService, ProtoBytes),
+    /// error responses carry no service payload.
+    ErrorRes(realtime::ErrorResponse),
+}
+
+///
+/// Decode the header of the realtime message and locate the service payload.
+/// 1. Walk the fields of the message.
+/// 2. Decode the header and the error response fields.
+/// 3. Keep the range of the service specific body, the last service wins and
+///    the repeated fields of the same service are merged.
+/// 4. Skip the unknown fields.
+///
+fn peek_realtime_message(msg: &[u8]) -> Result<PeekedRealtimeMessage, DecodeError> {
+    trace!("peek_realtime_message");
+
+    let mut rt_msg = PeekedRealtimeMessage {
+        header: None,
+        body: None,
+    };
+
+    let mut buf = msg;
+    while buf.has_remaining() {
+        let (tag, wire_type) = encoding::decode_key(&mut buf)?;
+
+        if tag == TAG_HEADER {
+            let header = rt_msg.header.get_or_insert_with(Default::default);
+            encoding::message::merge(wire_type, header, &mut buf, DecodeContext::default())?;
+        } else if tag == TAG_ERROR_RES {
+            let mut error_res = match rt_msg.body.take() {
+                Some(PeekedBody::ErrorRes(error_res)) => error_res,
+                _ => realtime::ErrorResponse::default(),
+            };
+            encoding::message::merge(
+                wire_type,
+                &mut error_res,
+                &mut buf,
+                DecodeContext::default(),
+            )?;
+            rt_msg.body = Some(PeekedBody::ErrorRes(error_res));
+        } else if let Some(service) = realtime_service_from_body_tag(tag) {
+            encoding::check_wire_type(WireType::LengthDelimited, wire_type)?;
+
+            let len = encoding::decode_varint(&mut buf)?;
+            if len > buf.remaining() as u64 {
+                return Err(DecodeError::new("buffer underflow"));
+            }
+
+            let start = msg.len() - buf.remaining();
+            let end = start + len as usize;
+            buf.advance(len as usize);
+
+            // a repeated body field merges into the message of the same service,
+            // the merge of the encoded messages is their concatenation.
+            rt_msg.body = Some(match rt_msg.body.take() {
+                Some(PeekedBody::Service(peeked, range)) if peeked == service => {
+                    PeekedBody::Merged(service, [&msg[range], &msg[start..end]].concat())
+                }
+                Some(PeekedBody::Merged(peeked, mut bytes)) if peeked == service => {
+                    bytes.extend_from_slice(&msg[start..end]);
+                    PeekedBody::Merged(service, bytes)
+                }
+                _ => PeekedBody::Service(service, start..end),
+            });
+        } else {
+            encoding::skip_field(wire_type, tag, &mut buf, DecodeContext::default())?;
         }
     }
 
-    // currently check redundant, but we might need when we add more services.
-    if proto_bytes.is_none() || service == RealtimeService::Undefined {
-        return None;
-    }
+    Ok(rt_msg)
+}
 
-    Some((service, proto_bytes.unwrap()))
+/// Move the bytes in the range out of the message buffer, the buffer is
+/// reused for the payload.
+fn take_payload(mut msg: ProtoBytes, range: Range<usize>) -> ProtoBytes {
+    msg.truncate(range.end);
+    msg.drain(..range.start);
+    msg
 }
 
 ///
 /// Process the header and body fields of the realtime message.
 /// 1. Check if header and body fields are valid.
 /// 2. Check header request_id and response_id fields.
-/// 3. Determine if message is TELL, REQUEST, or RESPONSE.
+/// 3. Determine if message is TELL, REQUEST, RESPONSE, or ERROR.
 /// 4. Extract service specific payload.
-/// 5. Return the ProtoMessage with appropriate payload.
+/// 5. Return the ProtoMessage with appropriate payload, or the ProtoError.
 ///
-fn process_header_and_message(rt_msg: RealtimeMessage) -> ProtoMessage {
-    // check for undefined message
-    if rt_msg.header.is_none() || rt_msg.body.is_none() {
-        error!("process_header_and_message_ERROR_undefined_header_or_body");
-        return ProtoMessage::Undefined;
-    }
-
-    let header = rt_msg.header.unwrap();
-    let body = rt_msg.body.unwrap();
+fn process_header_and_message(
+    msg: ProtoBytes,
+    rt_msg: PeekedRealtimeMessage,
+) -> Result<ProtoMessage, ProtoError> {
+    let header = rt_msg.header.ok_or(ProtoError::MissingHeader)?;
+    let body = rt_msg.body.ok_or(ProtoError::MissingBody)?;
+
+    let conflicting_ids = ProtoError::ConflictingIds {
+        request_id: header.request_id,
+        response_id: header.response_id,
+    };
 
     if header.request_id != 0 && header.response_id != 0 {
-        error!("process_header_and_message_ERROR_req_res_id_both_set");
-        return ProtoMessage::Undefined;
+        return Err(conflicting_ids);
     }
 
-    extract_service_payload_from_rt_message(body)
-        .map(|payload| {
-            if header.request_id != 0 {
-                let request_payload = ProtoPayloadRequest {
-                    request_id: header.request_id,
-                    service: payload.0,
-                    bytes: payload.1,
-                };
-
-                return ProtoMessage::Request(request_payload);
-            } else if header.response_id != 0 {
-                let response_payload = ProtoPayloadResponse {
-                    response_id: header.response_id,
-                    service: payload.0,
-                    bytes: payload.1,
-                };
-
-                return ProtoMessage::Response(response_payload);
-            } else {
-                let tell_payload = ProtoPayloadTell {
-                    service: payload.0,
-                    bytes: payload.1,
-                };
-
-                return ProtoMessage::Tell(tell_payload);
+    let (service, bytes) = match body {
+        PeekedBody::Service(service, range) => (service, take_payload(msg, range)),
+        PeekedBody::Merged(service, bytes) => (service, bytes),
+        // error response to a request.
+        PeekedBody::ErrorRes(error_res) => {
+            if header.response_id == 0 {
+                return Err(conflicting_ids);
             }
-        })
-        .or_else(|| {
-            error!("extract_service_payload_from_rt_message_ERROR");
-            Some(ProtoMessage::Undefined)
-        })
-        .expect("extract_service_payload_from_rt_message_EXPECT_ERROR")
+
+            let service = realtime::Service::from_i32(error_res.service)
+                .unwrap_or(realtime::Service::Undefined);
+            let error_payload = ProtoPayloadError {
+                response_id: header.response_id,
+                service: realtime_service_from_proto_service(service),
+                code: StatusCode::from_i32(error_res.code).unwrap_or(StatusCode::Failed),
+                message: error_res.message,
+            };
+
+            return Ok(ProtoMessage::Error(error_payload));
+        }
+    };
+
+    if header.request_id != 0 {
+        let request_payload = ProtoPayloadRequest {
+            request_id: header.request_id,
+            service,
+            bytes,
+        };
+
+        Ok(ProtoMessage::Request(request_payload))
+    } else if header.response_id != 0 {
+        let response_payload = ProtoPayloadResponse {
+            response_id: header.response_id,
+            service,
+            bytes,
+        };
+
+        Ok(ProtoMessage::Response(response_payload))
+    } else {
+        let tell_payload = ProtoPayloadTell {
+            service,
+            delivery: header.delivery(),
+            bytes,
+        };
+
+        Ok(ProtoMessage::Tell(tell_payload))
+    }
 }
@@ -0,0 +1,140 @@
+//!
+//! Protocol buffer helpers for activity service used by both the server and the client-sdk.
+//!
+
+#![allow(dead_code)]
+
+use log::trace;
+use prost::Message;
+
+use super::{ProtoBytes, ProtoError};
+use crate::realtime::{
+    self,
+    activity::{message::Payload, Definition, Member, Mode, Status},
+};
+
+/// Create the request to define a new activity in the cospace.
+pub fn create_activity_define_request(definition: Definition) -> ProtoBytes {
+    trace!("create_activity_define_request ID: {}", definition.id);
+
+    let define = realtime::activity::Define {
+        definition: Some(definition),
+    };
+    encode_activity_payload(Payload::DefineReq(define))
+}
+
+/// Create the request to join an activity.
+pub fn create_activity_join_request(activity_id: String, mode: Mode) -> ProtoBytes {
+    trace!("create_activity_join_request ID: {}", activity_id);
+
+    let join = realtime::activity::JoinRequest {
+        activity_id,
+        mode: mode as i32,
+    };
+    encode_activity_payload(Payload::JoinReq(join))
+}
+
+/// Create the request to leave the current activity.
+pub fn create_activity_leave_request() -> ProtoBytes {
+    trace!("create_activity_leave_request");
+
+    encode_activity_payload(Payload::LeaveReq(realtime::activity::LeaveRequest {}))
+}
+
+/// Create the request for the activities of the cospace.
+pub fn create_activity_definitions_request() -> ProtoBytes {
+    trace!("create_activity_definitions_request");
+
+    encode_activity_payload(Payload::DefinitionsReq(
+        realtime::activity::DefinitionsRequest {},
+    ))
+}
+
+/// Create a message for the members of the current activity.
+pub fn create_activity_message(activity_id: String, payload: Vec<u8>) -> ProtoBytes {
+    trace!("create_activity_message ID: {}", activity_id);
+
+    let msg = realtime::activity::ActivityMessage {
+        activity_id,
+        client_id: 0,
+        payload,
+    };
+    encode_activity_payload(Payload::ActivityMsg(msg))
+}
+
+/// Create the activities of the cospace along with their members.
+pub fn create_activity_definitions(activities: Vec<realtime::activity::Activity>) -> ProtoBytes {
+    trace!(
+        "create_activity_definitions ACTIVITIES: {}",
+        activities.len()
+    );
+
+    let definitions = realtime::activity::Definitions { activities };
+    encode_activity_payload(Payload::Definitions(definitions))
+}
+
+/// Create the result of a define, join or leave request.
+pub fn create_activity_response(status: Status, activity_id: String) -> ProtoBytes {
+    trace!("create_activity_response STATUS: {:?}", status);
+
+    let response = realtime::activity::Response {
+        status: status as i32,
+        activity_id,
+    };
+    encode_activity_payload(Payload::Response(response))
+}
+
+/// Create the broadcast when a participant joins an activity.
+pub fn create_activity_joined(activity_id: String, member: Member) -> ProtoBytes {
+    trace!("create_activity_joined CLIENT: {}", member.client_id);
+
+    let joined = realtime::activity::Joined {
+        activity_id,
+        member: Some(member),
+    };
+    encode_activity_payload(Payload::Joined(joined))
+}
+
+/// Create the broadcast when a participant leaves an activity.
+pub fn create_activity_left(activity_id: String, client_id: u32) -> ProtoBytes {
+    trace!("create_activity_left CLIENT: {}", client_id);
+
+    let left = realtime::activity::Left {
+        activity_id,
+        client_id,
+    };
+    encode_activity_payload(Payload::Left(left))
+}
+
+/// Create the relay of an activity message to the members of the activity.
+pub fn create_activity_relayed(
+    activity_id: String,
+    client_id: u32,
+    payload: Vec<u8>,
+) -> ProtoBytes {
+    trace!("create_activity_relayed CLIENT: {}", client_id);
+
+    let msg = realtime::activity::ActivityMessage {
+        activity_id,
+        client_id,
+        payload,
+    };
+    encode_activity_payload(Payload::Relayed(msg))
+}
+
+/// Decode activity service messages.
+pub fn decode_activity_message_and_extract_payload(bytes: &[u8]) -> Result<Payload, ProtoError> {
+    trace!("decode_activity_message BYTES_LEN: {}", bytes.len());
+
+    realtime::activity::Message::decode(bytes)?
+        .payload
+        .ok_or(ProtoError::MissingBody)
+}
+
+fn encode_activity_payload(payload: Payload) -> ProtoBytes {
+    let activity_msg = realtime::activity::Message {
+        payload: Some(payload),
+    };
+
+    activity_msg.encode_to_vec()
+}
@@ -4,17 +4,35 @@
 
 #![allow(dead_code)]
 
-use log::{error, trace};
+use log::trace;
 use prost::Message;
 
-use super::ProtoBytes;
-use crate::realtime;
+use super::{ProtoBytes, ProtoError};
+use crate::realtime::{self, connection::Feature};
 
-/// Create the handshake request to be always sent as first message from client.
-pub fn create_ticket_handshake_request(ticket: String) -> ProtoBytes {
+/// Websocket subprotocols of the realtime protocol, in order of preference
+/// (newest first). The server selects one of the subprotocols offered by the
+/// client, so that older clients keep connecting to newer servers.
+pub const WS_PROTOCOLS: [&str; 1] = ["realtime-proto-v01"];
+
+/// Optional features implemented by this version, the other features are
+/// never negotiated even when both peers list them.
+pub const IMPLEMENTED_FEATURES: [Feature; 1] = [Feature::Batching];
+
+/// Create the handshake request to be always sent as first message from client,
+/// along with the version and the supported features of the client-sdk.
+pub fn create_ticket_handshake_request(
+    ticket: String,
+    sdk_version: String,
+    features: &[Feature],
+) -> ProtoBytes {
     trace!("create_ticket_handshake_request TICKET: {}", ticket);
 
-    let ticket_req = realtime::connection::TicketHandshakeRequest { ticket };
+    let ticket_req = realtime::connection::TicketHandshakeRequest {
+        ticket,
+        sdk_version,
+        features: features.iter().map(|f| *f as i32).collect(),
+    };
     let connection_payload = realtime::connection::message::Payload::HandshakeReq(ticket_req);
     let connection_msg = realtime::connection::Message {
         payload: Some(connection_payload),
@@ -24,11 +42,20 @@ pub fn create_ticket_handshake_request(ticket: String) -> ProtoBytes {
 }
 
 /// Create the handshake response to be always sent as first message from  
-/// server as response to client's first ticket handshake request message.
-pub fn create_ticket_handshake_response(success: bool) -> ProtoBytes {
+/// server as response to client's first ticket handshake request message,
+/// along with the selected subprotocol and the negotiated features.
+pub fn create_ticket_handshake_response(
+    success: bool,
+    protocol: String,
+    features: &[Feature],
+) -> ProtoBytes {
     trace!("create_ticket_handshake_response SUCCESS: {}", success);
 
-    let ticket_res = realtime::connection::TicketHandshakeResponse { success };
+    let ticket_res = realtime::connection::TicketHandshakeResponse {
+        success,
+        protocol,
+        features: features.iter().map(|f| *f as i32).collect(),
+    };
     let connection_payload = realtime::connection::message::Payload::HandshakeRes(ticket_res);
     let connection_msg = realtime::connection::Message {
         payload: Some(connection_payload),
@@ -37,14 +64,33 @@ pub fn create_ticket_handshake_response(success: bool) -> ProtoBytes {
     connection_msg.encode_to_vec()
 }
 
+/// Negotiate the features of a connection: the supported and implemented
+/// features also offered by the peer. Features unknown to this version are ignored.
+pub fn negotiate_features(offered: &[i32], supported: &[Feature]) -> Vec<Feature> {
+    supported
+        .iter()
+        .filter(|f| IMPLEMENTED_FEATURES.contains(f))
+        .filter(|f| offered.contains(&(**f as i32)))
+        .copied()
+        .collect()
+}
+
+/// Features of the handshake message, features unknown to this version are ignored.
+pub fn features_from_handshake(features: &[i32]) -> Vec<Feature> {
+    features
+        .iter()
+        .filter_map(|f| Feature::from_i32(*f))
+        .filter(|f| *f != Feature::Undefined)
+        .collect()
+}
+
 /// Decode connection service messages.
 pub fn decode_connection_message_and_extract_payload(
     bytes: ProtoBytes,
-) -> Option<realtime::connection::message::Payload> {
+) -> Result<realtime::connection::message::Payload, ProtoError> {
     trace!("decode_connection_message BYTES_LEN: {}", bytes.len());
 
-    realtime::connection::Message::decode(&bytes[..])
-        .map(|msg| msg.payload)
-        .map_err(|e| error!("decode_connection_message_and_extract_payload: {}", e))
-        .unwrap_or(None)
+    realtime::connection::Message::decode(&bytes[..])?
+        .payload
+        .ok_or(ProtoError::MissingBody)
 }
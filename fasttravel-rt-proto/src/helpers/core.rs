@@ -4,20 +4,104 @@
 
 #![allow(dead_code)]
 
-use log::{error, trace};
+use log::trace;
 use prost::Message;
+use std::time::SystemTime;
 
-use super::ProtoBytes;
-use crate::realtime;
+use super::{ProtoBytes, ProtoError};
+use crate::realtime::{
+    self,
+    core::{message::Payload, SubscriptionStatus},
+};
+
+/// Create the request for the current time of the server.
+pub fn create_core_server_time_request() -> ProtoBytes {
+    trace!("create_core_server_time_request");
+
+    encode_core_payload(Payload::ServerTimeReq(realtime::core::ServerTimeRequest {}))
+}
+
+/// Create the response with the current time of the server.
+pub fn create_core_server_time_response(server_time: SystemTime) -> ProtoBytes {
+    trace!("create_core_server_time_response");
+
+    let response = realtime::core::ServerTimeResponse {
+        server_time: Some(server_time.into()),
+    };
+    encode_core_payload(Payload::ServerTimeRes(response))
+}
+
+/// Create the request to subscribe to a named topic of the cospace.
+pub fn create_core_subscribe_request(topic: String) -> ProtoBytes {
+    trace!("create_core_subscribe_request TOPIC: {}", topic);
+
+    encode_core_payload(Payload::SubscribeReq(realtime::core::SubscribeRequest {
+        topic,
+    }))
+}
+
+/// Create the request to unsubscribe from a named topic of the cospace.
+pub fn create_core_unsubscribe_request(topic: String) -> ProtoBytes {
+    trace!("create_core_unsubscribe_request TOPIC: {}", topic);
+
+    encode_core_payload(Payload::UnsubscribeReq(
+        realtime::core::UnsubscribeRequest { topic },
+    ))
+}
+
+/// Create the result of a subscribe or unsubscribe request.
+pub fn create_core_subscription_response(
+    status: SubscriptionStatus,
+    topic: String,
+    subscribed: bool,
+) -> ProtoBytes {
+    trace!("create_core_subscription_response STATUS: {:?}", status);
+
+    let response = realtime::core::SubscriptionResponse {
+        status: status as i32,
+        topic,
+        subscribed,
+    };
+    encode_core_payload(Payload::SubscriptionRes(response))
+}
+
+/// Create a message to publish to the subscribers of a named topic.
+pub fn create_core_topic_message(topic: String, payload: Vec<u8>) -> ProtoBytes {
+    trace!("create_core_topic_message TOPIC: {}", topic);
+
+    let msg = realtime::core::TopicMessage {
+        topic,
+        client_id: 0,
+        payload,
+    };
+    encode_core_payload(Payload::Publish(msg))
+}
+
+/// Create the relay of a topic message to the subscribers of the topic.
+pub fn create_core_topic_relayed(topic: String, client_id: u32, payload: Vec<u8>) -> ProtoBytes {
+    trace!("create_core_topic_relayed CLIENT: {}", client_id);
+
+    let msg = realtime::core::TopicMessage {
+        topic,
+        client_id,
+        payload,
+    };
+    encode_core_payload(Payload::Relayed(msg))
+}
 
 /// Decode core service messages.
-pub fn decode_core_message_and_extract_payload(
-    bytes: ProtoBytes,
-) -> Option<realtime::core::message::Payload> {
+pub fn decode_core_message_and_extract_payload(bytes: ProtoBytes) -> Result<Payload, ProtoError> {
     trace!("decode_core_message BYTES_LEN: {}", bytes.len());
 
-    realtime::core::Message::decode(&bytes[..])
-        .map(|msg| msg.payload)
-        .map_err(|e| error!("decode_core_message_and_extract_payload: {}", e))
-        .unwrap_or(None)
+    realtime::core::Message::decode(&bytes[..])?
+        .payload
+        .ok_or(ProtoError::MissingBody)
+}
+
+fn encode_core_payload(payload: Payload) -> ProtoBytes {
+    let core_msg = realtime::core::Message {
+        payload: Some(payload),
+    };
+
+    core_msg.encode_to_vec()
 }
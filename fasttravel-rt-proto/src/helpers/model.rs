@@ -0,0 +1,116 @@
+//!
+//! Protocol buffer helpers for model service used by both the server and the client-sdk.
+//!
+
+#![allow(dead_code)]
+
+use log::trace;
+use prost::Message;
+
+use super::{ProtoBytes, ProtoError};
+use crate::realtime::{
+    self,
+    model::{message::Payload, transaction_result, CrdtObject, CrdtOp, Mode, Operation},
+};
+
+/// Create a transaction request to be sent to the model authority.
+pub fn create_model_transaction(base_version: u64, ops: Vec<Operation>) -> ProtoBytes {
+    trace!("create_model_transaction OPS: {}", ops.len());
+
+    let transaction = realtime::model::Transaction { base_version, ops };
+    encode_model_payload(Payload::Transaction(transaction))
+}
+
+/// Create the request for the current snapshot of the model.
+/// The mode selects the model mode if the model of the cospace is not opened yet.
+pub fn create_model_snapshot_request(mode: Mode) -> ProtoBytes {
+    trace!("create_model_snapshot_request MODE: {:?}", mode);
+
+    let request = realtime::model::SnapshotRequest { mode: mode as i32 };
+    encode_model_payload(Payload::SnapshotReq(request))
+}
+
+/// Create the update of the local CRDT operations of a replica.
+pub fn create_model_crdt_update(ops: Vec<CrdtOp>) -> ProtoBytes {
+    trace!("create_model_crdt_update OPS: {}", ops.len());
+
+    let update = realtime::model::CrdtUpdate { client_id: 0, ops };
+    encode_model_payload(Payload::CrdtUpdate(update))
+}
+
+/// Create the transaction response for a committed transaction.
+pub fn create_model_transaction_commit(version: u64) -> ProtoBytes {
+    trace!("create_model_transaction_commit VERSION: {}", version);
+
+    let commit = realtime::model::Commit { version };
+    let result = realtime::model::TransactionResult {
+        result: Some(transaction_result::Result::Commit(commit)),
+    };
+    encode_model_payload(Payload::TransactionRes(result))
+}
+
+/// Create the transaction response for a rejected transaction.
+pub fn create_model_transaction_reject(reject: realtime::model::Reject) -> ProtoBytes {
+    trace!(
+        "create_model_transaction_reject OP_INDEX: {}",
+        reject.op_index
+    );
+
+    let result = realtime::model::TransactionResult {
+        result: Some(transaction_result::Result::Reject(reject)),
+    };
+    encode_model_payload(Payload::TransactionRes(result))
+}
+
+/// Create the broadcast of the operations committed by the model authority.
+pub fn create_model_committed(version: u64, client_id: u32, ops: Vec<Operation>) -> ProtoBytes {
+    trace!("create_model_committed VERSION: {}", version);
+
+    let committed = realtime::model::Committed {
+        version,
+        client_id,
+        ops,
+    };
+    encode_model_payload(Payload::Committed(committed))
+}
+
+/// Create the snapshot of the model.
+pub fn create_model_snapshot(version: u64, elements: Vec<realtime::model::Element>) -> ProtoBytes {
+    trace!("create_model_snapshot ELEMENTS: {}", elements.len());
+
+    let snapshot = realtime::model::Snapshot { version, elements };
+    encode_model_payload(Payload::Snapshot(snapshot))
+}
+
+/// Create the relay of the CRDT operations merged by the service.
+pub fn create_model_crdt_relay(client_id: u32, ops: Vec<CrdtOp>) -> ProtoBytes {
+    trace!("create_model_crdt_relay OPS: {}", ops.len());
+
+    let update = realtime::model::CrdtUpdate { client_id, ops };
+    encode_model_payload(Payload::CrdtRelay(update))
+}
+
+/// Create the snapshot of the CRDT objects for the replica.
+pub fn create_model_crdt_snapshot(replica: u32, objects: Vec<CrdtObject>) -> ProtoBytes {
+    trace!("create_model_crdt_snapshot OBJECTS: {}", objects.len());
+
+    let snapshot = realtime::model::CrdtSnapshot { replica, objects };
+    encode_model_payload(Payload::CrdtSnapshot(snapshot))
+}
+
+/// Decode model service messages.
+pub fn decode_model_message_and_extract_payload(bytes: &[u8]) -> Result<Payload, ProtoError> {
+    trace!("decode_model_message BYTES_LEN: {}", bytes.len());
+
+    realtime::model::Message::decode(bytes)?
+        .payload
+        .ok_or(ProtoError::MissingBody)
+}
+
+fn encode_model_payload(payload: Payload) -> ProtoBytes {
+    let model_msg = realtime::model::Message {
+        payload: Some(payload),
+    };
+
+    model_msg.encode_to_vec()
+}
@@ -0,0 +1,78 @@
+//!
+//! Protocol buffer helpers for presence service used by both the server and the client-sdk.
+//!
+
+#![allow(dead_code)]
+
+use log::trace;
+use prost::Message;
+
+use super::{ProtoBytes, ProtoError};
+use crate::realtime::{self, presence::message::Payload};
+
+/// Create the presence update replacing the presence state of the sender.
+pub fn create_presence_update(state: realtime::presence::State) -> ProtoBytes {
+    trace!("create_presence_update");
+
+    let update = realtime::presence::Update { state: Some(state) };
+    encode_presence_payload(Payload::Update(update))
+}
+
+/// Create the request for the current roster of the cospace.
+pub fn create_presence_snapshot_request() -> ProtoBytes {
+    trace!("create_presence_snapshot_request");
+
+    encode_presence_payload(Payload::SnapshotReq(realtime::presence::SnapshotRequest {}))
+}
+
+/// Create the roster snapshot sent to a participant.
+pub fn create_presence_snapshot(participants: Vec<realtime::presence::Participant>) -> ProtoBytes {
+    trace!("create_presence_snapshot PARTICIPANTS: {}", participants.len());
+
+    let snapshot = realtime::presence::Snapshot { participants };
+    encode_presence_payload(Payload::Snapshot(snapshot))
+}
+
+/// Create the delta broadcast when a participant joins the cospace.
+pub fn create_presence_joined(participant: realtime::presence::Participant) -> ProtoBytes {
+    trace!("create_presence_joined CLIENT: {}", participant.client_id);
+
+    let joined = realtime::presence::Joined {
+        participant: Some(participant),
+    };
+    encode_presence_payload(Payload::Joined(joined))
+}
+
+/// Create the delta broadcast when a participant leaves the cospace.
+pub fn create_presence_left(client_id: u32) -> ProtoBytes {
+    trace!("create_presence_left CLIENT: {}", client_id);
+
+    encode_presence_payload(Payload::Left(realtime::presence::Left { client_id }))
+}
+
+/// Create the delta broadcast when a participant updates its presence state.
+pub fn create_presence_updated(participant: realtime::presence::Participant) -> ProtoBytes {
+    trace!("create_presence_updated CLIENT: {}", participant.client_id);
+
+    let updated = realtime::presence::Updated {
+        participant: Some(participant),
+    };
+    encode_presence_payload(Payload::Updated(updated))
+}
+
+/// Decode presence service messages.
+pub fn decode_presence_message_and_extract_payload(bytes: &[u8]) -> Result<Payload, ProtoError> {
+    trace!("decode_presence_message BYTES_LEN: {}", bytes.len());
+
+    realtime::presence::Message::decode(bytes)?
+        .payload
+        .ok_or(ProtoError::MissingBody)
+}
+
+fn encode_presence_payload(payload: Payload) -> ProtoBytes {
+    let presence_msg = realtime::presence::Message {
+        payload: Some(payload),
+    };
+
+    presence_msg.encode_to_vec()
+}
@@ -0,0 +1,111 @@
+//!
+//! Negotiation of the optional features of the realtime protocol on the
+//! ticket handshake.
+//!
+
+use fasttravel_rt_proto::{
+    helpers::connection::{
+        create_ticket_handshake_request, create_ticket_handshake_response,
+        decode_connection_message_and_extract_payload, features_from_handshake, negotiate_features,
+        IMPLEMENTED_FEATURES,
+    },
+    realtime::connection::{message::Payload, Feature},
+};
+
+const ALL_FEATURES: [Feature; 3] = [Feature::Compression, Feature::Batching, Feature::JsonCodec];
+
+fn offered(features: &[Feature]) -> Vec<i32> {
+    features.iter().map(|f| *f as i32).collect()
+}
+
+#[test]
+fn negotiate_the_features_supported_by_both() {
+    let features = negotiate_features(&offered(&ALL_FEATURES), &[Feature::Batching]);
+    assert_eq!(features, vec![Feature::Batching]);
+
+    let features = negotiate_features(&offered(&[Feature::Batching]), &ALL_FEATURES);
+    assert_eq!(features, vec![Feature::Batching]);
+
+    let features = negotiate_features(&offered(&ALL_FEATURES), &ALL_FEATURES);
+    assert_eq!(features, vec![Feature::Batching]);
+}
+
+#[test]
+fn negotiate_never_returns_the_unimplemented_features() {
+    // every combination of the offered and the supported features.
+    let combinations: Vec<Vec<Feature>> = (0..1 << ALL_FEATURES.len())
+        .map(|mask| {
+            ALL_FEATURES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, f)| *f)
+                .collect()
+        })
+        .collect();
+
+    for offered_features in &combinations {
+        for supported in &combinations {
+            let features = negotiate_features(&offered(offered_features), supported);
+            assert!(features.iter().all(|f| IMPLEMENTED_FEATURES.contains(f)));
+        }
+    }
+
+    let features = negotiate_features(
+        &offered(&[Feature::JsonCodec, Feature::Compression]),
+        &ALL_FEATURES,
+    );
+    assert!(features.is_empty());
+}
+
+#[test]
+fn negotiate_nothing_without_common_features() {
+    assert!(negotiate_features(&[], &ALL_FEATURES).is_empty());
+    assert!(negotiate_features(&offered(&ALL_FEATURES), &[]).is_empty());
+    assert!(negotiate_features(&offered(&[Feature::Compression]), &[Feature::Batching]).is_empty());
+}
+
+#[test]
+fn negotiate_ignores_the_unknown_features() {
+    // features of a newer client-sdk.
+    let features = negotiate_features(&[Feature::Batching as i32, 42, -1], &ALL_FEATURES);
+    assert_eq!(features, vec![Feature::Batching]);
+}
+
+#[test]
+fn features_from_handshake_ignore_the_unknown_and_undefined() {
+    let features = features_from_handshake(&[
+        Feature::Undefined as i32,
+        Feature::Batching as i32,
+        42,
+        Feature::JsonCodec as i32,
+    ]);
+    assert_eq!(features, vec![Feature::Batching, Feature::JsonCodec]);
+
+    assert!(features_from_handshake(&[]).is_empty());
+}
+
+#[test]
+fn handshake_round_trip() {
+    let req =
+        create_ticket_handshake_request("ticket".to_string(), "0.1.0".to_string(), &ALL_FEATURES);
+    let req = match decode_connection_message_and_extract_payload(req) {
+        Ok(Payload::HandshakeReq(req)) => req,
+        _ => panic!("unexpected_payload"),
+    };
+    assert_eq!(req.sdk_version, "0.1.0");
+
+    let features = negotiate_features(&req.features, &[Feature::Batching]);
+    let res = create_ticket_handshake_response(true, "realtime-proto-v01".to_string(), &features);
+    let res = match decode_connection_message_and_extract_payload(res) {
+        Ok(Payload::HandshakeRes(res)) => res,
+        _ => panic!("unexpected_payload"),
+    };
+
+    assert!(res.success);
+    assert_eq!(res.protocol, "realtime-proto-v01");
+    assert_eq!(
+        features_from_handshake(&res.features),
+        vec![Feature::Batching]
+    );
+}
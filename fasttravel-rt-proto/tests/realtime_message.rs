@@ -0,0 +1,217 @@
+//!
+//! Round trips of the header-peeking decoder, the service payload sliced from
+//! the realtime message must decode to the body of the full decode.
+//!
+
+use prost::{
+    encoding::{self, WireType},
+    Message,
+};
+
+use fasttravel_rt_proto::{
+    helpers::{self, core as proto_core, model as proto_model, ProtoBytes, ProtoMessage},
+    realtime::{
+        self,
+        model::{operation, value, Operation, SetProperty, Value},
+        realtime_message::{Body, Header},
+    },
+    RealtimeService,
+};
+
+const TAG_HEADER: u32 = 1;
+const TAG_CORE_MSG: u32 = 3;
+const TAG_MODEL_MSG: u32 = 6;
+
+/// Length-delimited field of the realtime message.
+fn field(tag: u32, bytes: &[u8]) -> ProtoBytes {
+    let mut field = Vec::new();
+    encoding::encode_key(tag, WireType::LengthDelimited, &mut field);
+    encoding::encode_varint(bytes.len() as u64, &mut field);
+    field.extend_from_slice(bytes);
+    field
+}
+
+fn header(request_id: u32, response_id: u32) -> ProtoBytes {
+    let header = Header {
+        request_id,
+        response_id,
+        ..Default::default()
+    };
+    field(TAG_HEADER, &header.encode_to_vec())
+}
+
+/// Model commit setting the property of the element.
+fn model_payload(element: &str, version: u64) -> ProtoBytes {
+    let op = Operation {
+        op: Some(operation::Op::SetProperty(SetProperty {
+            id: element.to_string(),
+            key: "position".to_string(),
+            value: Some(Value {
+                kind: Some(value::Kind::StringValue(format!("{},0,0", version))),
+            }),
+        })),
+    };
+
+    proto_model::create_model_committed(version, 7, vec![op])
+}
+
+fn core_payload() -> ProtoBytes {
+    proto_core::create_core_topic_relayed("model".to_string(), 7, vec![1, 2, 3])
+}
+
+/// Process the message and compare the ids, the service and the payload
+/// with the full decode of the message.
+fn assert_round_trip(msg: ProtoBytes) {
+    let full = realtime::RealtimeMessage::decode(&msg[..]).expect("full_decode_failed");
+    let full_header = full.header.expect("full_decode_missing_header");
+
+    let (request_id, response_id, service, bytes) = match helpers::process_realtime_message(msg) {
+        Ok(ProtoMessage::Tell(tell)) => (0, 0, tell.service, tell.bytes),
+        Ok(ProtoMessage::Request(request)) => {
+            (request.request_id, 0, request.service, request.bytes)
+        }
+        Ok(ProtoMessage::Response(response)) => {
+            (0, response.response_id, response.service, response.bytes)
+        }
+        Ok(ProtoMessage::Error(_)) => panic!("unexpected_error_response"),
+        Err(e) => panic!("process_failed: {}", e),
+    };
+
+    assert_eq!(request_id, full_header.request_id);
+    assert_eq!(response_id, full_header.response_id);
+
+    match full.body.expect("full_decode_missing_body") {
+        Body::CoreMsg(core_msg) => {
+            assert_eq!(service, RealtimeService::Core);
+            assert_eq!(realtime::core::Message::decode(&bytes[..]), Ok(core_msg));
+        }
+        Body::ModelMsg(model_msg) => {
+            assert_eq!(service, RealtimeService::Model);
+            assert_eq!(realtime::model::Message::decode(&bytes[..]), Ok(model_msg));
+        }
+        _ => panic!("unexpected_body"),
+    }
+}
+
+#[test]
+fn header_before_body() {
+    let msg = [header(3, 0), field(TAG_MODEL_MSG, &model_payload("a", 1))].concat();
+    assert_round_trip(msg);
+}
+
+#[test]
+fn header_after_body() {
+    let msg = [field(TAG_MODEL_MSG, &model_payload("a", 1)), header(0, 5)].concat();
+    assert_round_trip(msg);
+}
+
+#[test]
+fn repeated_header_fields_merge() {
+    let msg = [
+        header(3, 0),
+        field(TAG_MODEL_MSG, &model_payload("a", 1)),
+        header(0, 0),
+    ]
+    .concat();
+    assert_round_trip(msg);
+}
+
+#[test]
+fn duplicate_body_fields_of_the_same_service_merge() {
+    let msg = [
+        header(0, 0),
+        field(TAG_MODEL_MSG, &model_payload("a", 1)),
+        field(TAG_MODEL_MSG, &model_payload("b", 2)),
+        field(TAG_MODEL_MSG, &model_payload("c", 3)),
+    ]
+    .concat();
+    assert_round_trip(msg);
+}
+
+#[test]
+fn duplicate_body_fields_of_other_services_last_wins() {
+    let msg = [
+        header(0, 0),
+        field(TAG_MODEL_MSG, &model_payload("a", 1)),
+        field(TAG_CORE_MSG, &core_payload()),
+    ]
+    .concat();
+    assert_round_trip(msg.clone());
+
+    let msg = [
+        field(TAG_CORE_MSG, &core_payload()),
+        field(TAG_MODEL_MSG, &model_payload("a", 1)),
+        field(TAG_CORE_MSG, &core_payload()),
+        header(0, 0),
+    ]
+    .concat();
+    assert_round_trip(msg);
+}
+
+#[test]
+fn unknown_fields_are_skipped() {
+    let msg = [
+        header(3, 0),
+        field(15, b"unknown"),
+        field(TAG_MODEL_MSG, &model_payload("a", 1)),
+    ]
+    .concat();
+    assert_round_trip(msg);
+}
+
+#[test]
+fn truncated_length_fails() {
+    let msg = [header(0, 0), field(TAG_MODEL_MSG, &model_payload("a", 1))].concat();
+
+    // the body is shorter than its length, or the length itself is cut.
+    let header_len = header(0, 0).len();
+    for len in [msg.len() - 1, header_len + 2, header_len + 1] {
+        let truncated = msg[..len].to_vec();
+
+        assert!(realtime::RealtimeMessage::decode(&truncated[..]).is_err());
+        assert!(helpers::process_realtime_message(truncated).is_err());
+    }
+}
+
+#[test]
+fn encoded_payload_round_trip() {
+    let payload = model_payload("a", 1);
+    let msg = helpers::create_request_message_from_service_payload(
+        9,
+        &RealtimeService::Model,
+        payload.clone(),
+    )
+    .unwrap();
+    assert_round_trip(msg.clone());
+
+    match helpers::process_realtime_message(msg) {
+        Ok(ProtoMessage::Request(request)) => assert_eq!(request.bytes, payload),
+        _ => panic!("unexpected_message"),
+    }
+}
+
+#[test]
+fn batch_round_trip() {
+    let messages = vec![
+        [header(3, 0), field(TAG_MODEL_MSG, &model_payload("a", 1))].concat(),
+        [field(TAG_CORE_MSG, &core_payload()), header(0, 0)].concat(),
+        [header(0, 5), field(TAG_MODEL_MSG, &model_payload("b", 2))].concat(),
+    ];
+
+    let batch = helpers::create_realtime_batch(&messages);
+    assert!(helpers::is_realtime_batch(&batch));
+    assert!(messages.iter().all(|msg| !helpers::is_realtime_batch(msg)));
+
+    let split = helpers::split_realtime_batch(&batch).unwrap();
+    assert_eq!(split, messages);
+    split.into_iter().for_each(assert_round_trip);
+}
+
+#[test]
+fn truncated_batch_fails() {
+    let messages = vec![[header(0, 0), field(TAG_MODEL_MSG, &model_payload("a", 1))].concat()];
+    let batch = helpers::create_realtime_batch(&messages);
+
+    assert!(helpers::split_realtime_batch(&batch[..batch.len() - 1]).is_err());
+    assert!(helpers::split_realtime_batch(&messages[0]).is_err());
+}
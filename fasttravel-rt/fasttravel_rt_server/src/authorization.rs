@@ -1,11 +1,91 @@
-use jsonwebtoken::{decode, Algorithm, Validation};
+use dashmap::{mapref::entry::Entry, DashMap};
+use jsonwebtoken::Validation;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashSet;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use uuid::Uuid;
+
+use fasttravel_rt_services::{ClientId, ClientIdentity, ClientRole};
 
 use crate::axum;
 use crate::*;
 
+pub use jsonwebtoken::Algorithm;
+
+/// The subject and the accepted audiences of a kind of token.
+#[derive(Clone, Debug)]
+pub struct ExpectedClaims {
+    pub subject: String,
+    pub audiences: Vec<String>,
+}
+
+impl ExpectedClaims {
+    fn new(subject: &str, audience: &str) -> Self {
+        Self {
+            subject: subject.to_string(),
+            audiences: vec![audience.to_string()],
+        }
+    }
+}
+
+/// Validation of the host requests and the tickets issued by the session_lambda.
+#[derive(Clone, Debug)]
+pub struct ClaimsValidationConfig {
+    /// Accepted signing algorithms, e.g. ES256, ES384, RS256 or EdDSA.
+    /// The key set could mix the kinds of keys, e.g. EC and RSA keys.
+    pub algorithms: Vec<Algorithm>,
+
+    /// Expected issuer (iss) of the tokens, not checked if None.
+    pub issuer: Option<String>,
+
+    /// Leeway in seconds for the expiration of the tokens (clock skew).
+    pub leeway: u64,
+
+    /// Claims of the host requests (HostWorkspaceClaims).
+    pub host: ExpectedClaims,
+
+    /// Claims of the status tickets (TicketClaimsStatus).
+    pub status: ExpectedClaims,
+
+    /// Claims of the connect query tickets (TicketClaimsQuery).
+    pub query: ExpectedClaims,
+
+    /// Claims of the handshake message tickets (TicketClaimsMessage).
+    pub message: ExpectedClaims,
+}
+
+// default claims validation config, matches the tokens of the mockers.
+impl Default for ClaimsValidationConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: vec![Algorithm::ES256],
+            issuer: None,
+            leeway: 60,
+            host: ExpectedClaims::new("certificate", "realtime"),
+            status: ExpectedClaims::new("sdk", "status"),
+            query: ExpectedClaims::new("sdk", "query"),
+            message: ExpectedClaims::new("sdk", "message"),
+        }
+    }
+}
+
+impl ClaimsValidationConfig {
+    fn validation(&self, expected: &ExpectedClaims) -> Validation {
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.algorithms = self.algorithms.clone();
+        validation.leeway = self.leeway;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        validation.sub = Some(expected.subject.clone());
+        validation.set_audience(&expected.audiences);
+        validation
+    }
+}
+
 /// the claim used during {realtime-server-url}/realtime/host/ request
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HostWorkspaceClaims {
@@ -24,11 +104,8 @@ impl HostWorkspaceClaims {
         }
     }
 
-    fn validation() -> Validation {
-        let mut validation = Validation::new(Algorithm::ES256);
-        validation.sub = Some("certificate".to_string());
-        validation.aud = Some(HashSet::from(["realtime".to_string()]));
-        validation
+    fn validation(config: &ClaimsValidationConfig) -> Validation {
+        config.validation(&config.host)
     }
 }
 
@@ -48,10 +125,11 @@ impl axum::FromRequestParts<RealtimeServerState> for HostWorkspaceClaims {
             .map_err(|_| AuthError::InvalidToken)?;
 
         // validate and decode the token
-        let validation = HostWorkspaceClaims::validation();
+        let validation = HostWorkspaceClaims::validation(&state.claims_validation);
 
-        let decoding = &state.public_keys.session_request_decoding;
-        let token_data = decode::<HostWorkspaceClaims>(bearer.token(), decoding, &validation)
+        let token_data = state
+            .public_keys
+            .decode::<HostWorkspaceClaims>(bearer.token(), &validation)
             .map_err(|_| AuthError::InvalidToken)?;
 
         Ok(token_data.claims)
@@ -59,28 +137,35 @@ impl axum::FromRequestParts<RealtimeServerState> for HostWorkspaceClaims {
 }
 
 /// claim for the status request: {realtime-server-url}/realtime/status/:cospace_id
+/// The status ticket is not single-use as the status is polled till the
+/// cospace is hosted.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TicketClaimsStatus {
     sub: String,
     aud: String,
     exp: usize,
+    jti: String,
+    cospace: Uuid, // the only cospace the ticket is valid for
 }
 impl TicketClaimsStatus {
     // easy helper for the mockers.
     // [todo] expose this only for "mocker" feature.
-    pub fn expected(exp: usize) -> Self {
+    pub fn expected(exp: usize, jti: String, cospace: Uuid) -> Self {
         Self {
             sub: "sdk".to_owned(),
             aud: "status".to_owned(),
             exp,
+            jti,
+            cospace,
         }
     }
 
-    fn validation() -> Validation {
-        let mut validation = Validation::new(Algorithm::ES256);
-        validation.sub = Some("sdk".to_string());
-        validation.aud = Some(HashSet::from(["status".to_string()]));
-        validation
+    pub(crate) fn cospace(&self) -> &Uuid {
+        &self.cospace
+    }
+
+    fn validation(config: &ClaimsValidationConfig) -> Validation {
+        config.validation(&config.status)
     }
 }
 
@@ -93,23 +178,52 @@ pub struct TicketClaimsQuery {
     sub: String,
     aud: String,
     exp: usize,
+    jti: String,   // id to check that ticket is used only once
+    cospace: Uuid, // the only cospace the ticket is valid for
+    user_id: String,
+    display_name: String,
+    // the tickets issued without a role are spectator tickets.
+    #[serde(default)]
+    role: ClientRole,
 }
 impl TicketClaimsQuery {
     // easy helper for the mockers.
     // [todo] expose this only for "mocker" feature.
-    pub fn expected(exp: usize) -> Self {
+    pub fn expected(exp: usize, jti: String, cospace: Uuid, identity: ClientIdentity) -> Self {
         Self {
             sub: "sdk".to_owned(),
             aud: "query".to_owned(),
             exp,
+            jti,
+            cospace,
+            user_id: identity.user_id,
+            display_name: identity.display_name,
+            role: identity.role,
         }
     }
 
-    pub(super) fn validation() -> Validation {
-        let mut validation = Validation::new(Algorithm::ES256);
-        validation.sub = Some("sdk".to_string());
-        validation.aud = Some(HashSet::from(["query".to_string()]));
-        validation
+    pub(crate) fn cospace(&self) -> &Uuid {
+        &self.cospace
+    }
+
+    pub(crate) fn identity(&self) -> ClientIdentity {
+        ClientIdentity {
+            user_id: self.user_id.clone(),
+            display_name: self.display_name.clone(),
+            role: self.role,
+        }
+    }
+
+    pub(crate) fn jti(&self) -> &str {
+        &self.jti
+    }
+
+    pub(crate) fn exp(&self) -> usize {
+        self.exp
+    }
+
+    pub(crate) fn validation(config: &ClaimsValidationConfig) -> Validation {
+        config.validation(&config.query)
     }
 }
 
@@ -119,24 +233,49 @@ pub struct TicketClaimsMessage {
     sub: String,
     aud: String,
     exp: usize,
-    // jti: String, // id to check that ticket is used only once
+    jti: String,   // id to check that ticket is used only once
+    cospace: Uuid, // the only cospace the ticket is valid for
+    user_id: String,
+    display_name: String,
+    // the tickets issued without a role are spectator tickets.
+    #[serde(default)]
+    role: ClientRole,
 }
 impl TicketClaimsMessage {
     // easy helper for the mockers.
     // [todo] expose this only for "mocker" feature.
-    pub fn expected(exp: usize) -> Self {
+    pub fn expected(exp: usize, jti: String, cospace: Uuid, identity: ClientIdentity) -> Self {
         Self {
             sub: "sdk".to_owned(),
             aud: "message".to_owned(),
             exp,
+            jti,
+            cospace,
+            user_id: identity.user_id,
+            display_name: identity.display_name,
+            role: identity.role,
         }
     }
 
-    pub(crate) fn validation() -> Validation {
-        let mut validation = Validation::new(Algorithm::ES256);
-        validation.sub = Some("sdk".to_string());
-        validation.aud = Some(HashSet::from(["message".to_string()]));
-        validation
+    /// Check that the ticket was issued to the client: for the same
+    /// cospace and user as the connect ticket.
+    pub(crate) fn is_issued_to(&self, client: &ClientId) -> bool {
+        self.cospace == client.cospace.uuid
+            && self.user_id == client.identity.user_id
+            && self.display_name == client.identity.display_name
+            && self.role == client.identity.role
+    }
+
+    pub(crate) fn jti(&self) -> &str {
+        &self.jti
+    }
+
+    pub(crate) fn exp(&self) -> usize {
+        self.exp
+    }
+
+    pub(crate) fn validation(config: &ClaimsValidationConfig) -> Validation {
+        config.validation(&config.message)
     }
 }
 
@@ -156,16 +295,76 @@ impl axum::FromRequestParts<RealtimeServerState> for TicketClaimsStatus {
             .map_err(|_| AuthError::InvalidToken)?;
 
         // validate and decode the token
-        let validation = TicketClaimsStatus::validation();
+        let validation = TicketClaimsStatus::validation(&state.claims_validation);
 
-        let decoding = &state.public_keys.session_request_decoding;
-        let token_data = decode::<TicketClaimsStatus>(bearer.token(), decoding, &validation)
+        let token_data = state
+            .public_keys
+            .decode::<TicketClaimsStatus>(bearer.token(), &validation)
             .map_err(|_| AuthError::InvalidToken)?;
 
         Ok(token_data.claims)
     }
 }
 
+/// Interval in seconds between successive purges of the expired ticket ids.
+const TICKET_PURGE_INTERVAL: usize = 60;
+
+/// Store of the used ticket ids (jti) to reject the replay of single-use tickets.
+/// An id is remembered till its ticket expires (including the validation leeway),
+/// after that the ticket itself is rejected.
+#[derive(Clone)]
+pub(crate) struct TicketReplayStore {
+    used: Arc<DashMap<String, usize>>,
+    next_purge: Arc<AtomicUsize>,
+    leeway: usize,
+}
+
+impl TicketReplayStore {
+    pub(crate) fn new(leeway: u64) -> Self {
+        Self {
+            used: Arc::new(DashMap::new()),
+            next_purge: Arc::new(AtomicUsize::new(0)),
+            leeway: leeway as usize,
+        }
+    }
+
+    /// Mark the ticket as used, false if the ticket was already used.
+    pub(crate) fn consume(&self, jti: &str, exp: usize) -> bool {
+        if jti.is_empty() {
+            return false;
+        }
+
+        let now = chrono::offset::Utc::now().timestamp() as usize;
+        self.purge_expired(now);
+
+        let valid_till = exp.saturating_add(self.leeway);
+        match self.used.entry(jti.to_string()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(valid_till);
+                true
+            }
+        }
+    }
+
+    fn purge_expired(&self, now: usize) {
+        let next_purge = self.next_purge.load(Ordering::Relaxed);
+        if now < next_purge {
+            return;
+        }
+
+        // only one caller purges.
+        let new_next = now + TICKET_PURGE_INTERVAL;
+        if self
+            .next_purge
+            .compare_exchange(next_purge, new_next, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.used.retain(|_, valid_till| *valid_till >= now);
+        }
+    }
+}
+
 /// Realtime server authetication errors.
 #[derive(Debug)]
 pub enum AuthError {
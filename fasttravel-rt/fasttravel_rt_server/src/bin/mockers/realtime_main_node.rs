@@ -30,18 +30,38 @@
 //!      "realtime-proto-v01");
 //!
 
-// Mocker ticket-public-key for the private-key with which the tickets were
-// signed by session_lambda. The mocker private-key is in session_lambda.rs
-// In a production setup get key from the JSON Web Key Sets (jwks.json) file.
-const TICKET_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
-MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEL4VQxcr0feOyBeq9mAT0vz/qoBw0
-epMsoJheEh4cspu9ms7oFL42rGK41t0Hmx1j4NJbx3lO5oziqG+/rg8SMw==
------END PUBLIC KEY-----";
+use fasttravel_rt_server::{JwksSource, PublicDecodingKeys};
+
+// Mocker JSON Web Key Set with the ticket-public-key for the private-key with
+// which the tickets were signed by session_lambda. The mocker private-key is
+// in session_lambda.rs, the key id (kid) must match the kid of the tickets.
+// In a production setup load the key set published by the session_lambda,
+// set the environment variable REALTIME_JWKS to a jwks.json path or URL.
+const TICKET_JWKS: &str = r#"{
+    "keys": [{
+        "kty": "EC",
+        "crv": "P-256",
+        "kid": "mocker-ticket-key-01",
+        "use": "sig",
+        "alg": "ES256",
+        "x": "L4VQxcr0feOyBeq9mAT0vz_qoBw0epMsoJheEh4csps",
+        "y": "vZrO6BS-NqxiuNbdB5sdY-DSW8d5TuaM4qhvv64PEjM"
+    }]
+}"#;
 
 // we need tokio runtime for axum.
 #[tokio::main]
 async fn main() {
-    fasttravel_rt_server::initialize_and_run_main_node(TICKET_PUBLIC_KEY)
+    let public_keys = match std::env::var("REALTIME_JWKS") {
+        Ok(jwks) if jwks.starts_with("http://") || jwks.starts_with("https://") => {
+            PublicDecodingKeys::from_jwks(JwksSource::Url(jwks)).await
+        }
+        Ok(jwks) => PublicDecodingKeys::from_jwks(JwksSource::File(jwks.into())).await,
+        Err(_) => PublicDecodingKeys::from_jwks_str(TICKET_JWKS),
+    }
+    .expect("public_keys_load_failed");
+
+    fasttravel_rt_server::initialize_and_run_main_node(public_keys)
         .await
         .ok();
 }
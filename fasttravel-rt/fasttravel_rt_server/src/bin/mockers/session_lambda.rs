@@ -36,11 +36,13 @@ use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
+use uuid::Uuid;
 
 use fasttravel_rt_server::{
     AuthError, HostSessionRequest, HostWorkspaceClaims, TicketClaimsMessage, TicketClaimsQuery,
     TicketClaimsStatus,
 };
+use fasttravel_rt_services::{ClientIdentity, ClientRole};
 
 // =============================================================================
 // Below debug keys generated using:
@@ -67,6 +69,10 @@ kyygmF4SHhyym72azugUvjasYrjW3QebHWPg0lvHeU7mjOKob7+uDxIz
 static AUTH_KEY_DECODING: Lazy<DecodingKey> =
     Lazy::new(|| DecodingKey::from_ec_pem(AUTH_PUBLIC_KEY.as_bytes()).unwrap());
 
+// key id (kid) of the ticket key in the mocker JSON Web Key Set of the
+// realtime server, refer to realtime_main_node.rs
+const TICKET_KEY_ID: &str = "mocker-ticket-key-01";
+
 static TICKET_KEY_ENCODING: Lazy<EncodingKey> =
     Lazy::new(|| EncodingKey::from_ec_pem(TICKET_PRIVATE_KEY.as_bytes()).unwrap());
 
@@ -116,18 +122,21 @@ async fn join_handler(
         return Err(AuthError::WrongCredentials);
     }
 
+    // the mocker has no user profiles, the user id doubles as the display name
+    // and all the users participate.
+    let identity = ClientIdentity {
+        user_id: claims.sub.clone(),
+        display_name: claims.sub.clone(),
+        role: ClientRole::Participant,
+    };
+
     // Ask REALTIME SERVER TO HOST the model-rrot, and if already hosted get the
     // COSPACE_uuid from model-root-cospace session store.
     let expiration_utc: chrono::DateTime<chrono::Utc> =
         chrono::offset::Utc::now() + chrono::Duration::seconds(60);
     let exp = expiration_utc.timestamp() as usize;
     let claims = HostWorkspaceClaims::expected(exp);
-    let certificate = encode(
-        &Header::new(Algorithm::ES256),
-        &claims,
-        &TICKET_KEY_ENCODING,
-    )
-    .map_err(|e| {
+    let certificate = encode(&ticket_header(), &claims, &TICKET_KEY_ENCODING).map_err(|e| {
         println!("TicketCreation: TICKET_CLAIMS_HOST: {}", e);
         AuthError::TicketCreation
     })?;
@@ -152,7 +161,7 @@ async fn join_handler(
         .unwrap();
 
     // send the tickets
-    let response = AuthResponseBody::new(cospace_data.uuid)?;
+    let response = AuthResponseBody::new(cospace_data.uuid, identity)?;
     Ok(Json(response))
 }
 
@@ -198,45 +207,38 @@ struct AuthResponseBody {
 }
 
 impl AuthResponseBody {
-    fn new(cospace_uuid: String) -> Result<Self, AuthError> {
+    fn new(cospace_uuid: String, identity: ClientIdentity) -> Result<Self, AuthError> {
+        let cospace = Uuid::parse_str(&cospace_uuid).map_err(|_| AuthError::TicketCreation)?;
+
         // 5 minutes expiration
         let expiration_utc = chrono::offset::Utc::now() + chrono::Duration::seconds(300);
         let exp = expiration_utc.timestamp() as usize;
 
         // Once we finalize the handshake flow, we might not need all the tickets.
-        let claims_status = TicketClaimsStatus::expected(exp);
-        let claims_query = TicketClaimsQuery::expected(exp);
-        let claims_message = TicketClaimsMessage::expected(exp);
-
-        let ticket_status = encode(
-            &Header::new(Algorithm::ES256),
-            &claims_status,
-            &TICKET_KEY_ENCODING,
-        )
-        .map_err(|e| {
-            println!("TicketCreation: TICKET_CLAIMS_STATUS: {}", e);
-            AuthError::TicketCreation
-        })?;
-
-        let ticket_query = encode(
-            &Header::new(Algorithm::ES256),
-            &claims_query,
-            &TICKET_KEY_ENCODING,
-        )
-        .map_err(|e| {
-            println!("TicketCreation: TICKET_CLAIMS_QUERY: {}", e);
-            AuthError::TicketCreation
-        })?;
-
-        let ticket_message = encode(
-            &Header::new(Algorithm::ES256),
-            &claims_message,
-            &TICKET_KEY_ENCODING,
-        )
-        .map_err(|e| {
-            println!("TicketCreation: TICKET_CLAIMS_MESSAGE: {}", e);
-            AuthError::TicketCreation
-        })?;
+        // unique ticket ids, the query and message tickets are single-use.
+        // the tickets are bound to the cospace and the user.
+        let claims_status = TicketClaimsStatus::expected(exp, new_ticket_id(), cospace);
+        let claims_query =
+            TicketClaimsQuery::expected(exp, new_ticket_id(), cospace, identity.clone());
+        let claims_message = TicketClaimsMessage::expected(exp, new_ticket_id(), cospace, identity);
+
+        let ticket_status = encode(&ticket_header(), &claims_status, &TICKET_KEY_ENCODING)
+            .map_err(|e| {
+                println!("TicketCreation: TICKET_CLAIMS_STATUS: {}", e);
+                AuthError::TicketCreation
+            })?;
+
+        let ticket_query =
+            encode(&ticket_header(), &claims_query, &TICKET_KEY_ENCODING).map_err(|e| {
+                println!("TicketCreation: TICKET_CLAIMS_QUERY: {}", e);
+                AuthError::TicketCreation
+            })?;
+
+        let ticket_message = encode(&ticket_header(), &claims_message, &TICKET_KEY_ENCODING)
+            .map_err(|e| {
+                println!("TicketCreation: TICKET_CLAIMS_MESSAGE: {}", e);
+                AuthError::TicketCreation
+            })?;
 
         Ok(Self {
             ticket_status,
@@ -283,3 +285,13 @@ async fn main() {
 
     fut.await;
 }
+
+fn new_ticket_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+fn ticket_header() -> Header {
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(TICKET_KEY_ID.to_string());
+    header
+}
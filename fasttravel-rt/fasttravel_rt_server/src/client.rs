@@ -1,18 +1,40 @@
 mod client_connection_actor_creator;
 mod client_connection_actor;
 mod client_connection_service_actor;
+mod outbound_queue;
 
 pub(crate) use client_connection_actor_creator::*;
 pub(crate) use client_connection_actor::*;
 pub(crate) use client_connection_service_actor::*;
+pub use outbound_queue::*;
+
+use std::time::Duration;
 
 use crate::{axum, CospaceActor};
-use fasttravel_rt_services::ClientId;
+use fasttravel_rt_services::{ClientId, ClientIdentity};
+
+/// Configuration of the client connections.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ConnectionConfig {
+    /// Duration between successive pings sent to the client.
+    pub(crate) heartbeat_interval: Duration,
+    /// Client gets disconnected if nothing is received for this duration.
+    pub(crate) heartbeat_timeout: Duration,
+    /// Pending requests fail if not answered within this duration.
+    pub(crate) request_timeout: Duration,
+    /// Client gets disconnected if the ticket handshake doesn't succeed
+    /// within this duration.
+    pub(crate) handshake_timeout: Duration,
+    /// Capacity and policies of the outbound queue of the client.
+    pub(crate) outbound_queue: OutboundQueueConfig,
+}
 
 /// Create client connection actor request message.
 pub(crate) struct CreateClientConnectionActorMessage {
     pub(crate) socket: axum::WebSocket,
     pub(crate) cospace_addr: factor::ActorAddr<CospaceActor>,
+    pub(crate) identity: ClientIdentity,
+    pub(crate) protocol: String,
 }
 impl factor::Message for CreateClientConnectionActorMessage {
     type Result = Option<(ClientId, factor::ActorAddr<ClientConnectionActor>)>;
@@ -1,20 +1,26 @@
-use flume;
-use futures::{channel::oneshot, Future};
+use futures::{channel::oneshot, future, Future};
+use futures_timer::Delay;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use factor::{self, ActorReceiverContext};
 
 use fasttravel_rt_proto::{
-    helpers::{self as proto_helpers, ProtoMessage, ProtoPayloadResponse, ProtoPayloadTell},
+    helpers::{
+        self as proto_helpers, ProtoMessage, ProtoPayloadError, ProtoPayloadResponse,
+        ProtoPayloadTell,
+    },
+    realtime::{DeliveryClass, StatusCode},
     RealtimeService,
 };
-use fasttravel_rt_services::{ClientId, ServiceTopics};
+use fasttravel_rt_services::{ClientId, Delivery, ProtoResponseError, ServiceTopics};
 
-use super::SocketMessage;
+use super::{CoalesceKey, Lane, OutboundQueue, SocketMessage};
 use crate::{
-    axum, ClientConnectionServiceActor, ClientMessage, ClientMessageRecipient, ClientMessageRoute,
-    MessagePayload, ServiceMessage, ServiceMessageRoute,
+    axum, ClientCommand, ClientConnectionMessage, ClientConnectionServiceActor, ClientMessage,
+    ClientMessageRecipient, ClientMessageRoute, CospaceActor, MessagePayload, ServiceMessage,
+    ServiceMessageRoute,
 };
 
 // ====================================================================
@@ -35,24 +41,42 @@ use crate::{
 /// Response promise which sends the response on complete.
 #[derive(Debug)]
 struct ResponsePromise {
-    tx: oneshot::Sender<Vec<u8>>,
+    tx: oneshot::Sender<Result<Vec<u8>, ProtoResponseError>>,
 }
 
 impl ResponsePromise {
     fn complete(self, encoded_bytes: Vec<u8>) {
-        let _ = self.tx.send(encoded_bytes);
+        let _ = self.tx.send(Ok(encoded_bytes));
+    }
+
+    fn reject(self, error: ProtoResponseError) {
+        let _ = self.tx.send(Err(error));
     }
 }
 
+/// State of the client connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConnectionState {
+    /// only the ticket handshake request is accepted.
+    AwaitingHandshake,
+    /// the client is connected to the cospace and the traffic is routed.
+    Authenticated,
+    /// the connection is closed or closing, the traffic is dropped.
+    Closed,
+}
+
 /// Client connection actor representing a single client connection to the realtime server.
 #[derive(Clone)]
 pub(crate) struct ClientConnectionActor {
     client_id: ClientId,
-    cospace_addr: factor::MessageClusterAddr<ClientMessage>,
+    state: ConnectionState,
+    weak_addr: factor::ActorWeakAddr<ClientConnectionActor>,
+    cospace_addr: factor::ActorAddr<CospaceActor>,
     conn_service_addr: factor::ActorAddr<ClientConnectionServiceActor>,
-    socket_tx_feeder: flume::Sender<axum::Message>,
+    outbound: OutboundQueue,
     request_id_counter: u32,
     service_promises: HashMap<u32, Arc<ResponsePromise>>,
+    request_timeout: Duration,
 }
 
 impl factor::ActorReceiver for ClientConnectionActor {
@@ -82,23 +106,96 @@ impl factor::MessageClusterHandler<ServiceMessage> for ClientConnectionActor {
             return factor::MessageResponseType::Future(Box::pin(promise_fut));
         }
 
-        factor::MessageResponseType::Result(None.into())
+        factor::MessageResponseType::Result(Err(ProtoResponseError::Failed).into())
+    }
+}
+
+// Handle commands from the cospace and the connection service.
+impl factor::MessageClusterHandler<ClientCommand> for ClientConnectionActor {
+    type Result = factor::MessageResponseType<<ClientCommand as factor::MessageCluster>::Result>;
+
+    fn handle(&mut self, msg: ClientCommand, _ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            ClientCommand::Disconnect { reason } => {
+                tracing::debug!(target: "server-event", "client_conn_actor_disconnect: {}", reason);
+
+                self.close_connection(axum::close_code::AWAY, reason);
+            }
+            ClientCommand::Handshake { success, batching } => {
+                if self.state != ConnectionState::AwaitingHandshake {
+                    return factor::MessageResponseType::Result(().into());
+                }
+
+                if success {
+                    if batching {
+                        self.outbound.enable_batching();
+                    }
+                    self.connect_to_cospace();
+                } else {
+                    self.state = ConnectionState::Closed;
+                    self.close_connection(axum::close_code::POLICY, "handshake_failed".into());
+                }
+            }
+            ClientCommand::HandshakeDeadline => {
+                if self.state == ConnectionState::AwaitingHandshake {
+                    tracing::debug!(target: "server-event", "client_conn_actor_handshake_timeout: {}", self.client_id.id);
+
+                    self.state = ConnectionState::Closed;
+                    self.close_connection(axum::close_code::POLICY, "handshake_timeout".into());
+                }
+            }
+        }
+
+        factor::MessageResponseType::Result(().into())
     }
 }
 
 impl ClientConnectionActor {
     pub(crate) fn new(
-        client_id: ClientId, cospace_addr: factor::MessageClusterAddr<ClientMessage>,
+        client_id: ClientId, weak_addr: factor::ActorWeakAddr<ClientConnectionActor>,
+        cospace_addr: factor::ActorAddr<CospaceActor>,
         conn_service_addr: factor::ActorAddr<ClientConnectionServiceActor>,
-        tx: flume::Sender<axum::Message>,
+        outbound: OutboundQueue, request_timeout: Duration,
     ) -> Self {
         Self {
             client_id,
+            state: ConnectionState::AwaitingHandshake,
+            weak_addr,
             cospace_addr,
             conn_service_addr,
-            socket_tx_feeder: tx,
+            outbound,
             request_id_counter: 0,
             service_promises: HashMap::new(),
+            request_timeout,
+        }
+    }
+
+    /// Join the cospace once the ticket handshake succeeds, the cospace
+    /// informs the services of the new client.
+    fn connect_to_cospace(&mut self) {
+        let addr = match self.weak_addr.upgrade() {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        self.state = ConnectionState::Authenticated;
+
+        let msg = ClientConnectionMessage::Connect {
+            client: self.client_id.clone(),
+            addr,
+        };
+        self.cospace_addr.tell_addr(msg).map_err(|e| tracing::error!(target: "server-event", "client_conn_actor_cospace_connect_failed: {}", e)).err();
+    }
+
+    /// Close the socket with the reason, closing the socket ends the
+    /// connection which tears down the client.
+    fn close_connection(&self, code: u16, reason: String) {
+        let frame = axum::CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        if let Err(e) = self.outbound.close(Some(frame)) {
+            tracing::error!(target: "server-event", "client_disconnect_close_send_failed: {}", e);
         }
     }
 
@@ -110,7 +207,26 @@ impl ClientConnectionActor {
             axum::Message::Binary(proto_bytes) => {
                 self.recv_proto_msg_from_client(proto_bytes, ctx);
             }
-            axum::Message::Text(text) => self.recv_text_message_from_client(text),
+            axum::Message::Text(text) => {
+                if self.state == ConnectionState::Authenticated {
+                    self.recv_text_message_from_client(text);
+                } else {
+                    tracing::warn!(target: "server-event", "client_conn_actor_unauthenticated_text_dropped");
+                }
+            }
+            axum::Message::Close(_) => {
+                tracing::debug!(target: "server-event", "client_conn_actor_connection_closed: {}", self.client_id.id);
+
+                // leave the cospace, the cospace informs the services.
+                if self.state == ConnectionState::Authenticated {
+                    let msg = ClientConnectionMessage::Disconnect(self.client_id.clone());
+                    let _ = self.cospace_addr.tell_addr(msg);
+                }
+                self.state = ConnectionState::Closed;
+
+                // dropping the promises fails the pending service requests.
+                self.service_promises.clear();
+            }
             _ => {
                 tracing::warn!(target: "server-event", "unhandled_socket_message_payload");
             }
@@ -120,7 +236,7 @@ impl ClientConnectionActor {
     /// Process the service message received from the services.
     pub(crate) fn recv_msg_from_service(
         &mut self, msg: ServiceMessage, ctx: &mut <Self as factor::ActorReceiver>::Context,
-    ) -> Option<impl Future<Output = Option<Vec<u8>>>> {
+    ) -> Option<impl Future<Output = Result<Vec<u8>, ProtoResponseError>>> {
         match &msg.route {
             ServiceMessageRoute::Ask(_) => {
                 return self.recv_req_from_service(msg, ctx);
@@ -151,7 +267,7 @@ impl ClientConnectionActor {
         if payload.service == RealtimeService::Connection {
             let _ = self.conn_service_addr.tell_addr(client_msg);
         } else {
-            let _ = self.cospace_addr.tell(client_msg);
+            let _ = self.cospace_addr.tell_addr(client_msg);
         }
     }
 
@@ -172,35 +288,69 @@ impl ClientConnectionActor {
         if payload.service == RealtimeService::Connection {
             addr_client_msg_handler = self.conn_service_addr.message_cluster_addr();
         } else {
-            addr_client_msg_handler = self.cospace_addr.clone();
+            addr_client_msg_handler = self.cospace_addr.message_cluster_addr();
         }
 
-        // send the ask-request to the service
+        // send the ask-request to the service, the client always gets either
+        // the response or an error response.
+        let request_timeout = self.request_timeout;
         let response_promise = async move {
-            // wait for the ask-response
-            if let Ok(Some(response_bytes)) = addr_client_msg_handler.ask(client_msg).await {
-                if let Some(res_msg) = proto_helpers::create_response_message_from_service_payload(
-                    payload.request_id,
-                    &payload.service,
-                    response_bytes,
-                ) {
-                    return Some(SocketMessage(axum::Message::Binary(res_msg)));
-                } else {
-                    tracing::error!(target: "server-event", "proto_response_msg_creation_from_service_payload_failed");
+            let request_id = payload.request_id;
+            let service = payload.service;
+
+            // wait for the ask-response or the request timeout.
+            let ask = addr_client_msg_handler.ask(client_msg);
+            let response = match future::select(Box::pin(ask), Delay::new(request_timeout)).await {
+                future::Either::Left((response, _)) => response,
+                future::Either::Right(_) => {
+                    tracing::error!(target: "server-event", "client_message_req_service_timeout: {}", request_id);
+
+                    let code = StatusCode::Timeout;
+                    let error = "service_request_timeout";
+                    return proto_helpers::create_error_response_message(
+                        request_id, &service, code, error,
+                    );
+                }
+            };
+
+            let (code, error) = match response {
+                Ok(Ok(response_bytes)) => {
+                    match proto_helpers::create_response_message_from_service_payload(
+                        request_id,
+                        &service,
+                        response_bytes,
+                    ) {
+                        Ok(res_msg) => return res_msg,
+                        Err(e) => {
+                            tracing::error!(target: "server-event", "proto_response_msg_creation_from_service_payload_failed: {}", e);
+                            (StatusCode::Failed, "service_response_invalid")
+                        }
+                    }
+                }
+                Ok(Err(ProtoResponseError::Unauthorized)) => {
+                    (StatusCode::Unauthorized, "service_request_unauthorized")
+                }
+                Ok(Err(ProtoResponseError::Timeout)) => {
+                    (StatusCode::Timeout, "service_request_timeout")
+                }
+                Ok(Err(_)) => (StatusCode::Failed, "service_request_failed"),
+                Err(e) => {
+                    tracing::error!(target: "server-event", "client_message_req_ask_service_failed: {}", e);
+                    (StatusCode::Unavailable, "service_unavailable")
                 }
             };
 
-            None
+            proto_helpers::create_error_response_message(request_id, &service, code, error)
         };
 
-        let tx = self.socket_tx_feeder.clone();
+        let outbound = self.outbound.clone();
 
         let task = async move {
-            if let Some(socket_msg) = response_promise.await {
-                // tx.send() never blocks, so doesn't guarantee delivery.
-                if let Err(e) = tx.send(socket_msg.0) {
-                    tracing::error!(target: "server-event", "client_message_response_send_failed: {}", e);
-                }
+            let res_msg = response_promise.await;
+
+            // the outbound queue is bounded, so doesn't guarantee delivery.
+            if let Err(e) = outbound.push(axum::Message::Binary(res_msg)) {
+                tracing::error!(target: "server-event", "client_message_response_send_failed: {}", e);
             }
         };
 
@@ -219,13 +369,64 @@ impl ClientConnectionActor {
         }
     }
 
+    #[inline(always)]
+    fn recv_proto_error_from_client(&mut self, payload: ProtoPayloadError) {
+        tracing::debug!(target: "server-event", "client_rejected_service_request: {:?} {}", payload.code, payload.message);
+
+        // reject the promise, the service gets the error.
+        if let Some(promise_handle) = self.service_promises.remove(&payload.response_id) {
+            let promise = Arc::try_unwrap(promise_handle)
+                .expect("expected_as_clone_is_just_to_satisfy_bound");
+            match payload.code {
+                StatusCode::Timeout => promise.reject(ProtoResponseError::Timeout),
+                _ => promise.reject(ProtoResponseError::Failed),
+            }
+        } else {
+            tracing::error!(target: "server-event", "client_message_req_promise_not_found");
+        }
+    }
+
     #[inline(always)]
     fn recv_proto_msg_from_client(
         &mut self, proto_bytes: Vec<u8>, ctx: &mut <Self as factor::ActorReceiver>::Context,
     ) {
         tracing::debug!(target: "server-event", "client_conn_actor_recv_proto_msg_from_client");
 
-        let proto_msg = proto_helpers::process_realtime_message(proto_bytes);
+        let proto_msg = match proto_helpers::process_realtime_message(proto_bytes) {
+            Ok(proto_msg) => proto_msg,
+            Err(e) => {
+                tracing::error!(target: "server-event", "client_conn_actor_recv_proto_msg_from_client_error: {}", e);
+                return;
+            }
+        };
+
+        // before the handshake only the connection service requests are routed.
+        if self.state != ConnectionState::Authenticated {
+            match proto_msg {
+                ProtoMessage::Request(payload)
+                    if self.state == ConnectionState::AwaitingHandshake
+                        && payload.service == RealtimeService::Connection =>
+                {
+                    self.recv_proto_req_from_client(payload, ctx);
+                }
+                ProtoMessage::Request(payload) => {
+                    tracing::warn!(target: "server-event", "client_conn_actor_unauthenticated_request_rejected");
+
+                    let res_msg = proto_helpers::create_error_response_message(
+                        payload.request_id,
+                        &payload.service,
+                        StatusCode::Unauthorized,
+                        "handshake_required",
+                    );
+                    let _ = self.outbound.push(axum::Message::Binary(res_msg));
+                }
+                _ => {
+                    tracing::warn!(target: "server-event", "client_conn_actor_unauthenticated_message_dropped");
+                }
+            }
+
+            return;
+        }
 
         match proto_msg {
             ProtoMessage::Tell(payload) => {
@@ -243,8 +444,10 @@ impl ClientConnectionActor {
 
                 self.recv_proto_res_from_client(payload);
             }
-            _ => {
-                tracing::error!(target: "server-event", "client_conn_actor_recv_proto_msg_from_client_error");
+            ProtoMessage::Error(payload) => {
+                tracing::trace!(target: "server-event", "client_conn_actor_recv_proto_error_from_client_before");
+
+                self.recv_proto_error_from_client(payload);
             }
         }
     }
@@ -267,38 +470,52 @@ impl ClientConnectionActor {
             payload: MessagePayload::Text(text),
         };
 
-        let _ = self.cospace_addr.tell(client_msg);
+        let _ = self.cospace_addr.tell_addr(client_msg);
     }
 
     #[inline(always)]
     fn recv_req_from_service(
         &mut self, msg: ServiceMessage, _ctx: &mut <Self as factor::ActorReceiver>::Context,
-    ) -> Option<impl Future<Output = Option<Vec<u8>>>> {
+    ) -> Option<impl Future<Output = Result<Vec<u8>, ProtoResponseError>>> {
         if let MessagePayload::Binary(proto_bytes) = msg.payload {
             let request_id = self.request_id_counter;
             self.request_id_counter += 1;
 
-            if let Some(proto_msg) = proto_helpers::create_request_message_from_service_payload(
+            let proto_msg = proto_helpers::create_request_message_from_service_payload(
                 request_id,
                 &msg.sender,
                 proto_bytes,
-            ) {
+            )
+            .map_err(|e| tracing::error!(target: "server-event", "proto_request_msg_creation_from_service_payload_failed: {}", e));
+
+            if let Ok(proto_msg) = proto_msg {
                 let a_msg = axum::Message::Binary(proto_msg);
 
-                // socket_tx_feeder.send() never blocks so delivery not guaranteed.
-                if let Err(e) = self.socket_tx_feeder.send(a_msg) {
+                // the outbound queue is bounded so delivery not guaranteed.
+                if let Err(e) = self.outbound.push(a_msg) {
                     tracing::error!(target: "server-event", "outgoing_service_message_req_send_failed: {}", e);
                 } else {
-                    // create the response promise.
-                    let (tx, rx) = oneshot::channel::<Vec<u8>>();
+                    // create the response promise, it fails if the client doesn't
+                    // respond within the timeout or the connection is closed.
+                    let (tx, rx) = oneshot::channel::<Result<Vec<u8>, ProtoResponseError>>();
+                    let timeout = Delay::new(self.request_timeout);
                     let promise_fut = async move {
-                        if let Ok(encoded_bytes) = rx.await {
-                            return Some(encoded_bytes);
+                        match future::select(rx, timeout).await {
+                            future::Either::Left((Ok(response), _)) => response,
+                            future::Either::Left((Err(_canceled), _)) => {
+                                Err(ProtoResponseError::Disconnected)
+                            }
+                            future::Either::Right(_) => {
+                                tracing::debug!(target: "server-event", "service_message_req_client_timeout: {}", request_id);
+                                Err(ProtoResponseError::Timeout)
+                            }
                         }
-                        None
                     };
 
-                    // [todo] schedule a task that will invalidate the promise after a duration.
+                    // remove the promises of the timed out requests.
+                    self.service_promises
+                        .retain(|_, promise| !promise.tx.is_canceled());
+
                     let promise = ResponsePromise { tx };
                     self.service_promises.insert(request_id, Arc::new(promise));
 
@@ -314,15 +531,39 @@ impl ClientConnectionActor {
 
     #[inline(always)]
     fn recv_tell_from_service(&mut self, msg: ServiceMessage) {
+        // the message is queued in the lane of its delivery class, the newer
+        // state replaces the queued state of the same key.
+        let (lane, class) = match msg.delivery {
+            Delivery::Reliable => (Lane::Reliable, DeliveryClass::Reliable),
+            Delivery::ReliableKeyed(key) => (
+                Lane::ReliableKeyed(CoalesceKey::Service(msg.sender.clone(), key)),
+                DeliveryClass::Reliable,
+            ),
+            Delivery::Latest(key) => (
+                Lane::Latest(CoalesceKey::Service(msg.sender.clone(), key)),
+                DeliveryClass::Latest,
+            ),
+            Delivery::Droppable => (Lane::Droppable, DeliveryClass::Droppable),
+        };
+
         let a_msg = match msg.payload {
             MessagePayload::Text(t) => axum::Message::Text(t),
-            MessagePayload::Binary(b) => axum::Message::Binary(b),
+            MessagePayload::Binary(b) => {
+                // wrap the service payload into a realtime message.
+                match proto_helpers::create_tell_message_with_delivery(&msg.sender, class, b) {
+                    Ok(rt_msg) => axum::Message::Binary(rt_msg),
+                    Err(e) => {
+                        tracing::error!(target: "server-event", "proto_tell_msg_creation_from_service_payload_failed: {}", e);
+                        return;
+                    }
+                }
+            }
         };
 
         tracing::debug!(target: "server-event", "client_conn_actor_recv_tell_from_service: {}", self.client_id.id);
 
-        // socket_tx_feeder.send() never blocks so delivery not guaranteed.
-        if let Err(e) = self.socket_tx_feeder.send(a_msg) {
+        // the outbound queue is bounded so delivery not guaranteed.
+        if let Err(e) = self.outbound.push_lane(a_msg, lane) {
             tracing::error!(target: "server-event", "outgoing_service_msg_tell_send_failed: {}", e);
         }
     }
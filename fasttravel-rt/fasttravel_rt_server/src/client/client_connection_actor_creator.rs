@@ -1,18 +1,39 @@
-use flume;
-use futures::{sink::SinkExt, stream::StreamExt};
+use futures::{future, sink::SinkExt, stream::StreamExt};
+use futures_timer::Delay;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
 
 use factor::{self, ActorReceiverContext};
+use fasttravel_rt_proto::helpers as proto_helpers;
 use fasttravel_rt_services::*;
 
-use super::{CreateClientConnectionActorMessage, SocketMessage};
+use super::{
+    CoalesceKey, ConnectionConfig, CreateClientConnectionActorMessage, Lane, OutboundQueue,
+    OutboundQueueStats, SocketMessage,
+};
 use crate::{
-    axum, ClientConnectionActor, ClientConnectionMessage, ClientConnectionServiceActor,
-    GenerateClientIdMessage,
+    axum, ClaimsValidationConfig, ClientCommand, ClientConnectionActor, ClientConnectionMessage,
+    ClientConnectionServiceActor, GenerateClientIdMessage, ProtocolConfig, PublicDecodingKeys,
+    TicketReplayStore,
 };
 
+/// Duration the close frame of a backpressured client is attempted for.
+const CLOSE_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum length in bytes of a batch of messages, a single message is never split.
+const BATCH_MAX_LEN: usize = 64 * 1024;
+
 /// Client connection actor creator.
 pub(crate) struct ClientConnectionActorCreator {
-    session_request_decoding: jsonwebtoken::DecodingKey,
+    public_keys: Arc<PublicDecodingKeys>,
+    used_tickets: TicketReplayStore,
+    claims_validation: Arc<ClaimsValidationConfig>,
+    outbound_stats: Arc<OutboundQueueStats>,
+    protocol: Arc<ProtocolConfig>,
+    config: ConnectionConfig,
 }
 
 impl factor::ActorReceiver for ClientConnectionActorCreator {
@@ -28,102 +49,304 @@ impl factor::MessageHandler<CreateClientConnectionActorMessage> for ClientConnec
     fn handle(
         &mut self, msg: CreateClientConnectionActorMessage, ctx: &mut Self::Context,
     ) -> Self::Result {
-        let session_request_decoding = self.session_request_decoding.clone();
-        let fut = Self::create_client_actor(msg, ctx.system(), session_request_decoding);
+        let public_keys = self.public_keys.clone();
+        let used_tickets = self.used_tickets.clone();
+        let claims_validation = self.claims_validation.clone();
+        let outbound_stats = self.outbound_stats.clone();
+        let protocol = self.protocol.clone();
+        let fut = Self::create_client_actor(
+            msg,
+            ctx.system(),
+            public_keys,
+            used_tickets,
+            claims_validation,
+            outbound_stats,
+            protocol,
+            self.config,
+        );
 
         factor::MessageResponseType::Future(Box::pin(fut))
     }
 }
 
 impl ClientConnectionActorCreator {
-    pub(crate) fn new(session_request_decoding: jsonwebtoken::DecodingKey) -> Self {
+    pub(crate) fn new(
+        public_keys: Arc<PublicDecodingKeys>, used_tickets: TicketReplayStore,
+        claims_validation: Arc<ClaimsValidationConfig>, outbound_stats: Arc<OutboundQueueStats>,
+        protocol: Arc<ProtocolConfig>, config: ConnectionConfig,
+    ) -> Self {
         Self {
-            session_request_decoding,
+            public_keys,
+            used_tickets,
+            claims_validation,
+            outbound_stats,
+            protocol,
+            config,
         }
     }
 
     /// Create the cllient connection actor for a new client connection.
     async fn create_client_actor(
         msg: CreateClientConnectionActorMessage, sys: factor::SystemRef,
-        session_request_decoding: jsonwebtoken::DecodingKey,
+        public_keys: Arc<PublicDecodingKeys>, used_tickets: TicketReplayStore,
+        claims_validation: Arc<ClaimsValidationConfig>, outbound_stats: Arc<OutboundQueueStats>,
+        protocol: Arc<ProtocolConfig>, config: ConnectionConfig,
     ) -> Option<(ClientId, factor::ActorAddr<ClientConnectionActor>)> {
-        // create the channels
+        // split the socket and create the bounded outbound queue.
         let (mut socket_tx, mut socket_rx) = msg.socket.split();
-        let (tx, rx) = flume::unbounded::<axum::Message>();
+        let queue = OutboundQueue::new(config.outbound_queue, outbound_stats);
 
         // generate the client id
         let client_id;
-        if let Ok(generated_id) = msg.cospace_addr.ask_addr(GenerateClientIdMessage).await {
+        let identity = msg.identity.clone();
+        if let Ok(generated_id) = msg
+            .cospace_addr
+            .ask_addr(GenerateClientIdMessage { identity })
+            .await
+        {
             client_id = generated_id;
         } else {
             return None;
         }
 
         // create the connection service actor
-        let config = factor::ActorBuilderConfig::default();
+        let config_actor = factor::ActorBuilderConfig::default();
         let mut client_id_moved = client_id.clone();
+        let subprotocol = msg.protocol.clone();
         let item = factor::ActorBuilder::create(
             move |_| {
                 ClientConnectionServiceActor::new(
                     client_id_moved.clone(),
-                    session_request_decoding.clone(),
+                    public_keys.clone(),
+                    used_tickets.clone(),
+                    claims_validation.clone(),
+                    subprotocol.clone(),
+                    protocol.clone(),
                 )
             },
             &sys,
-            config,
+            config_actor,
         );
         let conn_service_addr = sys.run_actor(item.unwrap());
         let moved_conn_service_addr = conn_service_addr.clone();
 
         // create the client connection actor.
         client_id_moved = client_id.clone();
-        let factory = move |_| {
+        let factory_conn_service_addr = conn_service_addr.clone();
+        let factory = move |weak_addr| {
             ClientConnectionActor::new(
                 client_id_moved.clone(),
-                msg.cospace_addr.message_cluster_addr(),
-                moved_conn_service_addr.clone(),
-                tx.clone(),
+                weak_addr,
+                msg.cospace_addr.clone(),
+                factory_conn_service_addr.clone(),
+                queue.clone(),
+                config.request_timeout,
             )
         };
-        let config = factor::ActorBuilderConfig::default();
-        let spawn_item = factor::ActorBuilder::create(factory, &sys, config);
+        let config_actor = factor::ActorBuilderConfig::default();
+        let spawn_item = factor::ActorBuilder::create(factory, &sys, config_actor);
         let client_addr = sys.run_actor(spawn_item.unwrap());
 
+        // notify the connection service actor, before the socket loopers start so
+        // that a disconnect never overtakes the connect. The client connection actor
+        // joins the cospace after the ticket handshake.
+        let _ = conn_service_addr.tell_addr(ClientConnectionMessage::Connect {
+            client: client_id.clone(),
+            addr: client_addr.clone(),
+        });
+
+        // enforce the handshake deadline.
+        let deadline_addr = client_addr.clone();
+        sys.spawn_ok(async move {
+            Delay::new(config.handshake_timeout).await;
+            let _ = deadline_addr.tell_addr(ClientCommand::HandshakeDeadline);
+        });
+
         // create the outgoing socket message looper.
+        let queue_socket = queue.clone();
+        let backpressure_timeout = config.outbound_queue.backpressure_timeout;
         let fut_socket_tx = async move {
-            while let Ok(msg) = rx.recv_async().await {
-                // forward outgoing messages from client connection actor to socket.
-                if let Err(e) = socket_tx.send(msg).await {
-                    tracing::error!(target: "server-event", "socket_outgoing_message_send_failed: {}", e);
+            let mut next = None;
+            loop {
+                let msg = match next.take() {
+                    Some(msg) => msg,
+                    None => match queue_socket.pop().await {
+                        Some(msg) => msg,
+                        None => return,
+                    },
+                };
+
+                // the binary messages ready to be sent go in a single batch, once
+                // the client negotiated the batching feature.
+                let (msg, pending) = batch_ready_messages(&queue_socket, msg);
+                next = pending;
+
+                let closing = matches!(msg, axum::Message::Close(_));
+
+                // forward outgoing messages from the queue to socket, a client not
+                // reading the socket for the backpressure timeout is disconnected.
+                let send = socket_tx.send(msg);
+                let timeout = Delay::new(backpressure_timeout);
+                let timed_out = match future::select(Box::pin(send), timeout).await {
+                    future::Either::Left((Ok(_), _)) => false,
+                    future::Either::Left((Err(e), _)) => {
+                        tracing::error!(target: "server-event", "socket_outgoing_message_send_failed: {}", e);
+                        // the socket is closed, stop the outgoing looper.
+                        queue_socket.fail(false);
+                        return;
+                    }
+                    future::Either::Right(_) => {
+                        tracing::debug!(target: "server-event", "socket_outgoing_message_send_timeout");
+                        queue_socket.fail(true);
+                        true
+                    }
+                };
+
+                // attempt the close frame queued by the failure, the client may
+                // still read it if the socket drains soon.
+                if timed_out {
+                    if let Some(close) = queue_socket.pop().await {
+                        let send = socket_tx.send(close);
+                        let timeout = Delay::new(CLOSE_FRAME_TIMEOUT);
+                        let _ = future::select(Box::pin(send), timeout).await;
+                    }
+                    return;
+                }
+
+                if closing {
+                    return;
                 }
             }
         };
         sys.spawn_ok(fut_socket_tx);
 
+        // last time anything was received from the client, in milliseconds
+        // since the connection was established.
+        let connected_at = Instant::now();
+        let last_seen = Arc::new(AtomicU64::new(0));
+        let last_seen_moved = last_seen.clone();
+
         // create the incoming socket message looper.
         let msg_addr = client_addr.message_addr::<SocketMessage>();
         let fut_socket_rx = async move {
             while let Some(msg) = socket_rx.next().await {
-                if let Ok(msg) = msg {
-                    // forward incoming socket messages to client connection actor.
-                    tracing::trace!(target: "server-event", "socket_message_received");
-
-                    let _ = msg_addr.tell(SocketMessage(msg));
-                } else {
-                    tracing::debug!(target: "server-event", "client_disconnected");
-                    // [todo] inform client_connection_actor
+                match msg {
+                    Ok(axum::Message::Close(_)) => {
+                        tracing::debug!(target: "server-event", "client_closed_connection");
+                        return;
+                    }
+                    Ok(msg) => {
+                        let elapsed = connected_at.elapsed().as_millis() as u64;
+                        last_seen_moved.store(elapsed, Ordering::Relaxed);
+
+                        // pings are answered by the socket, pongs only refresh last_seen.
+                        if let axum::Message::Ping(_) | axum::Message::Pong(_) = msg {
+                            continue;
+                        }
+
+                        // forward incoming socket messages to client connection actor.
+                        tracing::trace!(target: "server-event", "socket_message_received");
+
+                        let _ = msg_addr.tell(SocketMessage(msg));
+                    }
+                    Err(e) => {
+                        tracing::debug!(target: "server-event", "client_connection_error: {}", e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        // create the heartbeat looper, ping the client every interval and
+        // stop if nothing received within the timeout.
+        let heartbeat_queue = queue.clone();
+        let client_id_moved = client_id.clone();
+        let fut_heartbeat = async move {
+            loop {
+                Delay::new(config.heartbeat_interval).await;
+
+                let elapsed = connected_at.elapsed().as_millis() as u64;
+                let idle = elapsed.saturating_sub(last_seen.load(Ordering::Relaxed));
+
+                if Duration::from_millis(idle) >= config.heartbeat_timeout {
+                    tracing::debug!(target: "server-event", "client_heartbeat_timeout: {}", client_id_moved.id);
+                    return;
+                }
+
+                // the queue is closed or the client is not draining it, the push
+                // checks the backpressure every interval even if nothing else is
+                // queued. Pings are coalesced, a single ping is queued at a time.
+                let ping = axum::Message::Ping(Vec::new());
+                if heartbeat_queue
+                    .push_lane(ping, Lane::Latest(CoalesceKey::Ping))
+                    .is_err()
+                {
                     return;
                 }
             }
         };
-        sys.spawn_ok(fut_socket_rx);
 
-        // notify the connection service actor
-        let _ = conn_service_addr.tell_addr(ClientConnectionMessage::Connect {
-            client: client_id.clone(),
-            addr: client_addr.clone(),
-        });
+        // the connection ends when the socket is closed, fails, the heartbeat
+        // times out or the client doesn't drain its outbound queue. On end, close
+        // the socket and inform the client connection actor (which fails the
+        // pending requests and leaves the cospace) and the connection service
+        // actor. Once all the addresses are released the client actors stop.
+        let client_id_moved = client_id.clone();
+        let close_addr = client_addr.message_addr::<SocketMessage>();
+        let queue_close = queue;
+        let fut_connection = async move {
+            let fut_failed = queue_close.failed();
+            future::select(
+                Box::pin(fut_socket_rx),
+                future::select(Box::pin(fut_heartbeat), Box::pin(fut_failed)),
+            )
+            .await;
+
+            tracing::debug!(target: "server-event", "client_disconnected: {} dropped: {}", client_id_moved.id, queue_close.dropped());
+
+            let _ = queue_close.close(None);
+            let _ = close_addr.tell(SocketMessage(axum::Message::Close(None)));
+            let _ = moved_conn_service_addr
+                .tell_addr(ClientConnectionMessage::Disconnect(client_id_moved));
+        };
+        sys.spawn_ok(fut_connection);
 
         Some((client_id, client_addr))
     }
 }
+
+/// Batch the message with the binary messages ready in the queue, returns the
+/// message to send and the popped message that doesn't fit in the batch.
+fn batch_ready_messages(
+    queue: &OutboundQueue, msg: axum::Message,
+) -> (axum::Message, Option<axum::Message>) {
+    let first = match msg {
+        axum::Message::Binary(bytes) if queue.batching() => bytes,
+        msg => return (msg, None),
+    };
+
+    let mut len = first.len();
+    let mut messages = vec![first];
+    let mut next = None;
+    while len < BATCH_MAX_LEN {
+        match queue.try_pop() {
+            Some(axum::Message::Binary(bytes)) => {
+                len += bytes.len();
+                messages.push(bytes);
+            }
+            Some(msg) => {
+                next = Some(msg);
+                break;
+            }
+            None => break,
+        }
+    }
+
+    // a single message is sent as is.
+    if messages.len() == 1 {
+        return (axum::Message::Binary(messages.remove(0)), next);
+    }
+
+    let batch = proto_helpers::create_realtime_batch(&messages);
+    (axum::Message::Binary(batch), next)
+}
@@ -1,19 +1,31 @@
-use crate::{ClientConnectionMessage, ClientMessage, MessagePayload, ServiceMessage};
-use fasttravel_rt_services::ClientId;
+use crate::{
+    ClientCommand, ClientConnectionActor, ClientConnectionMessage, ClientMessage, MessagePayload,
+};
+use fasttravel_rt_services::{ClientId, ProtoResponseError};
 
 use fasttravel_rt_proto::{
     helpers::{self as proto_helpers},
-    realtime::{self, connection::TicketHandshakeRequest},
+    realtime::{
+        self,
+        connection::{Feature, TicketHandshakeRequest},
+    },
 };
-use jsonwebtoken;
+use std::sync::Arc;
 
-use crate::TicketClaimsMessage;
+use crate::{
+    ClaimsValidationConfig, ProtocolConfig, PublicDecodingKeys, TicketClaimsMessage,
+    TicketReplayStore,
+};
 
 pub(crate) struct ClientConnectionServiceActor {
     client_id: ClientId,
-    client_service_msg_addr: Option<factor::MessageClusterAddr<ServiceMessage>>,
-    session_request_decoding: jsonwebtoken::DecodingKey,
-    // heartbeat_interval: Duration,
+    client_addr: Option<factor::ActorAddr<ClientConnectionActor>>,
+    public_keys: Arc<PublicDecodingKeys>,
+    used_tickets: TicketReplayStore,
+    claims_validation: Arc<ClaimsValidationConfig>,
+    // websocket subprotocol selected for the connection.
+    subprotocol: String,
+    protocol: Arc<ProtocolConfig>,
 }
 
 impl factor::ActorReceiver for ClientConnectionServiceActor {
@@ -27,13 +39,20 @@ impl factor::MessageClusterHandler<ClientConnectionMessage> for ClientConnection
 
     fn handle(&mut self, msg: ClientConnectionMessage, _ctx: &mut Self::Context) -> Self::Result {
         match msg {
+            // messages of another client are rejected, the actor keeps serving its client.
             ClientConnectionMessage::Connect { client, addr } => {
-                assert_eq!(self.client_id, client);
-                self.client_service_msg_addr = Some(addr.message_cluster_addr());
+                if self.client_id == client {
+                    self.client_addr = Some(addr);
+                } else {
+                    tracing::error!(target: "server-event", "client_conn_service_actor_connect_rejected: {}", client.id);
+                }
             }
             ClientConnectionMessage::Disconnect(client_id) => {
-                assert_eq!(self.client_id, client_id);
-                self.client_service_msg_addr = None;
+                if self.client_id == client_id {
+                    self.client_addr = None;
+                } else {
+                    tracing::error!(target: "server-event", "client_conn_service_actor_disconnect_rejected: {}", client_id.id);
+                }
             }
         }
 
@@ -50,13 +69,15 @@ impl factor::MessageClusterHandler<ClientMessage> for ClientConnectionServiceAct
 
         match msg.payload {
             MessagePayload::Binary(bytes) => {
-                if let Some(payload) =
+                let payload =
                     proto_helpers::connection::decode_connection_message_and_extract_payload(bytes)
-                {
+                        .map_err(|e| tracing::error!(target: "server-event", "client_conn_service_actor_decode_failed: {}", e));
+
+                if let Ok(payload) = payload {
                     match payload {
                         realtime::connection::message::Payload::HandshakeReq(req) => {
                             let response = self.handle_handshake_request(req);
-                            return factor::MessageResponseType::Result(response.into());
+                            return factor::MessageResponseType::Result(Ok(response).into());
                         }
                         _ => {
                             tracing::warn!(target: "server-event", "client_conn_service_actor_unhandled_payload_received");
@@ -69,39 +90,78 @@ impl factor::MessageClusterHandler<ClientMessage> for ClientConnectionServiceAct
             }
         }
 
-        factor::MessageResponseType::Result(None.into())
+        factor::MessageResponseType::Result(Err(ProtoResponseError::Failed).into())
     }
 }
 
 impl ClientConnectionServiceActor {
     pub(crate) fn new(
-        client_id: ClientId, session_request_decoding: jsonwebtoken::DecodingKey,
+        client_id: ClientId, public_keys: Arc<PublicDecodingKeys>, used_tickets: TicketReplayStore,
+        claims_validation: Arc<ClaimsValidationConfig>, subprotocol: String,
+        protocol: Arc<ProtocolConfig>,
     ) -> Self {
         Self {
             client_id,
-            client_service_msg_addr: None,
-            session_request_decoding,
+            client_addr: None,
+            public_keys,
+            used_tickets,
+            claims_validation,
+            subprotocol,
+            protocol,
         }
     }
 
-    fn handle_handshake_request(&self, req: TicketHandshakeRequest) -> Option<Vec<u8>> {
-        let validation = TicketClaimsMessage::validation();
+    fn handle_handshake_request(&self, req: TicketHandshakeRequest) -> Vec<u8> {
+        let validation = TicketClaimsMessage::validation(&self.claims_validation);
         let mut success = false;
 
         tracing::debug!(target: "server-event", "client_conn_service_actor_handle_handshake_request");
-
-        match jsonwebtoken::decode::<TicketClaimsMessage>(
-            req.ticket.as_str(),
-            &self.session_request_decoding,
-            &validation,
-        ) {
-            Ok(_data) => success = true,
+        tracing::debug!(target: "server-event", "client_conn_service_actor_handshake_sdk_version: {}", req.sdk_version);
+
+        match self
+            .public_keys
+            .decode::<TicketClaimsMessage>(req.ticket.as_str(), &validation)
+        {
+            Ok(data) => {
+                let claims = &data.claims;
+
+                // the message ticket must be issued to the connected user, for
+                // the connected cospace, and is single-use.
+                if !claims.is_issued_to(&self.client_id) {
+                    tracing::error!(target: "server-event", "client_conn_service_actor_handshake_ticket_identity_mismatch");
+                } else if !self.used_tickets.consume(claims.jti(), claims.exp()) {
+                    tracing::error!(target: "server-event", "client_conn_service_actor_handshake_ticket_replayed");
+                } else {
+                    success = true;
+                }
+            }
             Err(e) => {
                 tracing::error!(target: "server-event", "client_conn_service_actor_handshake_ticket_auth_error: {}", e);
             }
         }
 
-        let response = proto_helpers::connection::create_ticket_handshake_response(success);
-        Some(response)
+        // the features supported by both the client-sdk and the server, none
+        // if the handshake failed.
+        let mut features = Vec::new();
+        if success {
+            features = proto_helpers::connection::negotiate_features(
+                &req.features,
+                &self.protocol.features,
+            );
+        }
+
+        // inform the client connection actor before responding, so that the
+        // client traffic following the response is routed.
+        if let Some(addr) = &self.client_addr {
+            let batching = features.contains(&Feature::Batching);
+            let command = ClientCommand::Handshake { success, batching };
+            addr.tell_addr(command).map_err(|e| tracing::error!(target: "server-event", "client_conn_service_actor_handshake_result_send_failed: {}", e)).err();
+        }
+
+        proto_helpers::connection::create_ticket_handshake_response(
+            success,
+            self.subprotocol.clone(),
+            &features,
+        )
     }
 }
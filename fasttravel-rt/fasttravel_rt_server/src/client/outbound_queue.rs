@@ -0,0 +1,695 @@
+use futures::{future, task::AtomicWaker};
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use fasttravel_rt_proto::RealtimeService;
+
+use crate::axum;
+
+/// Messages dropped when the droppable lane of a client is full.
+#[derive(Clone, Copy, Debug)]
+pub enum OverflowPolicy {
+    /// drop the oldest queued messages to make room for the new ones, for
+    /// experience-prioritized traffic where the latest messages matter most.
+    DropOldest,
+    /// keep the queued messages and drop the new ones.
+    DropNewest,
+}
+
+/// Number of messages sent from each lane in a scheduling round, as long as
+/// the lane has messages queued. A lane with a weight of zero is served
+/// like a lane with a weight of one.
+#[derive(Clone, Copy, Debug)]
+pub struct LaneWeights {
+    /// reliable ordered lane, e.g. model commits and responses.
+    pub reliable: u32,
+    /// latest-value-wins lane, e.g. presence updates.
+    pub latest: u32,
+    /// droppable lane, e.g. cosmetic effects.
+    pub droppable: u32,
+}
+
+/// Configuration of the outbound queues, every client connection has its own
+/// bounded queue of the messages waiting to be sent over the socket.
+///
+/// The queue keeps a lane for every delivery class:
+/// * reliable: messages are sent in order and never dropped, the lane grows
+/// past the capacity while it is full and the client is disconnected if it
+/// stays full for the backpressure timeout or grows past the reliable limit.
+/// A keyed reliable message drops the queued latest message with the same
+/// key, the newer latest messages of the key follow it in the reliable lane
+/// while it is queued.
+/// * latest: a queued message is replaced by the newer message with the same
+/// key, the oldest message is dropped if the lane is full.
+/// * droppable: messages are dropped by the overflow policy if the lane is full.
+#[derive(Clone, Copy, Debug)]
+pub struct OutboundQueueConfig {
+    /// Maximum number of messages queued for a client in each lane.
+    pub capacity: usize,
+
+    /// Hard limit of the reliable lane growing past the capacity, the client
+    /// is disconnected once exceeded without waiting for the backpressure timeout.
+    pub reliable_limit: usize,
+
+    /// Messages dropped when the droppable lane is full.
+    pub overflow: OverflowPolicy,
+
+    /// Weighted scheduling between the lanes.
+    pub weights: LaneWeights,
+
+    /// Client gets disconnected if one of its lanes stays full, or a single
+    /// message can't be written to the socket, for this duration.
+    pub backpressure_timeout: Duration,
+}
+
+// default outbound queue config.
+impl Default for OutboundQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            reliable_limit: 4096,
+            overflow: OverflowPolicy::DropOldest,
+            weights: LaneWeights {
+                reliable: 8,
+                latest: 4,
+                droppable: 1,
+            },
+            backpressure_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Counters of the outbound queues of all the client connections.
+#[derive(Debug, Default)]
+pub struct OutboundQueueStats {
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+    backpressured: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+/// Snapshot of the outbound queue counters.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OutboundQueueCounters {
+    /// messages dropped as the latest or droppable lane was full.
+    pub dropped: u64,
+    /// queued messages replaced by a newer message with the same key.
+    pub coalesced: u64,
+    /// times a lane became full.
+    pub backpressured: u64,
+    /// clients disconnected after sustained backpressure.
+    pub disconnected: u64,
+}
+
+impl OutboundQueueStats {
+    /// Current values of the counters.
+    pub fn counters(&self) -> OutboundQueueCounters {
+        OutboundQueueCounters {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            backpressured: self.backpressured.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Key of the coalesced messages, a queued message is replaced by the
+/// newer message with the same key.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum CoalesceKey {
+    /// heartbeat pings.
+    Ping,
+    /// state of the service, keyed by the service.
+    Service(RealtimeService, String),
+}
+
+/// Outbound lane a message is queued in.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Lane {
+    Reliable,
+    /// reliable message superseding the latest messages of the key.
+    ReliableKeyed(CoalesceKey),
+    Latest(CoalesceKey),
+    Droppable,
+}
+
+/// Errors queueing the outgoing messages.
+#[derive(Debug)]
+pub(crate) enum OutboundQueueError {
+    /// the connection is closed or closing.
+    Closed,
+    /// a lane stayed full for the backpressure timeout or the reliable lane
+    /// exceeded its limit, the client is getting disconnected.
+    Backpressure,
+}
+
+impl std::fmt::Display for OutboundQueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "outbound_queue_closed"),
+            Self::Backpressure => write!(f, "outbound_queue_backpressure"),
+        }
+    }
+}
+
+/// Bounded queue of the messages sent to a client. The client connection
+/// actor and the heartbeat push the messages and the outgoing socket looper
+/// pops and writes them to the socket.
+///
+/// The lanes are served in the reliable, latest, droppable order with a
+/// weighted round robin, so that the droppable messages never delay the
+/// reliable messages by more than their weight.
+#[derive(Clone)]
+pub(crate) struct OutboundQueue {
+    shared: Arc<SharedQueue>,
+}
+
+struct SharedQueue {
+    state: Mutex<QueueState>,
+    config: OutboundQueueConfig,
+    stats: Arc<OutboundQueueStats>,
+    // wakes the outgoing socket looper.
+    waker_pop: AtomicWaker,
+    // wakes the connection on failure.
+    waker_failed: AtomicWaker,
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// the reliable messages, along with the key of the keyed messages.
+    reliable: VecDeque<(axum::Message, Option<CoalesceKey>)>,
+    latest: VecDeque<(axum::Message, CoalesceKey)>,
+    droppable: VecDeque<axum::Message>,
+    /// close frame, sent once the lanes are drained.
+    close_frame: Option<axum::Message>,
+    /// messages the reliable, latest and droppable lanes could still send
+    /// in the current scheduling round.
+    credits: [u32; 3],
+    /// instant since when a lane is full.
+    full_since: Option<Instant>,
+    /// no more messages are accepted, the queued ones are still sent.
+    closed: bool,
+    /// the client is not draining its queue, the connection ends.
+    failed: bool,
+    /// the client negotiated the batching feature, the ready messages are sent
+    /// in a single socket message.
+    batching: bool,
+    /// messages of the client dropped as the queue was full.
+    dropped: u64,
+}
+
+impl OutboundQueue {
+    pub(crate) fn new(config: OutboundQueueConfig, stats: Arc<OutboundQueueStats>) -> Self {
+        Self {
+            shared: Arc::new(SharedQueue {
+                state: Mutex::new(QueueState::default()),
+                config,
+                stats,
+                waker_pop: AtomicWaker::new(),
+                waker_failed: AtomicWaker::new(),
+            }),
+        }
+    }
+
+    /// Queue the message for the client in the reliable lane.
+    pub(crate) fn push(&self, msg: axum::Message) -> Result<(), OutboundQueueError> {
+        self.push_lane(msg, Lane::Reliable)
+    }
+
+    /// Queue the message for the client in the lane.
+    pub(crate) fn push_lane(
+        &self, msg: axum::Message, lane: Lane,
+    ) -> Result<(), OutboundQueueError> {
+        let config = &self.shared.config;
+        let stats = &self.shared.stats;
+
+        let mut state = self.lock();
+        if state.closed {
+            return Err(OutboundQueueError::Closed);
+        }
+
+        // sustained backpressure, disconnect the client.
+        if state.backpressure_expired(config) {
+            drop(state);
+            self.fail(true);
+            return Err(OutboundQueueError::Backpressure);
+        }
+
+        let pushed = match lane {
+            Lane::Reliable => state.push_reliable(msg, None, config, stats),
+            Lane::ReliableKeyed(key) => {
+                // the queued latest message of the key is stale, it must never
+                // be sent after the reliable message.
+                let queued = state
+                    .latest
+                    .iter()
+                    .position(|(_, queued_key)| queued_key == &key);
+
+                if let Some(index) = queued {
+                    state.latest.remove(index);
+                    stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                }
+
+                state.push_reliable(msg, Some(key), config, stats)
+            }
+            // the lanes are not ordered between each other, the message follows
+            // the queued reliable message of the key in the reliable lane.
+            Lane::Latest(key) if state.is_reliable_queued(&key) => {
+                state.push_reliable(msg, Some(key), config, stats)
+            }
+            Lane::Latest(key) => {
+                let queued = state
+                    .latest
+                    .iter_mut()
+                    .find(|(_, queued_key)| queued_key == &key);
+
+                if let Some(queued) = queued {
+                    queued.0 = msg;
+                    stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+
+                if state.latest.len() >= config.capacity {
+                    state.overflow(stats);
+                    state.latest.pop_front();
+                }
+
+                state.latest.push_back((msg, key));
+                Ok(())
+            }
+            Lane::Droppable => {
+                if state.droppable.len() >= config.capacity {
+                    state.overflow(stats);
+
+                    match config.overflow {
+                        OverflowPolicy::DropOldest => {
+                            state.droppable.pop_front();
+                        }
+                        OverflowPolicy::DropNewest => return Ok(()),
+                    }
+                }
+
+                state.droppable.push_back(msg);
+                Ok(())
+            }
+        };
+        drop(state);
+
+        // the reliable lane exceeded its limit, disconnect the client.
+        if let Err(e) = pushed {
+            self.fail(true);
+            return Err(e);
+        }
+
+        self.shared.waker_pop.wake();
+        Ok(())
+    }
+
+    /// Queue the close frame and close the queue. The close frame is sent
+    /// after the messages queued before.
+    pub(crate) fn close(&self, frame: Option<axum::CloseFrame>) -> Result<(), OutboundQueueError> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(OutboundQueueError::Closed);
+        }
+
+        state.closed = true;
+        state.close_frame = Some(axum::Message::Close(frame));
+        drop(state);
+
+        self.shared.waker_pop.wake();
+        Ok(())
+    }
+
+    /// Fail the queue of a client not draining its queue (backpressure) or
+    /// with a broken socket. The queued messages are dropped and only the
+    /// close frame is sent, if the socket is still writable.
+    pub(crate) fn fail(&self, backpressure: bool) {
+        let mut state = self.lock();
+        if state.failed {
+            return;
+        }
+
+        state.failed = true;
+        state.closed = true;
+        state.reliable.clear();
+        state.latest.clear();
+        state.droppable.clear();
+        state.close_frame = None;
+
+        if backpressure {
+            tracing::debug!(target: "server-event", "outbound_queue_backpressure_disconnect: {}", state.dropped);
+
+            self.shared
+                .stats
+                .disconnected
+                .fetch_add(1, Ordering::Relaxed);
+
+            let frame = axum::CloseFrame {
+                code: axum::close_code::AGAIN,
+                reason: "outbound_backpressure".into(),
+            };
+            state.close_frame = Some(axum::Message::Close(Some(frame)));
+        }
+        drop(state);
+
+        self.shared.waker_pop.wake();
+        self.shared.waker_failed.wake();
+    }
+
+    /// Next message to write to the socket, None once the queue is closed
+    /// and all the queued messages are popped.
+    pub(crate) async fn pop(&self) -> Option<axum::Message> {
+        future::poll_fn(|cx| {
+            self.shared.waker_pop.register(cx.waker());
+
+            let mut state = self.lock_draining();
+            if let Some(msg) = state.pop_next(&self.shared.config) {
+                return Poll::Ready(Some(msg));
+            }
+
+            if state.closed {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Next message to write to the socket if one is queued, without waiting.
+    pub(crate) fn try_pop(&self) -> Option<axum::Message> {
+        self.lock_draining().pop_next(&self.shared.config)
+    }
+
+    /// Send the messages of the client in batches, once it negotiated the
+    /// batching feature.
+    pub(crate) fn enable_batching(&self) {
+        self.lock().batching = true;
+    }
+
+    /// Whether the messages of the client are sent in batches.
+    pub(crate) fn batching(&self) -> bool {
+        self.lock().batching
+    }
+
+    /// Resolves once the queue failed, the connection must end.
+    pub(crate) async fn failed(&self) {
+        future::poll_fn(|cx| {
+            self.shared.waker_failed.register(cx.waker());
+
+            if self.lock().failed {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Number of messages of the client dropped as the queue was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.lock().dropped
+    }
+
+    /// Lock the state to pop the queued messages. A client draining its queue
+    /// too slowly is disconnected once the backpressure outlasts the timeout,
+    /// even if no more messages are pushed.
+    fn lock_draining(&self) -> MutexGuard<'_, QueueState> {
+        let state = self.lock();
+        if !state.backpressure_expired(&self.shared.config) {
+            return state;
+        }
+
+        drop(state);
+        self.fail(true);
+        self.lock()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        // the state stays consistent even if a holder panicked.
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl QueueState {
+    /// Mark the start of the backpressure as a lane is full.
+    fn backpressure(&mut self, stats: &OutboundQueueStats) {
+        if self.full_since.is_none() {
+            self.full_since = Some(Instant::now());
+            stats.backpressured.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Check if a lane stayed full for the backpressure timeout.
+    fn backpressure_expired(&self, config: &OutboundQueueConfig) -> bool {
+        match self.full_since {
+            Some(since) => !self.failed && since.elapsed() >= config.backpressure_timeout,
+            None => false,
+        }
+    }
+
+    /// Queue the message in the reliable lane. Reliable messages are never
+    /// dropped, a client that can't keep up with them within the backpressure
+    /// timeout or the reliable limit has to reconnect and resync.
+    fn push_reliable(
+        &mut self, msg: axum::Message, key: Option<CoalesceKey>, config: &OutboundQueueConfig,
+        stats: &OutboundQueueStats,
+    ) -> Result<(), OutboundQueueError> {
+        if self.reliable.len() >= config.reliable_limit {
+            return Err(OutboundQueueError::Backpressure);
+        }
+
+        if self.reliable.len() >= config.capacity {
+            self.backpressure(stats);
+        }
+
+        self.reliable.push_back((msg, key));
+        Ok(())
+    }
+
+    /// Check if a reliable message of the key is queued.
+    fn is_reliable_queued(&self, key: &CoalesceKey) -> bool {
+        self.reliable
+            .iter()
+            .any(|(_, reliable_key)| reliable_key.as_ref() == Some(key))
+    }
+
+    /// Count a message dropped as its lane is full.
+    fn overflow(&mut self, stats: &OutboundQueueStats) {
+        self.backpressure(stats);
+
+        self.dropped += 1;
+        stats.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Pop the next message of the lanes, or the close frame once the lanes
+    /// are drained.
+    fn pop_next(&mut self, config: &OutboundQueueConfig) -> Option<axum::Message> {
+        if let Some(msg) = self.pop_weighted(config) {
+            // the backpressure ends once the client drained half of every lane.
+            if self.longest_lane() <= config.capacity / 2 {
+                self.full_since = None;
+            }
+
+            return Some(msg);
+        }
+
+        self.close_frame.take()
+    }
+
+    /// Pop the next message of the first lane, in the reliable, latest and
+    /// droppable order, with messages and credits left in the round. A new
+    /// round starts once no lane with messages has credits left.
+    fn pop_weighted(&mut self, config: &OutboundQueueConfig) -> Option<axum::Message> {
+        for _ in 0..2 {
+            if self.credits[0] > 0 && !self.reliable.is_empty() {
+                self.credits[0] -= 1;
+                return self.reliable.pop_front().map(|(msg, _)| msg);
+            }
+            if self.credits[1] > 0 && !self.latest.is_empty() {
+                self.credits[1] -= 1;
+                return self.latest.pop_front().map(|(msg, _)| msg);
+            }
+            if self.credits[2] > 0 && !self.droppable.is_empty() {
+                self.credits[2] -= 1;
+                return self.droppable.pop_front();
+            }
+
+            if self.reliable.is_empty() && self.latest.is_empty() && self.droppable.is_empty() {
+                return None;
+            }
+
+            let weights = &config.weights;
+            self.credits = [
+                weights.reliable.max(1),
+                weights.latest.max(1),
+                weights.droppable.max(1),
+            ];
+        }
+
+        None
+    }
+
+    fn longest_lane(&self) -> usize {
+        self.reliable
+            .len()
+            .max(self.latest.len())
+            .max(self.droppable.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(weights: LaneWeights) -> OutboundQueue {
+        let config = OutboundQueueConfig {
+            capacity: 8,
+            weights,
+            ..Default::default()
+        };
+
+        OutboundQueue::new(config, Arc::new(OutboundQueueStats::default()))
+    }
+
+    fn backpressured_queue(reliable_limit: usize, backpressure_timeout: Duration) -> OutboundQueue {
+        let config = OutboundQueueConfig {
+            capacity: 8,
+            reliable_limit,
+            backpressure_timeout,
+            ..Default::default()
+        };
+
+        OutboundQueue::new(config, Arc::new(OutboundQueueStats::default()))
+    }
+
+    fn is_backpressure_close(msg: Option<axum::Message>) -> bool {
+        match msg {
+            Some(axum::Message::Close(Some(frame))) => frame.code == axum::close_code::AGAIN,
+            _ => false,
+        }
+    }
+
+    fn push(queue: &OutboundQueue, n: u8, lane: Lane) {
+        queue
+            .push_lane(axum::Message::Binary(vec![n]), lane)
+            .unwrap();
+    }
+
+    fn presence(client: u32) -> CoalesceKey {
+        CoalesceKey::Service(RealtimeService::Presence, client.to_string())
+    }
+
+    /// Pop the queued messages in the order they are sent.
+    fn drain(queue: &OutboundQueue) -> Vec<u8> {
+        let mut sent = Vec::new();
+        while let Some(msg) = queue.try_pop() {
+            if let axum::Message::Binary(bytes) = msg {
+                sent.extend(bytes);
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn keyed_reliable_drops_the_stale_latest() {
+        let queue = queue(LaneWeights {
+            reliable: 1,
+            latest: 1,
+            droppable: 1,
+        });
+
+        // updated(7), updated(8), then left(7).
+        push(&queue, 1, Lane::Latest(presence(7)));
+        push(&queue, 2, Lane::Latest(presence(8)));
+        push(&queue, 3, Lane::ReliableKeyed(presence(7)));
+
+        assert_eq!(drain(&queue), vec![3, 2]);
+    }
+
+    #[test]
+    fn latest_follows_the_queued_keyed_reliable() {
+        let queue = queue(LaneWeights {
+            reliable: 1,
+            latest: 1,
+            droppable: 1,
+        });
+
+        // the latest lane is served after a single reliable message, the
+        // update of the joined participant still follows its join.
+        push(&queue, 1, Lane::Reliable);
+        push(&queue, 2, Lane::ReliableKeyed(presence(7)));
+        push(&queue, 3, Lane::Latest(presence(7)));
+        push(&queue, 4, Lane::Latest(presence(8)));
+
+        assert_eq!(drain(&queue), vec![1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn latest_is_coalesced_once_the_keyed_reliable_is_sent() {
+        let queue = queue(LaneWeights {
+            reliable: 1,
+            latest: 1,
+            droppable: 1,
+        });
+
+        push(&queue, 1, Lane::ReliableKeyed(presence(7)));
+        assert_eq!(drain(&queue), vec![1]);
+
+        push(&queue, 2, Lane::Latest(presence(7)));
+        push(&queue, 3, Lane::Latest(presence(7)));
+        assert_eq!(drain(&queue), vec![3]);
+    }
+
+    #[test]
+    fn reliable_limit_fails_the_queue() {
+        let queue = backpressured_queue(16, Duration::from_secs(60));
+
+        for n in 0..16 {
+            push(&queue, n, Lane::Reliable);
+        }
+
+        let pushed = queue.push_lane(axum::Message::Binary(vec![16]), Lane::Reliable);
+        assert!(matches!(pushed, Err(OutboundQueueError::Backpressure)));
+
+        // the queued messages are dropped, only the close frame is sent.
+        assert!(is_backpressure_close(queue.try_pop()));
+        assert!(queue.try_pop().is_none());
+    }
+
+    #[test]
+    fn expired_backpressure_fails_the_queue_on_pop() {
+        let queue = backpressured_queue(16, Duration::ZERO);
+
+        // the lane becomes full, no more messages are pushed.
+        for n in 0..9 {
+            push(&queue, n, Lane::Reliable);
+        }
+
+        assert!(is_backpressure_close(queue.try_pop()));
+        assert!(queue.try_pop().is_none());
+        assert_eq!(queue.shared.stats.counters().disconnected, 1);
+    }
+
+    #[test]
+    fn drained_backpressure_never_fails_the_queue() {
+        let queue = backpressured_queue(16, Duration::from_secs(60));
+
+        for n in 0..9 {
+            push(&queue, n, Lane::Reliable);
+        }
+
+        assert_eq!(drain(&queue), (0..9).collect::<Vec<_>>());
+        push(&queue, 9, Lane::Reliable);
+        assert_eq!(drain(&queue), vec![9]);
+    }
+}
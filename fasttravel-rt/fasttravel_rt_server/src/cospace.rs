@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 mod cospace_actor;
 mod cospace_manager;
@@ -65,10 +66,12 @@ pub(crate) struct ServiceMessage {
     pub(crate) sender: RealtimeService,
     pub(crate) route: ServiceMessageRoute,
     pub(crate) payload: MessagePayload,
+    /// delivery class, the outbound lane of the client the message is queued in.
+    pub(crate) delivery: Delivery,
 }
 
 impl factor::MessageCluster for ServiceMessage {
-    type Result = Option<Vec<u8>>;
+    type Result = Result<Vec<u8>, ProtoResponseError>;
 }
 
 /// The message recipient(s) of a client message.
@@ -107,20 +110,75 @@ pub(crate) struct ClientMessage {
     pub(crate) payload: MessagePayload,
 }
 
-// Send command to the client actors.
-pub(crate) enum _ClientCommands {
-    // e.g. if Ticket Authetication failed or HeartBeat Failed.
-    Disconnect(ClientId),
+impl factor::MessageCluster for ClientMessage {
+    type Result = Result<Vec<u8>, ProtoResponseError>;
 }
 
-impl factor::MessageCluster for ClientMessage {
-    type Result = Option<Vec<u8>>;
+/// Command sent to the client connection actors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum ClientCommand {
+    /// Close the client connection with the reason,
+    /// e.g. if the cospace terminated or ticket authentication failed.
+    Disconnect { reason: String },
+    /// Result of the ticket handshake, sent by the connection service, and
+    /// whether the batching feature was negotiated.
+    Handshake { success: bool, batching: bool },
+    /// The handshake deadline of the connection passed.
+    HandshakeDeadline,
+}
+
+impl factor::MessageCluster for ClientCommand {
+    type Result = ();
 }
 
-/// Message requesting to generate a unique id for a new client connection.
+/// Message requesting the collaborative space to disconnect all the clients
+/// with the close reason, and release the clients and services.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct CloseCospaceMessage {
+    pub(crate) reason: String,
+}
+
+impl factor::MessageCluster for CloseCospaceMessage {
+    type Result = ();
+}
+
+/// Message sent by services to update the state of a client that the
+/// collaborative space keeps for routing (e.g. the activity of the client).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ClientStateMessage {
+    pub(crate) client: ClientId,
+    pub(crate) update: ClientStateUpdate,
+}
+
+impl factor::MessageCluster for ClientStateMessage {
+    type Result = ();
+}
+
+/// Message requesting the occupancy of the collaborative space.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct CospaceOccupancyMessage;
+
+impl factor::MessageCluster for CospaceOccupancyMessage {
+    type Result = CospaceOccupancy;
+}
+
+/// Occupancy of the collaborative space.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CospaceOccupancy {
+    /// number of connected clients.
+    pub(crate) clients: usize,
+    /// duration since the last client disconnected (or since the cospace
+    /// started), None if clients are connected.
+    pub(crate) idle: Option<Duration>,
+}
+
+/// Message requesting to generate a unique id for a new client connection
+/// of the user with the verified identity.
 /// Ids are unique within a collaborative space, not globally.
 #[derive(Serialize, Deserialize, Clone)]
-pub(crate) struct GenerateClientIdMessage;
+pub(crate) struct GenerateClientIdMessage {
+    pub(crate) identity: ClientIdentity,
+}
 impl factor::MessageCluster for GenerateClientIdMessage {
     type Result = ClientId;
 }
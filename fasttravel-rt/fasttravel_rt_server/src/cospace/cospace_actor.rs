@@ -1,14 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 use factor;
 use fasttravel_rt_proto::RealtimeService;
-use fasttravel_rt_services::{ClientId, CospaceId, ServiceMessageRecipient, Services};
+use fasttravel_rt_services::{
+    ClientId, ClientIdentity, ClientRole, ClientStateUpdate, ClientTopics, CospaceId,
+    ProtoResponseError, ServiceMessageRecipient, ServiceTopics, Services,
+};
 
 use super::{
     service_pool::{ServicePool, ServicePoolMessenger},
-    ClientConnectionMessage, ClientMessage, ClientMessageRecipient, ClientMessageRoute,
-    GenerateClientIdMessage, ModelRoot, ServiceMessage, ServiceMessageRoute,
+    ClientCommand, ClientConnectionMessage, ClientMessage, ClientMessageRecipient,
+    ClientMessageRoute, ClientStateMessage, CloseCospaceMessage, CospaceOccupancy,
+    CospaceOccupancyMessage, GenerateClientIdMessage, MessagePayload, ModelRoot, ServiceMessage,
+    ServiceMessageRoute,
 };
+use crate::ClientConnectionActor;
+
+/// A client connected to the collaborative space, along with the
+/// client state that the services maintain for routing.
+#[derive(Clone)]
+struct ConnectedClient {
+    addr: factor::ActorAddr<ClientConnectionActor>,
+    identity: ClientIdentity,
+    /// the activity the client participates in.
+    activity: Option<String>,
+    /// the named topics the client subscribed to.
+    topics: HashSet<String>,
+}
+
+impl ConnectedClient {
+    /// Check if the client is subscribed to the broadcast topic.
+    fn is_subscribed(&self, topic: &ClientTopics) -> bool {
+        match topic {
+            ClientTopics::Cospace(_) => true,
+            ClientTopics::Activity(_, activity) => self.activity.as_ref() == Some(activity),
+            ClientTopics::Named(_, topic) => self.topics.contains(topic),
+        }
+    }
+}
 
 /// An actor representing a collaborative space.
 /// A collaborative space host multiple clients and a model-root.
@@ -20,7 +50,11 @@ pub(crate) struct CospaceActor {
     model_root: ModelRoot,
     client_id_counter: u32,
     services: ServicePool,
-    clients: HashMap<u32, factor::MessageClusterAddr<ServiceMessage>>,
+    clients: HashMap<u32, ConnectedClient>,
+    /// ids of the clients subscribed to the named topics.
+    subscribers: HashMap<String, HashSet<u32>>,
+    /// instant since when no clients are connected.
+    idle_since: Option<Instant>,
 }
 
 impl CospaceActor {
@@ -31,6 +65,41 @@ impl CospaceActor {
             client_id_counter: 1,
             services,
             clients: HashMap::new(),
+            subscribers: HashMap::new(),
+            idle_since: Some(Instant::now()),
+        }
+    }
+
+    /// The clients subscribed to the broadcast topic. The subscribers of
+    /// the named topics are looked up instead of checking all the clients.
+    fn subscribed_clients<'a>(
+        &'a self, topic: &'a ClientTopics,
+    ) -> Box<dyn Iterator<Item = &'a ConnectedClient> + 'a> {
+        match topic {
+            ClientTopics::Named(_, name) => Box::new(
+                self.subscribers
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(move |id| self.clients.get(id)),
+            ),
+            _ => Box::new(
+                self.clients
+                    .values()
+                    .filter(move |connected| connected.is_subscribed(topic)),
+            ),
+        }
+    }
+
+    /// Remove the client from the subscribers of the topics.
+    fn unsubscribe(&mut self, id: u32, topics: &HashSet<String>) {
+        for topic in topics {
+            if let Some(subscribers) = self.subscribers.get_mut(topic) {
+                subscribers.remove(&id);
+                if subscribers.is_empty() {
+                    self.subscribers.remove(topic);
+                }
+            }
         }
     }
 }
@@ -44,10 +113,11 @@ impl factor::MessageClusterHandler<GenerateClientIdMessage> for CospaceActor {
     type Result =
         factor::MessageResponseType<<GenerateClientIdMessage as factor::MessageCluster>::Result>;
 
-    fn handle(&mut self, _msg: GenerateClientIdMessage, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: GenerateClientIdMessage, _ctx: &mut Self::Context) -> Self::Result {
         let id = ClientId {
             id: self.client_id_counter,
             cospace: self.cospace.clone(),
+            identity: msg.identity,
         };
         self.client_id_counter += 1;
 
@@ -67,17 +137,21 @@ impl factor::MessageClusterHandler<ServiceMessage> for CospaceActor {
             ServiceMessageRoute::Tell(recipient) => {
                 match recipient {
                     ServiceMessageRecipient::Client(client) => {
-                        if let Some(addr) = self.clients.get(&client.id) {
-                            addr.tell(msg).map_err(|e| tracing::error!(target: "server-event", "cospace_tell_to_client_failed: {}", e)).err();
+                        if let Some(connected) = self.clients.get(&client.id) {
+                            connected.addr.tell_addr(msg).map_err(|e| tracing::error!(target: "server-event", "cospace_tell_to_client_failed: {}", e)).err();
                         } else {
                             tracing::error!(target: "server-event", "receipient_client_id_not_found_in_cospace");
                         }
                     }
-                    ServiceMessageRecipient::Broadcast(_topic) => {
-                        // [todo] add role-based authorization control to both
-                        // subscription and dispatch of broascast events and streams.
-                        for addr in self.clients.values() {
-                            let _ = addr.tell(msg.clone());
+                    ServiceMessageRecipient::Broadcast(topic) => {
+                        // only the clients subscribed to the topic, whose role the
+                        // policy of the sender service allows, receive the message.
+                        let service = get_server_service_from_realtime_service(&msg.sender);
+                        for connected in self
+                            .subscribed_clients(topic)
+                            .filter(|connected| service.can_receive(connected.identity.role, topic))
+                        {
+                            let _ = connected.addr.tell_addr(msg.clone());
                         }
                     }
                 }
@@ -86,29 +160,33 @@ impl factor::MessageClusterHandler<ServiceMessage> for CospaceActor {
                 let ret = self
                     .clients
                     .get(&client.id)
-                    .map(|addr| {
-                        let addr_moved = addr.clone();
+                    .map(|connected| {
+                        let addr_moved = connected.addr.clone();
 
                         let response = Box::pin(async move {
                             addr_moved
-                                .ask(msg)
+                                .ask_addr(msg)
                                 .await
                                 .map_err(|e| {
                                     tracing::error!(target: "server-event", "cospace_actor_handle_service_msg_ask_client_error: {:?}", e)
                                 })
-                                .unwrap_or(None)
+                                .unwrap_or(Err(ProtoResponseError::Failed))
                         });
 
                         factor::MessageResponseType::Future(response)
                     })
-                    .unwrap_or(factor::MessageResponseType::Result(None.into()));
+                    .unwrap_or_else(|| {
+                        factor::MessageResponseType::Result(
+                            Err(ProtoResponseError::Disconnected).into(),
+                        )
+                    });
 
                 return ret;
             }
             _ => {}
         }
 
-        factor::MessageResponseType::Result(None.into())
+        factor::MessageResponseType::Result(Err(ProtoResponseError::Failed).into())
     }
 }
 
@@ -120,11 +198,26 @@ impl factor::MessageClusterHandler<ClientConnectionMessage> for CospaceActor {
     fn handle(&mut self, msg: ClientConnectionMessage, _ctx: &mut Self::Context) -> Self::Result {
         match &msg {
             ClientConnectionMessage::Connect { client, addr } => {
-                self.clients.insert(client.id, addr.message_cluster_addr());
+                let connected = ConnectedClient {
+                    addr: addr.clone(),
+                    identity: client.identity.clone(),
+                    activity: None,
+                    topics: HashSet::new(),
+                };
+                self.clients.insert(client.id, connected);
+                self.idle_since = None;
                 self.services.broadcast(msg);
             }
             ClientConnectionMessage::Disconnect(client_id) => {
-                self.clients.remove(&client_id.id);
+                // inform the services only of the clients still connected.
+                if let Some(connected) = self.clients.remove(&client_id.id) {
+                    self.unsubscribe(client_id.id, &connected.topics);
+                    self.services.broadcast(msg);
+                }
+
+                if self.clients.is_empty() {
+                    self.idle_since.get_or_insert_with(Instant::now);
+                }
             }
         }
 
@@ -132,27 +225,140 @@ impl factor::MessageClusterHandler<ClientConnectionMessage> for CospaceActor {
     }
 }
 
+// Handle ClientStateMessage requests.
+impl factor::MessageClusterHandler<ClientStateMessage> for CospaceActor {
+    type Result =
+        factor::MessageResponseType<<ClientStateMessage as factor::MessageCluster>::Result>;
+
+    fn handle(&mut self, msg: ClientStateMessage, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(connected) = self.clients.get_mut(&msg.client.id) {
+            match msg.update {
+                ClientStateUpdate::Activity(activity) => connected.activity = activity,
+                ClientStateUpdate::Subscribe(topic) => {
+                    if connected.topics.insert(topic.clone()) {
+                        self.subscribers
+                            .entry(topic)
+                            .or_default()
+                            .insert(msg.client.id);
+                    }
+                }
+                ClientStateUpdate::Unsubscribe(topic) => {
+                    if connected.topics.remove(&topic) {
+                        self.unsubscribe(msg.client.id, &HashSet::from([topic]));
+                    }
+                }
+            }
+        } else {
+            tracing::warn!(target: "server-event", "cospace_client_state_update_client_not_found");
+        }
+
+        factor::MessageResponseType::Result(().into())
+    }
+}
+
+// Handle CospaceOccupancyMessage requests.
+impl factor::MessageClusterHandler<CospaceOccupancyMessage> for CospaceActor {
+    type Result =
+        factor::MessageResponseType<<CospaceOccupancyMessage as factor::MessageCluster>::Result>;
+
+    fn handle(&mut self, _msg: CospaceOccupancyMessage, _ctx: &mut Self::Context) -> Self::Result {
+        let occupancy = CospaceOccupancy {
+            clients: self.clients.len(),
+            idle: self.idle_since.map(|since| since.elapsed()),
+        };
+
+        factor::MessageResponseType::Result(occupancy.into())
+    }
+}
+
+// Handle CloseCospaceMessage requests.
+impl factor::MessageClusterHandler<CloseCospaceMessage> for CospaceActor {
+    type Result =
+        factor::MessageResponseType<<CloseCospaceMessage as factor::MessageCluster>::Result>;
+
+    fn handle(&mut self, msg: CloseCospaceMessage, _ctx: &mut Self::Context) -> Self::Result {
+        tracing::debug!(target: "server-event", "cospace_actor_close: {}", msg.reason);
+
+        // disconnect the clients and inform the services, once the node manager
+        // and the clients release their addresses the actor and its dedicated
+        // service pool stop.
+        self.subscribers.clear();
+        for (id, connected) in self.clients.drain() {
+            let command = ClientCommand::Disconnect {
+                reason: msg.reason.clone(),
+            };
+            connected.addr.tell_addr(command).map_err(|e| tracing::error!(target: "server-event", "cospace_close_tell_client_failed: {}", e)).err();
+
+            let client = ClientId {
+                id,
+                cospace: self.cospace.clone(),
+                identity: connected.identity,
+            };
+            self.services
+                .broadcast(ClientConnectionMessage::Disconnect(client));
+        }
+
+        factor::MessageResponseType::Result(().into())
+    }
+}
+
 // Handle ClientMessage requests.
 impl factor::MessageClusterHandler<ClientMessage> for CospaceActor {
     type Result = factor::MessageResponseType<<ClientMessage as factor::MessageCluster>::Result>;
 
     fn handle(&mut self, msg: ClientMessage, _ctx: &mut Self::Context) -> Self::Result {
+        // the role of the client in the cospace, messages of the clients
+        // not connected to the cospace are dropped.
+        let role = match self.clients.get(&msg.client.id) {
+            Some(connected) => connected.identity.role,
+            None => {
+                tracing::warn!(target: "server-event", "cospace_actor_client_message_from_unknown_client");
+                return factor::MessageResponseType::Result(
+                    Err(ProtoResponseError::Disconnected).into(),
+                );
+            }
+        };
+
         match &msg.route {
             ClientMessageRoute::Tell(recipient) => {
                 match recipient {
                     ClientMessageRecipient::Service(service) => {
                         let service = get_server_service_from_realtime_service(service);
-                        self.services.tell(&service, msg);
+                        if is_client_message_allowed(&service, role, &msg.payload) {
+                            self.services.tell(&service, msg);
+                        } else {
+                            tracing::warn!(target: "server-event", "cospace_actor_client_message_unauthorized: {:?}", role);
+                        }
                     }
-                    ClientMessageRecipient::Broadcast(_topic) => {
-                        // [todo] do not broadcast directly to services. push to topic-streams.
-                        // topic-streams not implemented yet.
-                        self.services.broadcast(msg);
+                    ClientMessageRecipient::Broadcast(topic) => {
+                        // only the services subscribed to the topic, whose policy
+                        // allows the message, receive the message.
+                        let services: Vec<&Services> = get_services_subscribed_to_topic(topic)
+                            .iter()
+                            .filter(|service| {
+                                is_client_message_allowed(service, role, &msg.payload)
+                            })
+                            .collect();
+
+                        if services.is_empty() {
+                            tracing::warn!(target: "server-event", "cospace_actor_client_broadcast_unauthorized: {:?}", role);
+                        }
+
+                        for service in services {
+                            self.services.tell(service, msg.clone());
+                        }
                     }
                 }
             }
             ClientMessageRoute::Ask(service) => {
                 let service = get_server_service_from_realtime_service(service);
+                if !is_client_message_allowed(&service, role, &msg.payload) {
+                    tracing::warn!(target: "server-event", "cospace_actor_client_request_unauthorized: {:?}", role);
+                    return factor::MessageResponseType::Result(
+                        Err(ProtoResponseError::Unauthorized).into(),
+                    );
+                }
+
                 if let Some(addr) = self.services.message_addr(&service) {
                     let response = Box::pin(async move {
                         addr.ask(msg)
@@ -160,7 +366,7 @@ impl factor::MessageClusterHandler<ClientMessage> for CospaceActor {
                             .map_err(|e| {
                                 tracing::error!(target: "server-event", "cospace_actor_handle_client_msg_ask_service_error: {:?}", e)
                             })
-                            .unwrap_or(None)
+                            .unwrap_or(Err(ProtoResponseError::Failed))
                     });
 
                     return factor::MessageResponseType::Future(response);
@@ -169,7 +375,33 @@ impl factor::MessageClusterHandler<ClientMessage> for CospaceActor {
             _ => {}
         }
 
-        factor::MessageResponseType::Result(None.into())
+        factor::MessageResponseType::Result(Err(ProtoResponseError::Failed).into())
+    }
+}
+
+/// The services of the service pool that receive the client broadcasts.
+const POOL_SERVICES: [Services; 4] = [
+    Services::Core,
+    Services::Presence,
+    Services::Activity,
+    Services::Model,
+];
+
+/// Get the services of the service pool subscribed to the client broadcast topic.
+fn get_services_subscribed_to_topic(topic: &ServiceTopics) -> &'static [Services] {
+    match topic {
+        ServiceTopics::Default => &POOL_SERVICES,
+    }
+}
+
+/// Check the policy of the service for the client message. Text payloads
+/// are only used for traces and are allowed.
+fn is_client_message_allowed(
+    service: &Services, role: ClientRole, payload: &MessagePayload,
+) -> bool {
+    match payload {
+        MessagePayload::Binary(bytes) => service.can_send(role, bytes),
+        MessagePayload::Text(_) => true,
     }
 }
 
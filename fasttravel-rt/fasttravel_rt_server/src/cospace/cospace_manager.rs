@@ -1,13 +1,17 @@
 use dashmap::DashMap;
+use futures::future;
+use futures_timer::Delay;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 use factor::{self, ActorReceiverContext, SystemRef};
 use fasttravel_rt_services::{CospaceId, ModelRoot};
 
 use crate::{
-    CospaceActor, ServiceAllocation, ServiceMessage, ServicePool, ServicesConfig, WorkerNodesConfig,
+    CloseCospaceMessage, CospaceActor, CospaceLifetimeConfig, CospaceLifetimePolicy,
+    CospaceOccupancyMessage, ServiceAllocation, ServicePool, ServicesConfig, WorkerNodesConfig,
 };
 
 #[derive(Clone)]
@@ -18,13 +22,16 @@ pub(crate) struct CospaceManager {
 impl CospaceManager {
     pub(crate) async fn new(
         config_workers: WorkerNodesConfig, config_services: ServicesConfig,
-        system: &factor::SystemRef,
+        config_lifetime: CospaceLifetimeConfig, system: &factor::SystemRef,
     ) -> Self {
-        Self {
-            inner: Arc::new(
-                CospaceManagerInner::new(config_workers, config_services, system).await,
-            ),
-        }
+        let inner = Arc::new(
+            CospaceManagerInner::new(config_workers, config_services, config_lifetime, system)
+                .await,
+        );
+
+        system.spawn_ok(Self::sweep_cospaces_periodically(Arc::downgrade(&inner)));
+
+        Self { inner }
     }
 
     pub(crate) fn hosted_cospaces(&self) -> &HostedCospaces {
@@ -46,7 +53,7 @@ impl CospaceManager {
         };
         let cospace_id_moved = cospace_id.clone();
         let hosted_cospaces_handle = self.inner.hosted_cospaces.clone();
-        let alloc = ResourceAllocation::Dedicated;
+        let policy = self.inner.config_lifetime.dedicated;
         let system = self.inner.system.clone();
 
         let task = async move {
@@ -55,18 +62,34 @@ impl CospaceManager {
                 hosted_cospaces_handle.failed(cospace_id_moved.clone());
             })?;
 
+            // the creation deadline passed while the worker node was spawning.
+            if !hosted_cospaces_handle.is_scheduled(&cospace_id_moved.uuid) {
+                tracing::warn!(target: "server-event", "dedicated_node_spawned_after_creation_deadline");
+                system.shutdown_worker_node(&node_id).await;
+
+                return Err(());
+            }
+
             if let Some(node_mgr_addr) = system
                 .get_remote_addr::<CospaceNodeManager>(node_id, crate::NODE_MANAGER_TAG)
                 .await
             {
-                Self::spawn_cospace_in_node(
+                let hosted = Self::spawn_cospace_in_node(
                     cospace_id_moved,
                     model_root,
                     node_mgr_addr,
-                    alloc,
+                    ResourceAllocation::Dedicated(node_id),
+                    policy,
                     hosted_cospaces_handle,
                 )
                 .await;
+
+                // handle failure
+                if hosted.is_err() {
+                    system.shutdown_worker_node(&node_id).await;
+
+                    return Err(());
+                }
             } else {
                 tracing::error!(target: "server-event", "get_remote_addr_dedicated_node_mgr_failed");
 
@@ -102,13 +125,15 @@ impl CospaceManager {
         let hosted_cospaces_handle = self.inner.hosted_cospaces.clone();
         let node_mgr_addr = self.inner.main_node_mgr.clone();
         let alloc = ResourceAllocation::Shared;
+        let policy = self.inner.config_lifetime.main;
 
         let task = async move {
-            Self::spawn_cospace_in_node(
+            let _ = Self::spawn_cospace_in_node(
                 cospace_id_moved,
                 model_root,
                 node_mgr_addr,
                 alloc,
+                policy,
                 hosted_cospaces_handle,
             )
             .await;
@@ -129,13 +154,15 @@ impl CospaceManager {
         let hosted_cospaces_handle = self.inner.hosted_cospaces.clone();
         let node_mgr_addr = self.inner.shared_node_mgr.clone();
         let alloc = ResourceAllocation::Shared;
+        let policy = self.inner.config_lifetime.shared;
 
         let task = async move {
-            Self::spawn_cospace_in_node(
+            let _ = Self::spawn_cospace_in_node(
                 cospace_id_moved,
                 model_root,
                 node_mgr_addr,
                 alloc,
+                policy,
                 hosted_cospaces_handle,
             )
             .await;
@@ -148,40 +175,152 @@ impl CospaceManager {
         Ok(cospace_id)
     }
 
+    /// Request the node manager to create the cospace actor. On failure the
+    /// cospace is marked as failed, the caller releases the allocated resources.
     async fn spawn_cospace_in_node(
         cospace_id: CospaceId, model_root: ModelRoot,
         node_mgr_addr: factor::ActorAddr<CospaceNodeManager>, alloc: ResourceAllocation,
-        hosted_cospaces_handle: HostedCospaces,
-    ) {
+        policy: CospaceLifetimePolicy, hosted_cospaces_handle: HostedCospaces,
+    ) -> Result<(), ()> {
         let msg = CreateCospaceActorMessage::new(cospace_id.clone(), model_root);
 
         // request cospace actor creation
         match node_mgr_addr.ask_addr(msg).await {
             Ok(Some(addr_actor)) => {
-                let cospace = match alloc {
-                    ResourceAllocation::Dedicated => HostedCospace::new_dedicated(addr_actor),
-                    ResourceAllocation::Shared => HostedCospace::new_shared(addr_actor),
-                };
-                hosted_cospaces_handle.insert_cospace(cospace_id, cospace);
+                let cospace = HostedCospace::new(addr_actor, node_mgr_addr.clone(), alloc, policy);
+                if hosted_cospaces_handle.insert_cospace(cospace_id.clone(), cospace) {
+                    return Ok(());
+                }
+
+                // the creation deadline passed and the cospace is already marked
+                // as failed, release the late cospace actor.
+                tracing::warn!(target: "server-event", "cospace_created_after_creation_deadline: {}", cospace_id.uuid);
+
+                let reason = "cospace_creation_timeout".to_string();
+                let msg = TerminateCospaceActorMessage::new(cospace_id, reason);
+                if let Err(e) = node_mgr_addr.ask_addr(msg).await {
+                    tracing::error!(target: "server-event", "error_in_ask_terminate_cospace: {}", e);
+                }
             }
             Ok(None) => {
                 tracing::error!(target: "server-event", "error_in_ask_create_cospace: ask_returns_None");
+                hosted_cospaces_handle.failed(cospace_id);
             }
             Err(e) => {
                 tracing::error!(target: "server-event", "error_in_ask_create_cospace: {}", e);
+                hosted_cospaces_handle.failed(cospace_id);
+            }
+        };
+
+        Err(())
+    }
+
+    /// Terminate a hosted cospace: the clients are disconnected with the reason,
+    /// the node manager releases the cospace actor and its services, and the
+    /// worker node of a dedicated cospace is shut down.
+    pub(crate) fn terminate_cospace(&self, cospace_id: &CospaceId, reason: &str) {
+        let cospace = match self.inner.hosted_cospaces.remove_cospace(cospace_id) {
+            Some(cospace) => cospace,
+            None => {
+                tracing::warn!(target: "server-event", "terminate_cospace_not_hosted: {}", cospace_id.uuid);
+                return;
+            }
+        };
+
+        let msg = TerminateCospaceActorMessage::new(cospace_id.clone(), reason.to_string());
+        let system = self.inner.system.clone();
+
+        let task = async move {
+            // wait for the node manager so that the clients are notified
+            // before the worker node is shut down.
+            match cospace.node_mgr.ask_addr(msg).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::warn!(target: "server-event", "terminate_cospace_not_found_in_node_mgr");
+                }
+                Err(e) => {
+                    tracing::error!(target: "server-event", "error_in_ask_terminate_cospace: {}", e);
+                }
+            }
+
+            if let ResourceAllocation::Dedicated(node_id) = cospace.resource_alloc {
+                system.shutdown_worker_node(&node_id).await;
             }
         };
+
+        self.inner.system.spawn_ok(task);
     }
 
-    pub(crate) fn _terminate_cospace(&self, cospace_id: &CospaceId) {
-        // [todo] (1) inform the actor, the node_mgr.
-        // [todo] (2) terminate the worker_node if cospace is dedicated.
-        self.inner.hosted_cospaces._remove_cospace(cospace_id)
+    /// Periodically terminate the cospaces that are idle or reached the max
+    /// lifetime. Stops once the cospace manager is dropped.
+    async fn sweep_cospaces_periodically(inner: Weak<CospaceManagerInner>) {
+        loop {
+            let interval = match inner.upgrade() {
+                Some(inner) => inner.config_lifetime.sweep_interval,
+                None => return,
+            };
+
+            Delay::new(interval).await;
+
+            match inner.upgrade() {
+                Some(inner) => Self { inner }.sweep_cospaces().await,
+                None => return,
+            }
+        }
+    }
+
+    async fn sweep_cospaces(&self) {
+        let config = &self.inner.config_lifetime;
+        let hosted_cospaces = &self.inner.hosted_cospaces;
+
+        hosted_cospaces.fail_overdue(config.creation_timeout);
+        hosted_cospaces.purge_expired(config.retention_period);
+
+        let mut occupancy_asks = Vec::new();
+        for (cospace_id, cospace) in self.inner.hosted_cospaces.lifetimes() {
+            let policy = cospace.policy;
+
+            if let Some(max_lifetime) = policy.max_lifetime {
+                if cospace.hosted_at.elapsed() >= max_lifetime {
+                    self.terminate_cospace(&cospace_id, "cospace_max_lifetime_reached");
+                    continue;
+                }
+            }
+
+            if let Some(idle_timeout) = policy.idle_timeout {
+                let addr = cospace.addr_actor;
+                let timeout = Delay::new(config.occupancy_timeout);
+                occupancy_asks.push(async move {
+                    let ask = addr.ask_addr(CospaceOccupancyMessage);
+                    let answer = future::select(Box::pin(ask), timeout).await;
+                    (cospace_id, idle_timeout, answer)
+                });
+            }
+        }
+
+        // the occupancies are asked concurrently, an unresponsive cospace must
+        // not stall the sweep of the others.
+        for (cospace_id, idle_timeout, answer) in future::join_all(occupancy_asks).await {
+            match answer {
+                future::Either::Left((Ok(occupancy), _)) => {
+                    if matches!(occupancy.idle, Some(idle) if idle >= idle_timeout) {
+                        self.terminate_cospace(&cospace_id, "cospace_idle_timeout");
+                    }
+                }
+                future::Either::Left((Err(e), _)) => {
+                    tracing::error!(target: "server-event", "error_in_ask_cospace_occupancy: {}", e);
+                }
+                future::Either::Right(_) => {
+                    tracing::warn!(target: "server-event", "ask_cospace_occupancy_timeout: {}", cospace_id.uuid);
+                }
+            }
+        }
     }
 }
 
 struct CospaceManagerInner {
     config_workers: WorkerNodesConfig,
+    config_lifetime: CospaceLifetimeConfig,
     system: SystemRef,
     main_node_mgr: factor::ActorAddr<CospaceNodeManager>,
     shared_node_mgr: factor::ActorAddr<CospaceNodeManager>,
@@ -191,7 +330,7 @@ struct CospaceManagerInner {
 impl CospaceManagerInner {
     async fn new(
         config_workers: WorkerNodesConfig, config_services: ServicesConfig,
-        system: &factor::SystemRef,
+        config_lifetime: CospaceLifetimeConfig, system: &factor::SystemRef,
     ) -> Self {
         // main_node manager
         let config = factor::ActorBuilderConfig::default();
@@ -223,6 +362,7 @@ impl CospaceManagerInner {
 
         Self {
             config_workers,
+            config_lifetime,
             system: system.clone(),
             main_node_mgr,
             shared_node_mgr,
@@ -254,6 +394,25 @@ impl factor::MessageCluster for CreateCospaceActorMessage {
     type Result = Option<factor::ActorAddr<CospaceActor>>;
 }
 
+/// Message requesting to terminate a collaboration space, the clients
+/// are disconnected with the reason.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct TerminateCospaceActorMessage {
+    cospace_id: CospaceId,
+    reason: String,
+}
+
+impl TerminateCospaceActorMessage {
+    pub(crate) fn new(cospace_id: CospaceId, reason: String) -> Self {
+        Self { cospace_id, reason }
+    }
+}
+
+impl factor::MessageCluster for TerminateCospaceActorMessage {
+    /// false if the cospace is not hosted by the node.
+    type Result = bool;
+}
+
 #[derive(Clone)]
 pub(crate) enum SpawnConfig {
     Dedicated,
@@ -266,7 +425,7 @@ pub(crate) enum SpawnConfig {
 pub(crate) struct CospaceNodeManager {
     config_services: ServicesConfig,
     config_spawn: SpawnConfig,
-    cospaces: Arc<DashMap<Uuid, factor::MessageClusterAddr<ServiceMessage>>>,
+    cospaces: Arc<DashMap<Uuid, factor::ActorAddr<CospaceActor>>>,
 }
 impl CospaceNodeManager {
     pub(crate) fn dedicated(config_services: ServicesConfig) -> Self {
@@ -294,7 +453,6 @@ impl factor::ActorReceiver for CospaceNodeManager {
     type Context = factor::BasicContext<Self>;
 }
 
-// [todo] TerminateCospaceActorMessage
 impl factor::MessageClusterHandler<CreateCospaceActorMessage> for CospaceNodeManager {
     type Result =
         factor::MessageResponseType<<CreateCospaceActorMessage as factor::MessageCluster>::Result>;
@@ -337,54 +495,98 @@ impl factor::MessageClusterHandler<CreateCospaceActorMessage> for CospaceNodeMan
             }
         };
 
-        self.cospaces
-            .insert(cospace_uuid, addr.message_cluster_addr());
+        self.cospaces.insert(cospace_uuid, addr.clone());
 
         factor::MessageResponseType::Result(Some(addr).into())
     }
 }
 
+impl factor::MessageClusterHandler<TerminateCospaceActorMessage> for CospaceNodeManager {
+    type Result = factor::MessageResponseType<
+        <TerminateCospaceActorMessage as factor::MessageCluster>::Result,
+    >;
+
+    fn handle(
+        &mut self, msg: TerminateCospaceActorMessage, _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        tracing::debug!(target: "server-event", "cospace_node_mgr_handle_terminate_cospace_actor_msg: {:#?}", msg.cospace_id);
+
+        // removing the address from the map releases the node manager's (and the
+        // shared services') reference to the cospace actor.
+        match self.cospaces.remove(&msg.cospace_id.uuid) {
+            Some((_, addr)) => {
+                let close = CloseCospaceMessage { reason: msg.reason };
+                let response = Box::pin(async move {
+                    addr.ask_addr(close)
+                        .await
+                        .map_err(|e| {
+                            tracing::error!(target: "server-event", "cospace_node_mgr_close_cospace_error: {:?}", e)
+                        })
+                        .is_ok()
+                });
+
+                factor::MessageResponseType::Future(response)
+            }
+            None => factor::MessageResponseType::Result(false.into()),
+        }
+    }
+}
+
 /// Details of the scheduled collaboration space creation request.
 struct CospaceCreationRequest {
     _id: CospaceId,
-    _timestamp: chrono::DateTime<chrono::Utc>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    failed_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl CospaceCreationRequest {
     fn new(cospace_id: CospaceId) -> Self {
         Self {
             _id: cospace_id,
-            _timestamp: chrono::offset::Utc::now(),
+            timestamp: chrono::offset::Utc::now(),
+            failed_at: None,
         }
     }
 }
 
+/// Whether the duration has elapsed since the timestamp.
+fn elapsed_since(timestamp: &chrono::DateTime<chrono::Utc>, duration: Duration) -> bool {
+    let elapsed = chrono::offset::Utc::now() - *timestamp;
+    matches!(elapsed.to_std(), Ok(elapsed) if elapsed >= duration)
+}
+
+#[derive(Clone)]
 enum ResourceAllocation {
-    Dedicated,
+    /// the worker node dedicated to the cospace.
+    Dedicated(factor::NodeId),
     Shared,
 }
 
+#[derive(Clone)]
 struct Tenant {}
 
+#[derive(Clone)]
 struct HostedCospace {
-    _tenant: Tenant,                     // future use
-    _resource_alloc: ResourceAllocation, // will be used in terminate
+    _tenant: Tenant, // future use
+    resource_alloc: ResourceAllocation,
+    policy: CospaceLifetimePolicy,
+    hosted_at: Instant,
+    node_mgr: factor::ActorAddr<CospaceNodeManager>,
     addr_actor: factor::ActorAddr<CospaceActor>,
 }
 
 impl HostedCospace {
-    fn new_dedicated(addr_actor: factor::ActorAddr<CospaceActor>) -> Self {
-        Self {
-            _tenant: Tenant {},
-            _resource_alloc: ResourceAllocation::Dedicated,
-            addr_actor,
-        }
-    }
-
-    fn new_shared(addr_actor: factor::ActorAddr<CospaceActor>) -> Self {
+    fn new(
+        addr_actor: factor::ActorAddr<CospaceActor>,
+        node_mgr: factor::ActorAddr<CospaceNodeManager>, resource_alloc: ResourceAllocation,
+        policy: CospaceLifetimePolicy,
+    ) -> Self {
         Self {
             _tenant: Tenant {},
-            _resource_alloc: ResourceAllocation::Shared,
+            resource_alloc,
+            policy,
+            hosted_at: Instant::now(),
+            node_mgr,
             addr_actor,
         }
     }
@@ -406,6 +608,7 @@ struct HostedCospacesInner {
     scheduled: DashMap<Uuid, CospaceCreationRequest>,
     failed: DashMap<Uuid, CospaceCreationRequest>,
     cospaces: DashMap<Uuid, HostedCospace>,
+    ended: DashMap<Uuid, chrono::DateTime<chrono::Utc>>,
 }
 
 impl HostedCospaces {
@@ -415,6 +618,7 @@ impl HostedCospaces {
                 scheduled: DashMap::new(),
                 failed: DashMap::new(),
                 cospaces: DashMap::new(),
+                ended: DashMap::new(),
             }),
         }
     }
@@ -438,25 +642,77 @@ impl HostedCospaces {
         self.inner.cospaces.contains_key(uuid)
     }
 
+    pub(crate) fn is_ended(&self, uuid: &Uuid) -> bool {
+        self.inner.ended.contains_key(uuid)
+    }
+
     pub(crate) fn scheduled(&self, cospace_id: CospaceId) {
         self.inner
             .scheduled
             .insert(cospace_id.uuid, CospaceCreationRequest::new(cospace_id));
-        // [todo] schedule a task to remove this and move to failed after timeout.
     }
 
     fn failed(&self, cospace_id: CospaceId) {
-        if let Some(pair) = self.inner.scheduled.remove(&cospace_id.uuid) {
-            self.inner.failed.insert(pair.0, pair.1);
+        if let Some((uuid, mut request)) = self.inner.scheduled.remove(&cospace_id.uuid) {
+            request.failed_at = Some(chrono::offset::Utc::now());
+            self.inner.failed.insert(uuid, request);
         }
     }
 
-    fn insert_cospace(&self, cospace_id: CospaceId, cospace: HostedCospace) {
+    /// Insert the created cospace, false if it is no longer scheduled
+    /// (creation deadline passed).
+    fn insert_cospace(&self, cospace_id: CospaceId, cospace: HostedCospace) -> bool {
+        if self.inner.scheduled.remove(&cospace_id.uuid).is_none() {
+            return false;
+        }
+
         self.inner.cospaces.insert(cospace_id.uuid, cospace);
-        self.inner.scheduled.remove(&cospace_id.uuid);
+        true
+    }
+
+    /// Move the scheduled cospaces not created within the timeout to failed.
+    fn fail_overdue(&self, creation_timeout: Duration) {
+        let overdue: Vec<Uuid> = self
+            .inner
+            .scheduled
+            .iter()
+            .filter(|entry| elapsed_since(&entry.timestamp, creation_timeout))
+            .map(|entry| *entry.key())
+            .collect();
+
+        for uuid in overdue {
+            tracing::warn!(target: "server-event", "cospace_creation_deadline_passed: {}", uuid);
+            self.failed(CospaceId { uuid });
+        }
+    }
+
+    /// Forget the failed and ended cospaces after the retention period.
+    fn purge_expired(&self, retention_period: Duration) {
+        self.inner
+            .failed
+            .retain(|_, request| match &request.failed_at {
+                Some(failed_at) => !elapsed_since(failed_at, retention_period),
+                None => true,
+            });
+        self.inner
+            .ended
+            .retain(|_, ended_at| !elapsed_since(ended_at, retention_period));
     }
 
-    pub(crate) fn _remove_cospace(&self, cospace_id: &CospaceId) {
-        self.inner.cospaces.remove(&cospace_id.uuid);
+    /// Remove the hosted cospace and mark it as ended.
+    fn remove_cospace(&self, cospace_id: &CospaceId) -> Option<HostedCospace> {
+        let (uuid, cospace) = self.inner.cospaces.remove(&cospace_id.uuid)?;
+        self.inner.ended.insert(uuid, chrono::offset::Utc::now());
+
+        Some(cospace)
+    }
+
+    /// Snapshot of the hosted cospaces to check their lifetimes.
+    fn lifetimes(&self) -> Vec<(CospaceId, HostedCospace)> {
+        self.inner
+            .cospaces
+            .iter()
+            .map(|entry| (CospaceId { uuid: *entry.key() }, entry.value().clone()))
+            .collect()
     }
 }
@@ -6,13 +6,13 @@ use uuid::Uuid;
 use factor;
 use fasttravel_rt_proto::RealtimeService;
 use fasttravel_rt_services::{
-    ClientId, ClientTopics, ExecutionContext, ProtoBytes, ProtoResponse, ProtoResponseError,
-    Service, ServiceMessageRecipient, Services,
+    ClientId, ClientStateUpdate, Delivery, ExecutionContext, ProtoBytes, ProtoResponse,
+    ProtoResponseError, Service, ServiceMessageRecipient, Services,
 };
 
 use super::{
-    ClientConnectionMessage, ClientMessage, ClientMessageRoute, CospaceActor, MessagePayload,
-    ServiceMessage, ServiceMessageRoute,
+    ClientConnectionMessage, ClientMessage, ClientMessageRoute, ClientStateMessage, CospaceActor,
+    MessagePayload, ServiceMessage, ServiceMessageRoute,
 };
 
 /// Service allocation defines how collaborative spaces are going to
@@ -27,7 +27,7 @@ pub(crate) enum ServiceAllocation {
     Dedicated(factor::ActorWeakAddr<CospaceActor>),
 
     /// service-instance shared by all cospace instances
-    Shared(Arc<DashMap<Uuid, factor::MessageClusterAddr<ServiceMessage>>>),
+    Shared(Arc<DashMap<Uuid, factor::ActorAddr<CospaceActor>>>),
 }
 
 /// Actor that owns and runs a Service.
@@ -98,14 +98,7 @@ where
             ClientMessageRoute::Ask(_) => match msg.payload {
                 MessagePayload::Binary(bytes) => {
                     let proto_res = self.service.answer_encoded(msg.client, &bytes);
-                    let t = async {
-                        if let Ok(encoded_bytes_vec) = proto_res.await {
-                            return Some(encoded_bytes_vec);
-                        } else {
-                            return None;
-                        }
-                    };
-                    return factor::MessageResponseType::Future(Box::pin(t));
+                    return factor::MessageResponseType::Future(proto_res);
                 }
                 _ => {
                     tracing::warn!(target: "server-event", "service_actor_ask_only_supported_for_binary_proto_payload");
@@ -116,7 +109,7 @@ where
             }
         }
 
-        factor::MessageResponseType::Result(None.into())
+        factor::MessageResponseType::Result(Err(ProtoResponseError::Failed).into())
     }
 }
 
@@ -143,17 +136,13 @@ impl ServiceExecutionContext {
     /// in its dedicated process, we won't need this as by default services will be dedicated.
     fn cospace_addr(
         &self, recipient: &ServiceMessageRecipient,
-    ) -> Option<factor::MessageClusterAddr<ServiceMessage>> {
+    ) -> Option<factor::ActorAddr<CospaceActor>> {
         match &self.allocation {
-            ServiceAllocation::Dedicated(addr) => {
-                addr.upgrade().map(|addr| addr.message_cluster_addr())
-            }
+            ServiceAllocation::Dedicated(addr) => addr.upgrade(),
             ServiceAllocation::Shared(map) => {
                 let key = match recipient {
                     ServiceMessageRecipient::Client(client) => client.cospace.uuid,
-                    ServiceMessageRecipient::Broadcast(topic) => match topic {
-                        ClientTopics::Cospace(cospace) => cospace.uuid,
-                    },
+                    ServiceMessageRecipient::Broadcast(topic) => topic.cospace().uuid,
                 };
 
                 if let Some(addr) = map.get(&key) {
@@ -176,9 +165,10 @@ impl ExecutionContext for ServiceExecutionContext {
                 sender: get_realtime_service_from_server_service(&self.s_type),
                 route: ServiceMessageRoute::Tell(recipient),
                 payload: MessagePayload::Text(text.to_string()),
+                delivery: Delivery::Reliable,
             };
 
-            addr.tell(msg).map_err(|e| tracing::error!(target: "server-event", "service_context_tell_text_to_client_failed: {}", e)).err();
+            addr.tell_addr(msg).map_err(|e| tracing::error!(target: "server-event", "service_context_tell_text_to_client_failed: {}", e)).err();
         } else {
             tracing::error!(target: "server-event", "cospace_recipient_not_found_in_service");
         }
@@ -190,9 +180,27 @@ impl ExecutionContext for ServiceExecutionContext {
                 sender: get_realtime_service_from_server_service(&self.s_type),
                 route: ServiceMessageRoute::Tell(recipient),
                 payload: MessagePayload::Binary(bytes.to_vec()),
+                delivery: Delivery::Reliable,
+            };
+
+            addr.tell_addr(msg).map_err(|e| tracing::error!(target: "server-event", "service_context_tell_encoded_to_client_failed: {}", e)).err();
+        } else {
+            tracing::error!(target: "server-event", "cospace_recipient_not_found_in_service");
+        }
+    }
+
+    fn tell_delivery_encoded(
+        &self, recipient: ServiceMessageRecipient, delivery: Delivery, bytes: &ProtoBytes,
+    ) {
+        if let Some(addr) = self.cospace_addr(&recipient) {
+            let msg = ServiceMessage {
+                sender: get_realtime_service_from_server_service(&self.s_type),
+                route: ServiceMessageRoute::Tell(recipient),
+                payload: MessagePayload::Binary(bytes.to_vec()),
+                delivery,
             };
 
-            addr.tell(msg).map_err(|e| tracing::error!(target: "server-event", "service_context_tell_encoded_to_client_failed: {}", e)).err();
+            addr.tell_addr(msg).map_err(|e| tracing::error!(target: "server-event", "service_context_tell_delivery_encoded_to_client_failed: {}", e)).err();
         } else {
             tracing::error!(target: "server-event", "cospace_recipient_not_found_in_service");
         }
@@ -204,13 +212,13 @@ impl ExecutionContext for ServiceExecutionContext {
                 sender: get_realtime_service_from_server_service(&self.s_type),
                 route: ServiceMessageRoute::Ask(client),
                 payload: MessagePayload::Binary(bytes.to_vec()),
+                delivery: Delivery::Reliable,
             };
 
             let response = Box::pin(async move {
-                addr.ask(msg)
+                addr.ask_addr(msg)
                     .await
-                    .and_then(|result| result.ok_or(factor::MessageSendError::ErrDefault))
-                    .map_err(|_e| ProtoResponseError)
+                    .unwrap_or(Err(ProtoResponseError::Failed))
             });
 
             return response;
@@ -218,7 +226,17 @@ impl ExecutionContext for ServiceExecutionContext {
             tracing::error!(target: "server-event", "cospace_recipient_not_found_in_service");
         }
 
-        Box::pin(async { Err(ProtoResponseError) })
+        Box::pin(async { Err(ProtoResponseError::Failed) })
+    }
+
+    fn update_client_state(&self, client: ClientId, update: ClientStateUpdate) {
+        if let Some(addr) = self.cospace_addr(&ServiceMessageRecipient::Client(client.clone())) {
+            let msg = ClientStateMessage { client, update };
+
+            addr.tell_addr(msg).map_err(|e| tracing::error!(target: "server-event", "service_context_update_client_state_failed: {}", e)).err();
+        } else {
+            tracing::error!(target: "server-event", "cospace_recipient_not_found_in_service");
+        }
     }
 
     fn spawn_ok(&self, task: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) {
@@ -2,6 +2,10 @@ use super::{service_actor::*, ClientConnectionMessage, ClientMessage};
 use fasttravel_rt_services::*;
 
 /// Static service configurations
+///
+/// The presence, activity and model services keep the state of the cospaces
+/// they serve (rosters, activities and models) in the service instance, so
+/// they always run with a pool size of one, other sizes are ignored.
 #[derive(Clone)]
 pub struct ServicesConfig {
     pub pool_size_core: u8,
@@ -11,6 +15,17 @@ pub struct ServicesConfig {
 }
 
 const DEAFULT_POOL_SIZE: u8 = 1;
+const STATEFUL_POOL_SIZE: u8 = 1;
+
+/// Pool size of a service keeping the state of the cospaces.
+fn stateful_pool_size(service: &Services, pool_size: u8) -> usize {
+    if pool_size != STATEFUL_POOL_SIZE {
+        tracing::warn!(target: "server-event", "service_pool_size_ignored: {:?}, {}", service, pool_size);
+    }
+
+    STATEFUL_POOL_SIZE as usize
+}
+
 impl Default for ServicesConfig {
     /// Default static service configurations
     fn default() -> Self {
@@ -110,9 +125,18 @@ impl ServicePool {
         let mut config_model = factor::ActorBuilderConfig::default();
 
         config_core.pool_size = Some(config_services.pool_size_core as usize);
-        config_presence.pool_size = Some(config_services.pool_size_presence as usize);
-        config_activity.pool_size = Some(config_services.pool_size_activity as usize);
-        config_model.pool_size = Some(config_services.pool_size_model as usize);
+        config_presence.pool_size = Some(stateful_pool_size(
+            &Services::Presence,
+            config_services.pool_size_presence,
+        ));
+        config_activity.pool_size = Some(stateful_pool_size(
+            &Services::Activity,
+            config_services.pool_size_activity,
+        ));
+        config_model.pool_size = Some(stateful_pool_size(
+            &Services::Model,
+            config_services.pool_size_model,
+        ));
 
         // Create and run the Core Service.
         let s_alloc = allocation.clone();
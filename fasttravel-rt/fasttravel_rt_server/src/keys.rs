@@ -0,0 +1,233 @@
+use jsonwebtoken::{
+    decode, decode_header, errors::ErrorKind, jwk::JwkSet, DecodingKey, TokenData, Validation,
+};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{RwLock, Weak};
+use std::time::Duration;
+
+use futures_timer::Delay;
+
+/// Location of the JSON Web Key Set (jwks.json) published by the session_lambda.
+#[derive(Clone, Debug)]
+pub enum JwksSource {
+    /// Path of the jwks.json file.
+    File(PathBuf),
+    /// URL of the jwks.json, e.g. a local endpoint serving the key set.
+    Url(String),
+}
+
+/// Errors loading the public keys or decoding the tokens with them.
+#[derive(Debug)]
+pub enum DecodingKeysError {
+    /// Failed to read or fetch the key set.
+    Load(String),
+    /// The key set is not valid or has no usable keys.
+    InvalidKeySet(String),
+    /// No active key matches the key id (kid) of the token.
+    UnknownKey(Option<String>),
+    /// The token is not valid for the key.
+    Token(jsonwebtoken::errors::Error),
+}
+
+impl std::fmt::Display for DecodingKeysError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Load(e) => write!(f, "key_set_load_failed: {}", e),
+            Self::InvalidKeySet(e) => write!(f, "key_set_invalid: {}", e),
+            Self::UnknownKey(kid) => write!(f, "unknown_key: {:?}", kid),
+            Self::Token(e) => write!(f, "token_invalid: {}", e),
+        }
+    }
+}
+
+/// Public keys to decode requests and tickets issued by
+/// the session_manager/session_lambda.
+///
+/// The keys are either a single key (e.g. a PEM string) used for all the
+/// tokens, or a JSON Web Key Set where the key is selected by the key id
+/// (kid) in the token header. Multiple keys of the set are active at once,
+/// so that the session_lambda could rotate its keys: publish the new key,
+/// start signing with it and retire the old key once its tokens expire.
+/// The key set is reloaded from its source without restarting the node.
+pub struct PublicDecodingKeys {
+    source: Option<JwksSource>,
+    keys: RwLock<HashMap<String, DecodingKey>>,
+    // key used for the tokens without a key id of the key set.
+    single: Option<DecodingKey>,
+}
+
+impl Default for PublicDecodingKeys {
+    fn default() -> Self {
+        Self::from_key(DecodingKey::from_secret(b"secret"))
+    }
+}
+
+impl PublicDecodingKeys {
+    /// Use a single key for all the tokens.
+    pub fn from_key(key: DecodingKey) -> Self {
+        Self {
+            source: None,
+            keys: RwLock::new(HashMap::new()),
+            single: Some(key),
+        }
+    }
+
+    /// Use a single EC public key in PEM format for all the tokens.
+    pub fn from_ec_pem(pem: &str) -> Result<Self, DecodingKeysError> {
+        DecodingKey::from_ec_pem(pem.as_bytes())
+            .map(Self::from_key)
+            .map_err(|e| DecodingKeysError::InvalidKeySet(e.to_string()))
+    }
+
+    /// Use the keys of a JSON Web Key Set document, without a source to reload from.
+    pub fn from_jwks_str(jwks: &str) -> Result<Self, DecodingKeysError> {
+        Ok(Self {
+            source: None,
+            keys: RwLock::new(parse_jwks(jwks)?),
+            single: None,
+        })
+    }
+
+    /// Load the JSON Web Key Set from the source, the keys could later be
+    /// reloaded from the same source.
+    pub async fn from_jwks(source: JwksSource) -> Result<Self, DecodingKeysError> {
+        let keys = parse_jwks(&fetch_jwks(&source).await?)?;
+
+        Ok(Self {
+            source: Some(source),
+            keys: RwLock::new(keys),
+            single: None,
+        })
+    }
+
+    /// Reload the key set from its source and replace the active keys,
+    /// returns the number of active keys. The active keys are kept if
+    /// the reload fails.
+    pub async fn reload(&self) -> Result<usize, DecodingKeysError> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Err(DecodingKeysError::Load("no_key_set_source".to_string())),
+        };
+
+        let keys = parse_jwks(&fetch_jwks(source).await?)?;
+        let count = keys.len();
+
+        match self.keys.write() {
+            Ok(mut active) => *active = keys,
+            Err(e) => return Err(DecodingKeysError::Load(e.to_string())),
+        }
+
+        Ok(count)
+    }
+
+    /// Decode and validate the token with the key matching its key id.
+    pub(crate) fn decode<T: DeserializeOwned>(
+        &self, token: &str, validation: &Validation,
+    ) -> Result<TokenData<T>, DecodingKeysError> {
+        let header = decode_header(token).map_err(DecodingKeysError::Token)?;
+        let kid = header.kid;
+
+        // the key with the kid, else the single key.
+        let key = kid
+            .as_ref()
+            .and_then(|kid| self.keys.read().ok()?.get(kid).cloned())
+            .or_else(|| self.single.clone());
+
+        let key = match key {
+            Some(key) => key,
+            None => return Err(DecodingKeysError::UnknownKey(kid)),
+        };
+
+        // validate with the algorithm of the token only, as all the validation
+        // algorithms must match the kind of the key.
+        if !validation.algorithms.contains(&header.alg) {
+            return Err(DecodingKeysError::Token(ErrorKind::InvalidAlgorithm.into()));
+        }
+        let mut validation = validation.clone();
+        validation.algorithms = vec![header.alg];
+
+        decode::<T>(token, &key, &validation).map_err(DecodingKeysError::Token)
+    }
+
+    /// Periodically reload the key set from its source. Stops once the keys
+    /// are dropped or if the keys have no source. Must run on the tokio
+    /// runtime, as the key set is fetched with tokio io.
+    pub(crate) async fn reload_periodically(keys: Weak<PublicDecodingKeys>, interval: Duration) {
+        loop {
+            Delay::new(interval).await;
+
+            let keys = match keys.upgrade() {
+                Some(keys) if keys.source.is_some() => keys,
+                _ => return,
+            };
+
+            match keys.reload().await {
+                Ok(count) => {
+                    tracing::debug!(target: "server-event", "public_keys_reloaded: {}", count)
+                }
+                Err(e) => {
+                    tracing::error!(target: "server-event", "public_keys_reload_failed: {}", e)
+                }
+            }
+        }
+    }
+
+    pub(crate) fn has_source(&self) -> bool {
+        self.source.is_some()
+    }
+}
+
+async fn fetch_jwks(source: &JwksSource) -> Result<String, DecodingKeysError> {
+    match source {
+        JwksSource::File(path) => tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| DecodingKeysError::Load(e.to_string())),
+        JwksSource::Url(url) => {
+            let res = reqwest::get(url)
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(|e| DecodingKeysError::Load(e.to_string()))?;
+
+            res.text()
+                .await
+                .map_err(|e| DecodingKeysError::Load(e.to_string()))
+        }
+    }
+}
+
+/// Parse the key set, the keys without a key id or not usable for
+/// decoding are skipped.
+fn parse_jwks(jwks: &str) -> Result<HashMap<String, DecodingKey>, DecodingKeysError> {
+    let set: JwkSet =
+        serde_json::from_str(jwks).map_err(|e| DecodingKeysError::InvalidKeySet(e.to_string()))?;
+
+    let mut keys = HashMap::new();
+    for jwk in &set.keys {
+        let kid = match &jwk.common.key_id {
+            Some(kid) => kid.clone(),
+            None => {
+                tracing::error!(target: "server-event", "jwks_key_without_kid_skipped");
+                continue;
+            }
+        };
+
+        match DecodingKey::from_jwk(jwk) {
+            Ok(key) => {
+                keys.insert(kid, key);
+            }
+            Err(e) => {
+                tracing::error!(target: "server-event", "jwks_key_skipped: {}: {}", kid, e);
+            }
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(DecodingKeysError::InvalidKeySet(
+            "no_usable_keys".to_string(),
+        ));
+    }
+
+    Ok(keys)
+}
@@ -61,7 +61,6 @@
 #![forbid(unsafe_code)]
 
 use clap::Parser;
-use jsonwebtoken::DecodingKey;
 use std::sync::Arc;
 
 use factor::SystemRef;
@@ -69,12 +68,17 @@ use factor::SystemRef;
 mod authorization;
 mod client;
 mod cospace;
+mod keys;
 mod server;
 mod websocket;
 
 pub use authorization::*;
 use client::*;
+pub use client::{
+    LaneWeights, OutboundQueueConfig, OutboundQueueCounters, OutboundQueueStats, OverflowPolicy,
+};
 use cospace::*;
+pub use keys::*;
 pub use server::*;
 pub use websocket::HostSessionRequest;
 use websocket::*;
@@ -126,30 +130,21 @@ struct Args {
 ///
 /// This function must be awaited on inside the context of a tokio-runtime.
 ///
-/// @param session_request_key_str: The PUBLIC-KEY of the session-lambda service. This is the
-///         key that is used to decode and authenticate the host-session
-///         requests that the session-lambda will send to this server.
+/// @param public_keys: The PUBLIC-KEYS of the session-lambda service. These are the
+///         keys that are used to decode and authenticate the host-session
+///         requests that the session-lambda will send to this server, and the
+///         tickets it issues to the client-sdk. Either a single key or a
+///         JSON Web Key Set (jwks.json) that is reloaded periodically.
 ///         (1) Refer to fasttravel-rt/main/README.md to get details on
 ///         fasttravel's recommended deployment architecture and the
 ///         session-lambda.
 ///         (2) Refer to src/bin/mockers/realtime_main_node.rs for usage example.
 ///
 pub async fn initialize_and_run_main_node(
-    session_request_key_str: &str,
+    public_keys: PublicDecodingKeys,
 ) -> Result<(), NodeInitializationError> {
     tracing::info!(target: "server_event", "initialize_and_run_main_node");
 
-    let session_request_decoding = DecodingKey::from_ec_pem(session_request_key_str.as_bytes())
-        .map_err(|e| {
-            tracing::error!(target: "server_event", "FATAL_session_decoding_key_generation_failed {}", e);
-            NodeInitializationError
-        })?;
-
-    let public_keys = PublicDecodingKeys {
-        session_request_key_str: session_request_key_str.to_string(),
-        session_request_decoding,
-    };
-
     // configurations
     let mut config_server = ServerConfig::default();
     config_server.public_keys = Arc::new(public_keys);
@@ -267,8 +262,11 @@ pub fn build_remote_types_provider() -> factor::RemoteMessageTypeProvider {
     provider.register::<CospaceActor, GenerateClientIdMessage>();
     provider.register::<CospaceActor, ClientConnectionMessage>();
     provider.register::<CospaceActor, ClientMessage>();
+    provider.register::<CospaceActor, CospaceOccupancyMessage>();
     provider.register::<ClientConnectionActor, ServiceMessage>();
+    provider.register::<ClientConnectionActor, ClientCommand>();
     provider.register::<CospaceNodeManager, CreateCospaceActorMessage>();
+    provider.register::<CospaceNodeManager, TerminateCospaceActorMessage>();
 
     provider
 }
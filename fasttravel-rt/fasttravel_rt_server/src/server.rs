@@ -2,10 +2,13 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use factor;
+use fasttravel_rt_proto::{helpers::connection::WS_PROTOCOLS, realtime::connection::Feature};
 
 use crate::{
-    run_ws_server, CospaceManager, NodeInitializationError, ServicesConfig,
-    WebsocketOnUpgradeMessage, WebsocketServiceActor,
+    run_ws_server, ClaimsValidationConfig, ConnectionConfig, CospaceManager,
+    NodeInitializationError, OutboundQueueConfig, OutboundQueueCounters, OutboundQueueStats,
+    PublicDecodingKeys, ServicesConfig, TicketReplayStore, WebsocketOnUpgradeMessage,
+    WebsocketServiceActor,
 };
 
 /// Realtime server state that all message handlers receive to have access to
@@ -17,6 +20,9 @@ pub(crate) struct RealtimeServerState {
     pub(crate) cospace_mgr: CospaceManager,
     pub(crate) ws_addr: factor::MessageAddr<WebsocketOnUpgradeMessage>,
     pub(crate) public_keys: Arc<PublicDecodingKeys>,
+    pub(crate) used_tickets: TicketReplayStore,
+    pub(crate) claims_validation: Arc<ClaimsValidationConfig>,
+    pub(crate) protocol: Arc<ProtocolConfig>,
 }
 
 /// Configuration of the Workers. Paths and other details of the Worker nodes.
@@ -57,22 +63,90 @@ impl Default for WorkerNodesConfig {
     }
 }
 
-/// Public keys to decode requests and tickets issued by
-/// the session_manager/session_lambda.
-///
-/// We store both the string and the decoding-key as decoding-key
-/// is not serializable, in such scenarios we could use the string
-/// and regenerate the decoding-key.
-pub struct PublicDecodingKeys {
-    pub session_request_key_str: String,
-    pub session_request_decoding: jsonwebtoken::DecodingKey,
+/// Lifetime policy of the cospaces hosted in a kind of node. A cospace gets
+/// terminated automatically when idle or when it reaches the max lifetime.
+#[derive(Clone, Copy, Debug)]
+pub struct CospaceLifetimePolicy {
+    /// Terminate the cospace after no clients are connected for the duration.
+    pub idle_timeout: Option<Duration>,
+
+    /// Terminate the cospace after the duration since it was hosted.
+    pub max_lifetime: Option<Duration>,
+}
+
+/// Lifetime policies of the cospaces for the three kinds of nodes.
+/// Refer to lib.rs for the cospaces each kind of node is fit for.
+#[derive(Clone, Debug)]
+pub struct CospaceLifetimeConfig {
+    /// cospaces hosted in the main node.
+    pub main: CospaceLifetimePolicy,
+
+    /// cospaces hosted in the shared node.
+    pub shared: CospaceLifetimePolicy,
+
+    /// cospaces hosted in dedicated nodes.
+    pub dedicated: CospaceLifetimePolicy,
+
+    /// Scheduled cospaces not created within the duration are marked as failed.
+    pub creation_timeout: Duration,
+
+    /// Duration the failed and ended cospaces are remembered, so that the
+    /// status of the cospace could be queried.
+    pub retention_period: Duration,
+
+    /// Duration between successive checks of the hosted cospaces.
+    pub sweep_interval: Duration,
+
+    /// Duration a sweep waits for the occupancy of a cospace before skipping it.
+    pub occupancy_timeout: Duration,
+}
+
+// default cospace lifetime config.
+impl Default for CospaceLifetimeConfig {
+    fn default() -> Self {
+        Self {
+            // short-lived cospaces.
+            main: CospaceLifetimePolicy {
+                idle_timeout: Some(Duration::from_secs(300)),
+                max_lifetime: Some(Duration::from_secs(4 * 3600)),
+            },
+            // test/anonymous/demo cospaces.
+            shared: CospaceLifetimePolicy {
+                idle_timeout: Some(Duration::from_secs(120)),
+                max_lifetime: Some(Duration::from_secs(3600)),
+            },
+            dedicated: CospaceLifetimePolicy {
+                idle_timeout: Some(Duration::from_secs(1800)),
+                max_lifetime: None,
+            },
+            creation_timeout: Duration::from_secs(60),
+            retention_period: Duration::from_secs(3600),
+            sweep_interval: Duration::from_secs(30),
+            occupancy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Versions and optional features of the realtime protocol the server accepts,
+/// so that the server and the client-sdk could be upgraded independently.
+#[derive(Clone, Debug)]
+pub struct ProtocolConfig {
+    /// Accepted websocket subprotocols in order of preference, the first one
+    /// also offered by the client is selected for the connection.
+    pub subprotocols: Vec<String>,
+
+    /// Optional features the server supports, the features of a connection
+    /// are negotiated on the ticket handshake. Features not implemented by
+    /// the server are never negotiated.
+    pub features: Vec<Feature>,
 }
 
-impl Default for PublicDecodingKeys {
+// default protocol config.
+impl Default for ProtocolConfig {
     fn default() -> Self {
         Self {
-            session_request_key_str: "".to_string(),
-            session_request_decoding: jsonwebtoken::DecodingKey::from_secret(b"secret"),
+            subprotocols: WS_PROTOCOLS.iter().map(|p| p.to_string()).collect(),
+            features: vec![Feature::Batching],
         }
     }
 }
@@ -91,8 +165,32 @@ pub struct ServerConfig {
     /// Duration between successive heartbeats.
     pub heartbeat_interval: Duration,
 
+    /// Duration to wait for the response of a request, on both the client
+    /// requests to the services and the service requests to the client.
+    pub request_timeout: Duration,
+
+    /// Client will get disconnected if the ticket handshake doesn't succeed
+    /// within the duration after connection.
+    pub handshake_timeout: Duration,
+
     /// public key to decode the tickets provided to the authorized client-sdk.
     pub public_keys: Arc<PublicDecodingKeys>,
+
+    /// Duration between successive reloads of the public keys loaded from
+    /// a key set source (jwks.json).
+    pub public_keys_reload_interval: Duration,
+
+    /// Algorithms, issuer, leeway and expected claims of the tokens.
+    pub claims_validation: ClaimsValidationConfig,
+
+    /// Capacity and slow-consumer policies of the outbound queues of the clients.
+    pub outbound_queue: OutboundQueueConfig,
+
+    /// Idle and max lifetime policies of the hosted cospaces.
+    pub cospace_lifetime: CospaceLifetimeConfig,
+
+    /// Accepted subprotocols and supported features of the realtime protocol.
+    pub protocol: ProtocolConfig,
 }
 
 // default realtime server config.
@@ -103,7 +201,14 @@ impl Default for ServerConfig {
             wss_port: 27000,
             heartbeat_timeout: Duration::from_secs(180),
             heartbeat_interval: Duration::from_secs(30),
+            request_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
             public_keys: Arc::new(PublicDecodingKeys::default()),
+            public_keys_reload_interval: Duration::from_secs(600),
+            claims_validation: ClaimsValidationConfig::default(),
+            outbound_queue: OutboundQueueConfig::default(),
+            cospace_lifetime: CospaceLifetimeConfig::default(),
+            protocol: ProtocolConfig::default(),
         }
     }
 }
@@ -121,6 +226,18 @@ pub struct Server {
 
     /// The websocket service actor.
     ws: factor::ActorAddr<WebsocketServiceActor>,
+
+    /// Ids of the used single-use tickets.
+    used_tickets: TicketReplayStore,
+
+    /// Validation of the tokens.
+    claims_validation: Arc<ClaimsValidationConfig>,
+
+    /// Counters of the outbound queues of the clients.
+    outbound_stats: Arc<OutboundQueueStats>,
+
+    /// Accepted subprotocols and supported features.
+    protocol: Arc<ProtocolConfig>,
 }
 
 impl Server {
@@ -132,14 +249,53 @@ impl Server {
         // get the service configuration.
         let config_services = config_services.unwrap_or_default();
 
-        let cospace_mgr = CospaceManager::new(config_workers, config_services, &system).await;
+        let cospace_mgr = CospaceManager::new(
+            config_workers,
+            config_services,
+            config_server.cospace_lifetime.clone(),
+            &system,
+        )
+        .await;
+
+        // keep the public keys in sync with the key set of the session_lambda,
+        // the reload runs on the tokio runtime the server is built on.
+        if config_server.public_keys.has_source() {
+            tokio::spawn(PublicDecodingKeys::reload_periodically(
+                Arc::downgrade(&config_server.public_keys),
+                config_server.public_keys_reload_interval,
+            ));
+        }
 
         // create and run the websocket service actor.
         let system_moved = system.clone();
         let config = factor::ActorBuilderConfig::default();
-        let session_request_decoding = config_server.public_keys.session_request_decoding.clone();
-        let factory =
-            move |_| WebsocketServiceActor::new(&system_moved, session_request_decoding.clone());
+        let public_keys = config_server.public_keys.clone();
+        let claims_validation = Arc::new(config_server.claims_validation.clone());
+        let claims_validation_moved = claims_validation.clone();
+        let used_tickets = TicketReplayStore::new(claims_validation.leeway);
+        let used_tickets_moved = used_tickets.clone();
+        let outbound_stats = Arc::new(OutboundQueueStats::default());
+        let outbound_stats_moved = outbound_stats.clone();
+        let protocol = Arc::new(config_server.protocol.clone());
+        let protocol_moved = protocol.clone();
+        let config_connection = ConnectionConfig {
+            heartbeat_interval: config_server.heartbeat_interval,
+            heartbeat_timeout: config_server.heartbeat_timeout,
+            request_timeout: config_server.request_timeout,
+            handshake_timeout: config_server.handshake_timeout,
+            outbound_queue: config_server.outbound_queue,
+        };
+        let factory = move |_| {
+            WebsocketServiceActor::new(
+                &system_moved,
+                public_keys.clone(),
+                used_tickets_moved.clone(),
+                claims_validation_moved.clone(),
+                outbound_stats_moved.clone(),
+                protocol_moved.clone(),
+                config_connection,
+            )
+        };
 
         let spawn_item = factor::ActorBuilder::create(factory, &system, config)
             .ok_or(NodeInitializationError)?;
@@ -150,9 +306,19 @@ impl Server {
             system,
             cospace_mgr,
             ws,
+            used_tickets,
+            claims_validation,
+            outbound_stats,
+            protocol,
         })
     }
 
+    /// Dropped messages, backpressure and disconnect counters of the
+    /// outbound queues of the clients.
+    pub fn outbound_queue_counters(&self) -> OutboundQueueCounters {
+        self.outbound_stats.counters()
+    }
+
     /// Run the realtime server.
     pub async fn run_realtime(&self) {
         // Create the server state.
@@ -161,6 +327,9 @@ impl Server {
             cospace_mgr: self.cospace_mgr.clone(),
             ws_addr: self.ws.message_addr(),
             public_keys: self.config_server.public_keys.clone(),
+            used_tickets: self.used_tickets.clone(),
+            claims_validation: self.claims_validation.clone(),
+            protocol: self.protocol.clone(),
         };
 
         run_ws_server(
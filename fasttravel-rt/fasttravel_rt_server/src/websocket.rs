@@ -1,6 +1,8 @@
 mod websocket_actor;
 mod websocket_server;
 
+use fasttravel_rt_services::ClientIdentity;
+
 use crate::CospaceActor;
 
 pub(crate) use websocket_actor::*;
@@ -11,6 +13,10 @@ pub(crate) use websocket_server::*;
 pub(crate) struct WebsocketOnUpgradeMessage {
     pub(crate) socket: axum::WebSocket,
     pub(crate) cospace_addr: factor::ActorAddr<CospaceActor>,
+    /// identity verified from the connect ticket.
+    pub(crate) identity: ClientIdentity,
+    /// websocket subprotocol selected for the connection.
+    pub(crate) protocol: String,
 }
 
 impl factor::Message for WebsocketOnUpgradeMessage {
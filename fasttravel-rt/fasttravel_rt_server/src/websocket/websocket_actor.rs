@@ -1,8 +1,12 @@
+use std::sync::Arc;
+
 use factor::{self, ActorReceiverContext};
 
 use super::WebsocketOnUpgradeMessage;
 use crate::{
-    ClientConnectionActorCreator, ClientConnectionMessage, CreateClientConnectionActorMessage,
+    ClaimsValidationConfig, ClientConnectionActorCreator, ConnectionConfig,
+    CreateClientConnectionActorMessage, OutboundQueueStats, ProtocolConfig, PublicDecodingKeys,
+    TicketReplayStore,
 };
 
 /// Websocket service actor handling new client connections.
@@ -13,10 +17,22 @@ pub(crate) struct WebsocketServiceActor {
 
 impl WebsocketServiceActor {
     pub(crate) fn new(
-        system: &factor::SystemRef, session_request_decoding: jsonwebtoken::DecodingKey,
+        system: &factor::SystemRef, public_keys: Arc<PublicDecodingKeys>,
+        used_tickets: TicketReplayStore, claims_validation: Arc<ClaimsValidationConfig>,
+        outbound_stats: Arc<OutboundQueueStats>, protocol: Arc<ProtocolConfig>,
+        config_connection: ConnectionConfig,
     ) -> Self {
         let config = factor::ActorBuilderConfig::default();
-        let factory = move |_| ClientConnectionActorCreator::new(session_request_decoding.clone());
+        let factory = move |_| {
+            ClientConnectionActorCreator::new(
+                public_keys.clone(),
+                used_tickets.clone(),
+                claims_validation.clone(),
+                outbound_stats.clone(),
+                protocol.clone(),
+                config_connection,
+            )
+        };
         let spawn_item = factor::ActorBuilder::create(factory, system, config);
         let cnx_creator = system.run_actor(spawn_item.unwrap());
         Self { cnx_creator }
@@ -50,14 +66,14 @@ impl WebsocketServiceActor {
             .ask(CreateClientConnectionActorMessage {
                 socket: msg.socket,
                 cospace_addr: msg.cospace_addr.clone(),
+                identity: msg.identity,
+                protocol: msg.protocol,
             })
             .await
         {
-            Ok(Some((id, addr))) => {
-                // inform cospace of new client connection.
-                let _ = msg
-                    .cospace_addr
-                    .tell_addr(ClientConnectionMessage::Connect { client: id, addr });
+            Ok(Some((id, _addr))) => {
+                // the cospace is informed of the new client connection by the creator.
+                tracing::debug!(target: "server-event", "client_connection_created: {}", id.id);
             }
             Ok(None) => {
                 tracing::error!(target: "server-event", "error_in_ask_create_client_connection: ask_returns_None");
@@ -1,5 +1,6 @@
 pub(crate) mod axum {
     pub(crate) use axum::extract::ws::Message;
+    pub(crate) use axum::extract::ws::{close_code, CloseFrame};
     pub(crate) use axum::extract::ws::WebSocket;
     pub(crate) use axum::extract::ws::WebSocketUpgrade;
     pub(crate) use axum::extract::FromRequestParts;
@@ -7,6 +8,7 @@ pub(crate) mod axum {
     pub(crate) use axum::extract::{Path, Query, TypedHeader};
     pub(crate) use axum::http::request::Parts;
     pub(crate) use axum::http::StatusCode;
+    pub(crate) use axum::http::{header, HeaderMap};
     pub(crate) use axum::response::{IntoResponse, Response};
     pub(crate) use axum::routing::{get, post};
     pub(crate) use axum::Json;
@@ -23,7 +25,6 @@ mod tower {
     pub(crate) use tower_http::trace::TraceLayer;
 }
 
-use jsonwebtoken::decode;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
@@ -35,14 +36,16 @@ use crate::{
     WebsocketOnUpgradeMessage,
 };
 
-const WS_PROTOCOL: &'static str = "realtime-proto-v01";
-
 /// Run the realtime websocket server.
 ///
 /// example socket_addr: ([0, 0, 0, 0], 27000)
 /// example ws url: const socket = new WebSocket(
 ///      "wss://realtime.fasttravel.xyz/realtime/connect/67e55044-10b1-426f-9247-bb680e5fe0c8?ticket=gTbhgat...",
-///      "realtime-proto-v01");
+///      ["realtime-proto-v01"]);
+///
+/// The subprotocol of the connection is the first of the accepted subprotocols
+/// (refer to ProtocolConfig) offered by the client, the upgrade is rejected if
+/// the client offers none of them.
 pub(crate) async fn run_ws_server(socket_addr: ([u8; 4], u16), state: RealtimeServerState) {
     let app = axum::Router::with_state(state)
         .route("/realtime/host/", axum::post(realtime_host))
@@ -124,51 +127,88 @@ async fn realtime_host(
 
 /// Retrieve the status of a cospace.
 async fn realtime_status(
-    _claims: TicketClaimsStatus, axum::State(state): axum::State<RealtimeServerState>,
+    claims: TicketClaimsStatus, axum::State(state): axum::State<RealtimeServerState>,
     axum::Path(cospace_uuid): axum::Path<Uuid>,
-) -> impl axum::IntoResponse {
-    // check cospace status (NOT_FOUND, SCHEDULED, HOSTED, FAILED)
+) -> Result<String, AuthError> {
+    // the ticket is only valid for the status of its cospace.
+    if claims.cospace() != &cospace_uuid {
+        return Err(AuthError::WrongCredentials);
+    }
+
+    // check cospace status (NOT_FOUND, SCHEDULED, HOSTED, FAILED, ENDED)
     if state.cospace_mgr.hosted_cospaces().is_hosted(&cospace_uuid) {
-        return "HOSTED".to_string();
+        return Ok("HOSTED".to_string());
     } else if state
         .cospace_mgr
         .hosted_cospaces()
         .is_scheduled(&cospace_uuid)
     {
-        return "SCHEDULED".to_string();
+        return Ok("SCHEDULED".to_string());
     } else if state.cospace_mgr.hosted_cospaces().is_failed(&cospace_uuid) {
-        return "FAILED".to_string();
+        return Ok("FAILED".to_string());
+    } else if state.cospace_mgr.hosted_cospaces().is_ended(&cospace_uuid) {
+        return Ok("ENDED".to_string());
     }
 
-    "NOT_FOUND".to_string()
+    Ok("NOT_FOUND".to_string())
 }
 
 /// Handle new websocket client connections
 async fn realtime_connect(
     ws: axum::WebSocketUpgrade, axum::Path(uuid): axum::Path<Uuid>,
     params: Option<axum::Query<HashMap<String, String>>>,
-    user_agent: Option<axum::TypedHeader<headers::UserAgent>>,
+    user_agent: Option<axum::TypedHeader<headers::UserAgent>>, request_headers: axum::HeaderMap,
     axum::State(state): axum::State<RealtimeServerState>,
 ) -> Result<axum::Response, AuthError> {
     if let Some(axum::TypedHeader(user_agent)) = user_agent {
         tracing::trace!(target: "server-event", "`{}`_connected", user_agent.as_str());
     }
 
-    // get ticket from query parameter and validate
-    if !check_connect_authorization(&params, &state) {
-        return Err(AuthError::InvalidToken);
+    // reject the clients offering none of the accepted subprotocols, before the
+    // ticket is consumed.
+    if !offers_accepted_subprotocol(&request_headers, &state.protocol.subprotocols) {
+        tracing::debug!(target: "server-event", "realtime_connect_unsupported_subprotocol");
+
+        let res = (axum::StatusCode::BAD_REQUEST, "unsupported_subprotocol");
+        return Ok(axum::IntoResponse::into_response(res));
     }
 
+    // get ticket from query parameter and validate
+    let claims = match check_connect_authorization(&params, &uuid, &state) {
+        Some(claims) => claims,
+        None => return Err(AuthError::InvalidToken),
+    };
+
     // Make sure the cospace is already created
     if let Some(cospace_addr) = state.cospace_mgr.hosted_cospaces().get_cospace_addr(&uuid) {
         let addr_moved = cospace_addr.clone();
 
+        // the query ticket is single-use, consumed only once the connection
+        // is about to be upgraded.
+        if !state.used_tickets.consume(claims.jti(), claims.exp()) {
+            tracing::error!(target: "server-event", "connect_ticket_replayed");
+            return Err(AuthError::InvalidToken);
+        }
+        let identity = claims.identity();
+
         // inform the websocket service of new client connection.
         let wss = state.ws_addr.clone();
-        let res = ws.protocols([WS_PROTOCOL]).on_upgrade(|socket| async move {
+        let protocols = state.protocol.subprotocols.clone();
+        let res = ws.protocols(protocols).on_upgrade(|socket| async move {
+            // the offered subprotocols are checked before the upgrade.
+            let protocol = match socket.protocol().and_then(|p| p.to_str().ok()) {
+                Some(protocol) => protocol.to_string(),
+                None => {
+                    tracing::error!(target: "server-event", "realtime_connect_missing_subprotocol");
+                    return;
+                }
+            };
+
             let _ = wss.tell(WebsocketOnUpgradeMessage {
                 socket,
                 cospace_addr: addr_moved,
+                identity,
+                protocol,
             });
         });
 
@@ -178,20 +218,40 @@ async fn realtime_connect(
     Err(AuthError::WrongCredentials)
 }
 
-/// check connect request authorization
+/// check the client offers one of the accepted subprotocols.
+fn offers_accepted_subprotocol(headers: &axum::HeaderMap, accepted: &[String]) -> bool {
+    headers
+        .get_all(axum::header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|offered| accepted.iter().any(|protocol| protocol == offered.trim()))
+}
+
+/// check connect request authorization, returns the verified claims of the ticket.
+/// The single-use ticket is not consumed here.
 fn check_connect_authorization(
-    params: &Option<axum::Query<HashMap<String, String>>>, state: &RealtimeServerState,
-) -> bool {
+    params: &Option<axum::Query<HashMap<String, String>>>, cospace_uuid: &Uuid,
+    state: &RealtimeServerState,
+) -> Option<TicketClaimsQuery> {
     if let Some(axum::Query(params)) = params {
         if let Some(query_ticket) = params.get("ticket") {
-            let validation = TicketClaimsQuery::validation();
+            let validation = TicketClaimsQuery::validation(&state.claims_validation);
+
+            match state
+                .public_keys
+                .decode::<TicketClaimsQuery>(query_ticket, &validation)
+            {
+                Ok(data) => {
+                    // the query ticket is only valid for its cospace.
+                    if data.claims.cospace() != cospace_uuid {
+                        tracing::error!(target: "server-event", "connect_ticket_cospace_mismatch");
+                        return None;
+                    }
 
-            let decoding = &state.public_keys.session_request_decoding;
-            match decode::<TicketClaimsQuery>(query_ticket, decoding, &validation) {
-                Ok(_data) => {
                     tracing::debug!(target: "server-event", "check_connect_auth_ok");
 
-                    return true;
+                    return Some(data.claims);
                 }
                 Err(e) => {
                     tracing::error!(target: "server_event", "connect_ticket_auth_error: {}", e)
@@ -200,7 +260,7 @@ fn check_connect_authorization(
         }
     }
 
-    false
+    None
 }
 
 #[allow(dead_code)]
@@ -24,10 +24,37 @@ impl CospaceId {
 
 /// Client Id of the clients connected to a collaborative space.
 /// Client ids are unique inside a collaborative space, not globally.
+/// The same user could connect multiple clients, e.g. multiple tabs.
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct ClientId {
     pub id: u32,
     pub cospace: CospaceId,
+    pub identity: ClientIdentity,
+}
+
+/// Identity of the user connected through a client, verified from the
+/// tickets issued by the session_lambda.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClientIdentity {
+    pub user_id: String,
+    pub display_name: String,
+    pub role: ClientRole,
+}
+
+/// Role of the user in the collaborative space. The services declare the
+/// roles allowed to send their messages and to receive their broadcasts.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientRole {
+    /// the user hosting the cospace.
+    Host,
+    /// the users managing the cospace along with the host.
+    Admin,
+    /// the users participating in the activities of the cospace.
+    Participant,
+    /// the users only observing the cospace.
+    #[default]
+    Spectator,
 }
 
 /// Identifier/info of the persistent root in an applications data-model 
@@ -10,9 +10,11 @@ use futures::future::Future;
 use serde::{Deserialize, Serialize};
 
 mod ids;
+mod policy;
 mod services;
 
-pub use ids::{ClientId, CospaceId, ModelRoot};
+pub use ids::{ClientId, ClientIdentity, ClientRole, CospaceId, ModelRoot};
+pub use policy::*;
 pub use services::*;
 
 /// streams and events that services publish and clients subscribe to.
@@ -21,13 +23,29 @@ pub use services::*;
 pub enum ClientTopics {
     /// all clients connected to the cospace receive the broadcast.
     Cospace(CospaceId),
+    /// only the clients participating in the activity receive the broadcast.
+    Activity(CospaceId, String),
+    /// only the clients subscribed to the named topic of the cospace
+    /// receive the broadcast.
+    Named(CospaceId, String),
+}
+
+impl ClientTopics {
+    /// The cospace the topic belongs to.
+    pub fn cospace(&self) -> &CospaceId {
+        match self {
+            ClientTopics::Cospace(cospace) => cospace,
+            ClientTopics::Activity(cospace, _) => cospace,
+            ClientTopics::Named(cospace, _) => cospace,
+        }
+    }
 }
 
 /// streams and events that clients publish and services subscribe to.
 #[derive(Serialize, Deserialize)]
 #[derive(Clone, Debug)]
 pub enum ServiceTopics {
-    /// all services receive the broadcast.
+    /// all the services of the cospace receive the broadcast.
     Default,
 }
 
@@ -76,8 +94,53 @@ pub type ProtoBytes = [u8];
 pub type ProtoResponse = Pin<Box<dyn Future<Output = Result<Vec<u8>, ProtoResponseError>> + Send>>;
 
 /// Message Sender Error.
-#[derive(Debug)]
-pub struct ProtoResponseError;
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+pub enum ProtoResponseError {
+    /// the request could not be answered.
+    Failed,
+    /// no response within the request deadline.
+    Timeout,
+    /// the client disconnected before responding.
+    Disconnected,
+    /// the role of the client is not allowed to send the request.
+    Unauthorized,
+}
+
+/// State of a client kept by the host collaboration space, updated by the
+/// services and used by the collaboration space to route messages.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+pub enum ClientStateUpdate {
+    /// client joined an activity (Some) or left its activity (None).
+    Activity(Option<String>),
+    /// client subscribed to the named topic of its cospace.
+    Subscribe(String),
+    /// client unsubscribed from the named topic of its cospace.
+    Unsubscribe(String),
+}
+
+/// Delivery class of a message sent by a service to clients. The client
+/// connection keeps a separate outbound lane for every class, so that the
+/// droppable effects never delay the reliable state.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Delivery {
+    /// data-prioritized state (e.g. model commits), delivered in order and
+    /// never dropped.
+    Reliable,
+    /// data-prioritized state of the key (e.g. presence joins and leaves),
+    /// delivered in order and never dropped. The queued latest message of
+    /// the service with the same key is superseded and dropped, and the newer
+    /// latest messages of the key are delivered after it.
+    ReliableKeyed(String),
+    /// participant-prioritized state (e.g. presence), a queued message is
+    /// replaced by the newer message of the service with the same key.
+    Latest(String),
+    /// experience-prioritized effects, dropped first when the client is
+    /// not keeping up.
+    Droppable,
+}
 
 /// Execution context that the service depends on to send messages to host
 /// collaboration space and clients.
@@ -87,7 +150,17 @@ pub trait ExecutionContext: Send {
     fn spawn_ok(&self, task: Pin<Box<dyn Future<Output = ()> + Send + 'static>>);
     fn tell_text(&self, recipient: ServiceMessageRecipient, text: &str);
     fn tell_encoded(&self, recipient: ServiceMessageRecipient, bytes: &ProtoBytes);
+    fn tell_delivery_encoded(
+        &self, recipient: ServiceMessageRecipient, delivery: Delivery, bytes: &ProtoBytes,
+    );
     fn ask_encoded(&self, client: ClientId, bytes: &ProtoBytes) -> ProtoResponse;
+    fn update_client_state(&self, client: ClientId, update: ClientStateUpdate);
+
+    /// Publish to the clients subscribed to the named topic of the cospace.
+    fn publish_encoded(&self, cospace: &CospaceId, topic: &str, bytes: &ProtoBytes) {
+        let topic = ClientTopics::Named(cospace.clone(), topic.to_string());
+        self.tell_encoded(ServiceMessageRecipient::Broadcast(topic), bytes);
+    }
 }
 
 /// Trait Object of ExecutionContext
@@ -0,0 +1,137 @@
+use crate::*;
+
+/// All the roles.
+pub const ALL_ROLES: &[ClientRole] = &[
+    ClientRole::Host,
+    ClientRole::Admin,
+    ClientRole::Participant,
+    ClientRole::Spectator,
+];
+
+/// Roles participating in the cospace, all but the spectators.
+pub const MEMBER_ROLES: &[ClientRole] =
+    &[ClientRole::Host, ClientRole::Admin, ClientRole::Participant];
+
+/// Roles managing the cospace.
+pub const MANAGER_ROLES: &[ClientRole] = &[ClientRole::Host, ClientRole::Admin];
+
+/// Kind of the messages clients send to a service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    /// reads the state of the service, e.g. snapshot requests.
+    Read,
+    /// changes the state of the sender or the shared state, e.g. presence
+    /// updates, joining activities and model edits.
+    Write,
+    /// manages the cospace for all the clients, e.g. defining activities.
+    Manage,
+}
+
+/// Authorization policy a service declares. The host collaboration space
+/// enforces the policy before dispatching the messages.
+pub trait ServicePolicy {
+    /// Kind of the encoded client message, None if the message is not
+    /// a client message of the service.
+    fn message_kind(bytes: &ProtoBytes) -> Option<MessageKind>;
+
+    /// Roles allowed to send the kind of messages to the service.
+    fn senders(kind: MessageKind) -> &'static [ClientRole];
+
+    /// Roles receiving the broadcasts of the service on the topic.
+    fn receivers(topic: &ClientTopics) -> &'static [ClientRole];
+}
+
+impl Services {
+    /// Check if the role is allowed to send the encoded message to the service.
+    pub fn can_send(&self, role: ClientRole, bytes: &ProtoBytes) -> bool {
+        let kind = match self {
+            Services::Core => ServiceCore::message_kind(bytes),
+            Services::Presence => ServicePresence::message_kind(bytes),
+            Services::Activity => ServiceActivity::message_kind(bytes),
+            Services::Model => ServiceModel::message_kind(bytes),
+            _ => None,
+        };
+
+        kind.map(|kind| self.can_send_kind(role, kind))
+            .unwrap_or(false)
+    }
+
+    /// Check if the role is allowed to send the kind of messages to the service.
+    pub fn can_send_kind(&self, role: ClientRole, kind: MessageKind) -> bool {
+        let senders = match self {
+            Services::Core => ServiceCore::senders(kind),
+            Services::Presence => ServicePresence::senders(kind),
+            Services::Activity => ServiceActivity::senders(kind),
+            Services::Model => ServiceModel::senders(kind),
+            _ => &[],
+        };
+
+        senders.contains(&role)
+    }
+
+    /// Check if the role receives the broadcasts of the service on the topic.
+    pub fn can_receive(&self, role: ClientRole, topic: &ClientTopics) -> bool {
+        let receivers = match self {
+            Services::Core => ServiceCore::receivers(topic),
+            Services::Presence => ServicePresence::receivers(topic),
+            Services::Activity => ServiceActivity::receivers(topic),
+            Services::Model => ServiceModel::receivers(topic),
+            _ => &[],
+        };
+
+        receivers.contains(&role)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICES: [Services; 4] = [
+        Services::Core,
+        Services::Presence,
+        Services::Activity,
+        Services::Model,
+    ];
+
+    fn named(name: &str) -> ClientTopics {
+        ClientTopics::Named(CospaceId::generate(), name.to_string())
+    }
+
+    #[test]
+    fn spectators_are_excluded_from_the_members_topics() {
+        let topic = named("members/voice");
+
+        assert!(!Services::Core.can_receive(ClientRole::Spectator, &topic));
+        for role in MEMBER_ROLES {
+            assert!(Services::Core.can_receive(*role, &topic));
+        }
+    }
+
+    #[test]
+    fn all_roles_receive_the_public_topics() {
+        let cospace = CospaceId::generate();
+        let topics = [
+            ClientTopics::Cospace(cospace.clone()),
+            ClientTopics::Activity(cospace, "quiz".to_string()),
+            named("cursors"),
+        ];
+
+        for service in &SERVICES {
+            for topic in &topics {
+                for role in ALL_ROLES {
+                    assert!(service.can_receive(*role, topic));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_services_receive_nothing() {
+        let topic = ClientTopics::Cospace(CospaceId::generate());
+
+        for role in ALL_ROLES {
+            assert!(!Services::Logging.can_receive(*role, &topic));
+        }
+    }
+}
@@ -1,5 +1,13 @@
-
 //#[cfg(mockers)]
 pub mod mocker;
 //#[cfg(mockers)]
-pub use mocker::{ServiceActivity, ServiceCore, ServiceIdentity, ServiceModel, ServicePresence};
+pub use mocker::ServiceIdentity;
+
+mod activity;
+mod core;
+mod model;
+mod presence;
+pub use activity::ServiceActivity;
+pub use self::core::ServiceCore;
+pub use model::ServiceModel;
+pub use presence::ServicePresence;
@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use fasttravel_rt_proto::{
+    helpers::activity as proto_activity,
+    realtime::activity::{self as proto, message::Payload, Mode, Status},
+};
+
+use crate::*;
+
+/// Activities of a single cospace. A cospace hosts a single model-root,
+/// so these are the activity definitions of the hosted model-root.
+/// The activities are dropped when the last connected client leaves.
+#[derive(Default)]
+struct CospaceActivities {
+    definitions: HashMap<String, proto::Definition>,
+    /// activity and mode of every participating client, indexed by the client id.
+    members: HashMap<u32, (String, Mode)>,
+    /// connected clients of the cospace.
+    clients: HashSet<u32>,
+}
+
+impl CospaceActivities {
+    fn activities(&self) -> Vec<proto::Activity> {
+        self.definitions
+            .values()
+            .map(|definition| proto::Activity {
+                definition: Some(definition.clone()),
+                members: self.members_of(&definition.id),
+            })
+            .collect()
+    }
+
+    fn members_of(&self, activity_id: &str) -> Vec<proto::Member> {
+        self.members
+            .iter()
+            .filter(|(_, (activity, _))| activity == activity_id)
+            .map(|(client_id, (_, mode))| proto::Member {
+                client_id: *client_id,
+                mode: *mode as i32,
+            })
+            .collect()
+    }
+
+    fn define(&mut self, definition: proto::Definition) -> Status {
+        if definition.id.is_empty() {
+            return Status::Invalid;
+        }
+        if self.definitions.contains_key(&definition.id) {
+            return Status::AlreadyDefined;
+        }
+
+        self.definitions.insert(definition.id.clone(), definition);
+        Status::Ok
+    }
+
+    fn join(&mut self, client: &ClientId, request: &proto::JoinRequest) -> Status {
+        let client_id = client.id;
+        let mode = match Mode::from_i32(request.mode) {
+            Some(mode) => mode,
+            None => return Status::Invalid,
+        };
+
+        // the server decides the modes available to the client from its
+        // verified role, the spectators only join to watch.
+        if client.identity.role == ClientRole::Spectator && mode != Mode::Spectator {
+            return Status::ModeNotAllowed;
+        }
+
+        let definition = match self.definitions.get(&request.activity_id) {
+            Some(definition) => definition,
+            None => return Status::NotFound,
+        };
+
+        // a participant could be in only one activity at a time.
+        if self.members.contains_key(&client_id) {
+            return Status::AlreadyInActivity;
+        }
+
+        if !definition.allowed_modes.is_empty() && !definition.allowed_modes.contains(&request.mode)
+        {
+            return Status::ModeNotAllowed;
+        }
+
+        if mode == Mode::Participant && definition.capacity > 0 {
+            let participants = self
+                .members
+                .values()
+                .filter(|(activity, mode)| activity == &definition.id && *mode == Mode::Participant)
+                .count();
+
+            if participants >= definition.capacity as usize {
+                return Status::Full;
+            }
+        }
+
+        self.members
+            .insert(client_id, (request.activity_id.clone(), mode));
+        Status::Ok
+    }
+
+    fn leave(&mut self, client_id: u32) -> Option<String> {
+        self.members
+            .remove(&client_id)
+            .map(|(activity, _)| activity)
+    }
+
+    fn activity_of(&self, client_id: u32) -> Option<&String> {
+        self.members.get(&client_id).map(|(activity, _)| activity)
+    }
+}
+
+/// Service managing the activities available to the clients of a cospace.
+///
+/// Activities are defined per cospace (model-root), a client could join
+/// a single activity at a time in one of the modes the activity allows.
+/// The service broadcasts who joins and leaves which activity to the
+/// cospace, and relays the activity messages only to the members of the
+/// activity (the cospace routes them based on the client's activity).
+pub struct ServiceActivity {
+    dispatcher: ExecutionContextObj,
+    cospaces: HashMap<Uuid, CospaceActivities>,
+}
+
+impl ServiceActivity {
+    fn broadcast(&self, cospace: &CospaceId, bytes: &ProtoBytes) {
+        let recipient = ServiceMessageRecipient::Broadcast(ClientTopics::Cospace(cospace.clone()));
+        self.dispatcher.tell_encoded(recipient, bytes);
+    }
+
+    fn definitions(&self, cospace: &CospaceId) -> Vec<u8> {
+        let activities = self
+            .cospaces
+            .get(&cospace.uuid)
+            .map(|activities| activities.activities())
+            .unwrap_or_default();
+        proto_activity::create_activity_definitions(activities)
+    }
+
+    /// Process the define, join, leave and definitions requests,
+    /// returns the response to the requesting client.
+    fn process_request(&mut self, client: &ClientId, payload: Payload) -> Option<Vec<u8>> {
+        let activities = self.cospaces.entry(client.cospace.uuid).or_default();
+
+        match payload {
+            Payload::DefineReq(define) => {
+                let definition = define.definition.unwrap_or_default();
+                let activity_id = definition.id.clone();
+
+                let status = activities.define(definition);
+                if status == Status::Ok {
+                    let definitions = self.definitions(&client.cospace);
+                    self.broadcast(&client.cospace, &definitions);
+                }
+
+                Some(proto_activity::create_activity_response(
+                    status,
+                    activity_id,
+                ))
+            }
+            Payload::JoinReq(join) => {
+                let status = activities.join(client, &join);
+                if status == Status::Ok {
+                    let member = proto::Member {
+                        client_id: client.id,
+                        mode: join.mode,
+                    };
+
+                    self.dispatcher.update_client_state(
+                        client.clone(),
+                        ClientStateUpdate::Activity(Some(join.activity_id.clone())),
+                    );
+
+                    let joined =
+                        proto_activity::create_activity_joined(join.activity_id.clone(), member);
+                    self.broadcast(&client.cospace, &joined);
+                }
+
+                Some(proto_activity::create_activity_response(
+                    status,
+                    join.activity_id,
+                ))
+            }
+            Payload::LeaveReq(_) => match activities.leave(client.id) {
+                Some(activity_id) => {
+                    self.dispatcher
+                        .update_client_state(client.clone(), ClientStateUpdate::Activity(None));
+
+                    let left = proto_activity::create_activity_left(activity_id.clone(), client.id);
+                    self.broadcast(&client.cospace, &left);
+
+                    Some(proto_activity::create_activity_response(
+                        Status::Ok,
+                        activity_id,
+                    ))
+                }
+                None => Some(proto_activity::create_activity_response(
+                    Status::NotInActivity,
+                    String::new(),
+                )),
+            },
+            Payload::DefinitionsReq(_) => Some(self.definitions(&client.cospace)),
+            _ => None,
+        }
+    }
+
+    fn relay(&mut self, client: &ClientId, msg: proto::ActivityMessage) {
+        // clients could only send messages to the activity they participate in.
+        let participating = self
+            .cospaces
+            .get(&client.cospace.uuid)
+            .and_then(|activities| activities.activity_of(client.id))
+            .map(|activity| activity == &msg.activity_id)
+            .unwrap_or(false);
+
+        if !participating {
+            return;
+        }
+
+        let relayed = proto_activity::create_activity_relayed(
+            msg.activity_id.clone(),
+            client.id,
+            msg.payload,
+        );
+        let recipient = ServiceMessageRecipient::Broadcast(ClientTopics::Activity(
+            client.cospace.clone(),
+            msg.activity_id,
+        ));
+        self.dispatcher.tell_encoded(recipient, &relayed);
+    }
+}
+
+impl Service for ServiceActivity {
+    fn new(dispatcher: ExecutionContextObj) -> Self {
+        Self {
+            dispatcher,
+            cospaces: HashMap::new(),
+        }
+    }
+
+    fn recv_connect(&mut self, client: ClientId) {
+        self.cospaces
+            .entry(client.cospace.uuid)
+            .or_default()
+            .clients
+            .insert(client.id);
+
+        // every client receives the available activities on join.
+        let definitions = self.definitions(&client.cospace);
+        self.dispatcher
+            .tell_encoded(ServiceMessageRecipient::Client(client), &definitions);
+    }
+
+    fn recv_disconnect(&mut self, client: ClientId) {
+        let activities = match self.cospaces.get_mut(&client.cospace.uuid) {
+            Some(activities) => activities,
+            None => return,
+        };
+
+        activities.clients.remove(&client.id);
+        let left = activities.leave(client.id);
+
+        if activities.clients.is_empty() {
+            self.cospaces.remove(&client.cospace.uuid);
+        } else if let Some(activity_id) = left {
+            let left = proto_activity::create_activity_left(activity_id, client.id);
+            self.broadcast(&client.cospace, &left);
+        }
+    }
+
+    fn recv_text(&mut self, _client: ClientId, _text: &str) {
+        // text messages are only used for traces, no functionality.
+    }
+
+    fn recv_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) {
+        match proto_activity::decode_activity_message_and_extract_payload(bytes) {
+            Ok(Payload::ActivityMsg(msg)) => self.relay(&client, msg),
+            Ok(payload) => {
+                if let Some(response) = self.process_request(&client, payload) {
+                    self.dispatcher
+                        .tell_encoded(ServiceMessageRecipient::Client(client), &response);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn answer_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) -> ProtoResponse {
+        let response = proto_activity::decode_activity_message_and_extract_payload(bytes)
+            .ok()
+            .and_then(|payload| self.process_request(&client, payload))
+            .ok_or(ProtoResponseError::Failed);
+
+        Box::pin(async move { response })
+    }
+}
+
+impl ServicePolicy for ServiceActivity {
+    fn message_kind(bytes: &ProtoBytes) -> Option<MessageKind> {
+        match proto_activity::decode_activity_message_and_extract_payload(bytes).ok()? {
+            Payload::DefinitionsReq(_) => Some(MessageKind::Read),
+            Payload::JoinReq(_) | Payload::LeaveReq(_) | Payload::ActivityMsg(_) => {
+                Some(MessageKind::Write)
+            }
+            Payload::DefineReq(_) => Some(MessageKind::Manage),
+            _ => None,
+        }
+    }
+
+    fn senders(kind: MessageKind) -> &'static [ClientRole] {
+        match kind {
+            MessageKind::Read => ALL_ROLES,
+            // spectators watch the activities without taking part.
+            MessageKind::Write => MEMBER_ROLES,
+            MessageKind::Manage => MANAGER_ROLES,
+        }
+    }
+
+    fn receivers(_topic: &ClientTopics) -> &'static [ClientRole] {
+        // spectators follow the activities, joined in the spectator mode.
+        ALL_ROLES
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::SystemTime;
+
+use fasttravel_rt_proto::{
+    helpers::core as proto_core,
+    realtime::core::{message::Payload, SubscriptionStatus, TopicMessage},
+};
+
+use crate::*;
+
+/// Maximum length of a topic name in bytes.
+const MAX_TOPIC_LEN: usize = 128;
+
+/// Prefix of the named topics private to the members of the cospace, the
+/// spectators don't receive their messages.
+const MEMBERS_TOPIC_PREFIX: &str = "members/";
+
+/// Service providing the core functionalities to the clients of a cospace.
+///
+/// Answers the server-time requests, clients use the round trips to
+/// synchronize their clocks with the server clock.
+///
+/// Manages the subscriptions of the clients to the named topics of the
+/// cospace and publishes the topic messages of the clients. The cospace
+/// keeps the subscriptions and fans out the topic messages to the
+/// subscribers only, the topics prefixed with `members/` only to the
+/// subscribed members.
+pub struct ServiceCore {
+    dispatcher: ExecutionContextObj,
+}
+
+impl ServiceCore {
+    fn process_request(&self, client: &ClientId, payload: Payload) -> Option<Vec<u8>> {
+        match payload {
+            Payload::ServerTimeReq(_) => Some(proto_core::create_core_server_time_response(
+                SystemTime::now(),
+            )),
+            Payload::SubscribeReq(req) => Some(self.subscribe(client, req.topic, true)),
+            Payload::UnsubscribeReq(req) => Some(self.subscribe(client, req.topic, false)),
+            _ => None,
+        }
+    }
+
+    fn subscribe(&self, client: &ClientId, topic: String, subscribe: bool) -> Vec<u8> {
+        if !is_valid_topic(&topic) {
+            return proto_core::create_core_subscription_response(
+                SubscriptionStatus::InvalidTopic,
+                topic,
+                false,
+            );
+        }
+
+        let update = if subscribe {
+            ClientStateUpdate::Subscribe(topic.clone())
+        } else {
+            ClientStateUpdate::Unsubscribe(topic.clone())
+        };
+        self.dispatcher.update_client_state(client.clone(), update);
+
+        proto_core::create_core_subscription_response(SubscriptionStatus::Ok, topic, subscribe)
+    }
+
+    fn publish(&self, client: &ClientId, msg: TopicMessage) {
+        if !is_valid_topic(&msg.topic) {
+            return;
+        }
+
+        let relayed =
+            proto_core::create_core_topic_relayed(msg.topic.clone(), client.id, msg.payload);
+        self.dispatcher
+            .publish_encoded(&client.cospace, &msg.topic, &relayed);
+    }
+}
+
+fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty() && topic.len() <= MAX_TOPIC_LEN
+}
+
+impl Service for ServiceCore {
+    fn new(dispatcher: ExecutionContextObj) -> Self {
+        Self { dispatcher }
+    }
+
+    fn recv_connect(&mut self, _client: ClientId) {}
+
+    fn recv_disconnect(&mut self, _client: ClientId) {}
+
+    fn recv_text(&mut self, client: ClientId, text: &str) {
+        // text messages are only used for traces, echoed back to the client
+        // on the debug text channel.
+        self.dispatcher
+            .tell_text(ServiceMessageRecipient::Client(client), text);
+    }
+
+    fn recv_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) {
+        match proto_core::decode_core_message_and_extract_payload(bytes.to_vec()) {
+            Ok(Payload::Publish(msg)) => self.publish(&client, msg),
+            Ok(payload) => {
+                if let Some(response) = self.process_request(&client, payload) {
+                    self.dispatcher
+                        .tell_encoded(ServiceMessageRecipient::Client(client), &response);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn answer_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) -> ProtoResponse {
+        let response = proto_core::decode_core_message_and_extract_payload(bytes.to_vec())
+            .ok()
+            .and_then(|payload| self.process_request(&client, payload))
+            .ok_or(ProtoResponseError::Failed);
+
+        Box::pin(async move { response })
+    }
+}
+
+impl ServicePolicy for ServiceCore {
+    fn message_kind(bytes: &ProtoBytes) -> Option<MessageKind> {
+        match proto_core::decode_core_message_and_extract_payload(bytes.to_vec()).ok()? {
+            Payload::ServerTimeReq(_) | Payload::SubscribeReq(_) | Payload::UnsubscribeReq(_) => {
+                Some(MessageKind::Read)
+            }
+            Payload::Publish(_) => Some(MessageKind::Write),
+            _ => None,
+        }
+    }
+
+    fn senders(kind: MessageKind) -> &'static [ClientRole] {
+        match kind {
+            MessageKind::Read => ALL_ROLES,
+            // spectators follow the topics without publishing.
+            MessageKind::Write => MEMBER_ROLES,
+            MessageKind::Manage => &[],
+        }
+    }
+
+    fn receivers(topic: &ClientTopics) -> &'static [ClientRole] {
+        match topic {
+            ClientTopics::Named(_, name) if name.starts_with(MEMBERS_TOPIC_PREFIX) => MEMBER_ROLES,
+            _ => ALL_ROLES,
+        }
+    }
+}
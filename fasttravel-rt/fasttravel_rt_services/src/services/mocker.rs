@@ -1,11 +1,7 @@
 use crate::*;
 
 // type aliases till we push the 0.0.1-dev.0-services branch.
-pub type ServiceCore = Mocker;
 pub type ServiceIdentity = Mocker;
-pub type ServicePresence = Mocker;
-pub type ServiceActivity = Mocker;
-pub type ServiceModel = Mocker;
 
 // Mocking service used for testing.
 pub struct Mocker {
@@ -42,6 +38,6 @@ impl Service for Mocker {
 
     fn answer_encoded(&mut self, client: ClientId, _bytes: &ProtoBytes) -> ProtoResponse {
         println!("Mocker_answer_encoded | client_id: {}", client.id);
-        Box::pin(async { Err(ProtoResponseError) })
+        Box::pin(async { Err(ProtoResponseError::Failed) })
     }
 }
@@ -0,0 +1,216 @@
+mod store;
+
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use fasttravel_rt_crdt::Replica;
+use fasttravel_rt_proto::{
+    helpers::model as proto_model,
+    realtime::model::{self as proto, message::Payload, Mode},
+};
+
+use crate::*;
+use store::ModelStore;
+
+/// Replica id of the service, the service merges and relays but never
+/// creates CRDT operations. Client replicas use the client id.
+const SERVICE_REPLICA: u32 = u32::MAX;
+
+/// Model of a single cospace, the mode is selected by the first request.
+enum CospaceModel {
+    /// The service is the authority and applies the transactions.
+    Authoritative(ModelStore),
+    /// The service merges and relays the CRDT updates of the replicas.
+    Crdt(Replica),
+}
+
+impl CospaceModel {
+    fn new(mode: Mode) -> Self {
+        match mode {
+            Mode::Authoritative => Self::Authoritative(ModelStore::default()),
+            Mode::Crdt => Self::Crdt(Replica::new(SERVICE_REPLICA)),
+        }
+    }
+
+    fn snapshot(&self, client: &ClientId) -> Vec<u8> {
+        match self {
+            Self::Authoritative(store) => {
+                proto_model::create_model_snapshot(store.version(), store.elements())
+            }
+            Self::Crdt(replica) => {
+                proto_model::create_model_crdt_snapshot(client.id, replica.snapshot())
+            }
+        }
+    }
+}
+
+/// Service managing the realtime models opened in the cospace.
+///
+/// The model of a cospace runs in one of two modes (data-prioritized state):
+/// * Authoritative: clients ask the service to apply transactions, the
+///   service validates and applies them in the order received, answers
+///   with a commit or a reject and broadcasts the committed operations.
+/// * CRDT: clients apply their changes to a local replica and send the
+///   CRDT operations, the service merges them into its replica and relays
+///   them to the cospace.
+///
+/// The model of a cospace is freed when its last connected client leaves.
+pub struct ServiceModel {
+    dispatcher: ExecutionContextObj,
+    models: HashMap<Uuid, CospaceModel>,
+    /// connected clients of every cospace, indexed by the cospace uuid.
+    clients: HashMap<Uuid, HashSet<u32>>,
+}
+
+impl ServiceModel {
+    fn model(&mut self, cospace: &CospaceId, mode: Mode) -> &mut CospaceModel {
+        self.models
+            .entry(cospace.uuid)
+            .or_insert_with(|| CospaceModel::new(mode))
+    }
+
+    fn recv_crdt_update(&mut self, client: ClientId, update: proto::CrdtUpdate) {
+        // updates on an authoritative model are dropped.
+        let replica = match self.model(&client.cospace, Mode::Crdt) {
+            CospaceModel::Crdt(replica) => replica,
+            CospaceModel::Authoritative(_) => return,
+        };
+
+        // relay only the operations merged into the service replica, the
+        // operations tagged with the dots of other clients are dropped.
+        let ops: Vec<_> = update
+            .ops
+            .into_iter()
+            .filter(|op| replica.apply_from(client.id, op).is_ok())
+            .collect();
+
+        if !ops.is_empty() {
+            let relay = proto_model::create_model_crdt_relay(client.id, ops);
+            let recipient =
+                ServiceMessageRecipient::Broadcast(ClientTopics::Cospace(client.cospace.clone()));
+            self.dispatcher.tell_encoded(recipient, &relay);
+        }
+    }
+
+    fn answer_transaction(&mut self, client: ClientId, transaction: proto::Transaction) -> Vec<u8> {
+        let store = match self.model(&client.cospace, Mode::Authoritative) {
+            CospaceModel::Authoritative(store) => store,
+            CospaceModel::Crdt(_) => {
+                let reject = proto::Reject {
+                    reason: proto::RejectReason::Invalid as i32,
+                    op_index: 0,
+                    message: "model_mode_crdt".to_string(),
+                    version: 0,
+                };
+                return proto_model::create_model_transaction_reject(reject);
+            }
+        };
+
+        match store.apply(&transaction) {
+            Ok(version) => {
+                let committed =
+                    proto_model::create_model_committed(version, client.id, transaction.ops);
+                let recipient = ServiceMessageRecipient::Broadcast(ClientTopics::Cospace(
+                    client.cospace.clone(),
+                ));
+                self.dispatcher.tell_encoded(recipient, &committed);
+
+                proto_model::create_model_transaction_commit(version)
+            }
+            Err(reject) => proto_model::create_model_transaction_reject(reject),
+        }
+    }
+}
+
+impl Service for ServiceModel {
+    fn new(dispatcher: ExecutionContextObj) -> Self {
+        Self {
+            dispatcher,
+            models: HashMap::new(),
+            clients: HashMap::new(),
+        }
+    }
+
+    fn recv_connect(&mut self, client: ClientId) {
+        self.clients
+            .entry(client.cospace.uuid)
+            .or_default()
+            .insert(client.id);
+
+        // late joiners start from the current snapshot of an opened model.
+        if let Some(model) = self.models.get(&client.cospace.uuid) {
+            let snapshot = model.snapshot(&client);
+            self.dispatcher
+                .tell_encoded(ServiceMessageRecipient::Client(client), &snapshot);
+        }
+    }
+
+    fn recv_disconnect(&mut self, client: ClientId) {
+        let empty = match self.clients.get_mut(&client.cospace.uuid) {
+            Some(clients) => clients.remove(&client.id) && clients.is_empty(),
+            None => false,
+        };
+
+        if empty {
+            self.clients.remove(&client.cospace.uuid);
+            self.models.remove(&client.cospace.uuid);
+        }
+    }
+
+    fn recv_text(&mut self, _client: ClientId, _text: &str) {
+        // text messages are only used for traces, no functionality.
+    }
+
+    fn recv_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) {
+        // transactions are only accepted as asks, as they always get a response.
+        match proto_model::decode_model_message_and_extract_payload(bytes) {
+            Ok(Payload::SnapshotReq(req)) => {
+                let mode = Mode::from_i32(req.mode).unwrap_or(Mode::Authoritative);
+                let snapshot = self.model(&client.cospace, mode).snapshot(&client);
+                self.dispatcher
+                    .tell_encoded(ServiceMessageRecipient::Client(client), &snapshot);
+            }
+            Ok(Payload::CrdtUpdate(update)) => self.recv_crdt_update(client, update),
+            _ => {}
+        }
+    }
+
+    fn answer_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) -> ProtoResponse {
+        let response = match proto_model::decode_model_message_and_extract_payload(bytes) {
+            Ok(Payload::Transaction(transaction)) => {
+                Ok(self.answer_transaction(client, transaction))
+            }
+            Ok(Payload::SnapshotReq(req)) => {
+                let mode = Mode::from_i32(req.mode).unwrap_or(Mode::Authoritative);
+                Ok(self.model(&client.cospace, mode).snapshot(&client))
+            }
+            _ => Err(ProtoResponseError::Failed),
+        };
+
+        Box::pin(async move { response })
+    }
+}
+
+impl ServicePolicy for ServiceModel {
+    fn message_kind(bytes: &ProtoBytes) -> Option<MessageKind> {
+        match proto_model::decode_model_message_and_extract_payload(bytes).ok()? {
+            Payload::SnapshotReq(_) => Some(MessageKind::Read),
+            Payload::Transaction(_) | Payload::CrdtUpdate(_) => Some(MessageKind::Write),
+            _ => None,
+        }
+    }
+
+    fn senders(kind: MessageKind) -> &'static [ClientRole] {
+        match kind {
+            MessageKind::Read => ALL_ROLES,
+            // spectators follow the model without editing it.
+            MessageKind::Write => MEMBER_ROLES,
+            MessageKind::Manage => MANAGER_ROLES,
+        }
+    }
+
+    fn receivers(_topic: &ClientTopics) -> &'static [ClientRole] {
+        // spectators follow the model of the cospace.
+        ALL_ROLES
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use fasttravel_rt_proto::realtime::model::{
+    operation::Op, Element, Operation, Reject, RejectReason, Transaction,
+};
+
+/// Reason and message of a rejected operation.
+type OpRejection = (RejectReason, &'static str);
+
+/// Undo record of an applied operation, used to roll back a rejected transaction.
+enum Undo {
+    Created(String),
+    Replaced(Element),
+    Deleted(Vec<Element>),
+}
+
+/// Authoritative store of the realtime elements of a single cospace.
+///
+/// Transactions are validated and applied in the order they are received,
+/// every committed transaction increments the model version. The empty model
+/// is at version 1, a base version of 0 skips the conflict check.
+pub(super) struct ModelStore {
+    version: u64,
+    elements: HashMap<String, Element>,
+}
+
+impl Default for ModelStore {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            elements: HashMap::new(),
+        }
+    }
+}
+
+impl ModelStore {
+    pub(super) fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub(super) fn elements(&self) -> Vec<Element> {
+        self.elements.values().cloned().collect()
+    }
+
+    /// Validate and apply all the operations of the transaction, or none.
+    /// Returns the new model version on commit.
+    pub(super) fn apply(&mut self, transaction: &Transaction) -> Result<u64, Reject> {
+        if transaction.ops.is_empty() {
+            return Err(self.reject(RejectReason::Invalid, 0, "transaction_empty"));
+        }
+
+        if transaction.base_version != 0 && transaction.base_version != self.version {
+            return Err(self.reject(RejectReason::Conflict, 0, "transaction_base_version_stale"));
+        }
+
+        let mut undo = Vec::with_capacity(transaction.ops.len());
+        for (index, operation) in transaction.ops.iter().enumerate() {
+            if let Err((reason, message)) = self.apply_op(operation, &mut undo) {
+                self.rollback(undo);
+                return Err(self.reject(reason, index, message));
+            }
+        }
+
+        self.version += 1;
+        Ok(self.version)
+    }
+
+    fn apply_op(&mut self, operation: &Operation, undo: &mut Vec<Undo>) -> Result<(), OpRejection> {
+        match &operation.op {
+            Some(Op::Create(create)) => {
+                let element = create
+                    .element
+                    .as_ref()
+                    .ok_or((RejectReason::Invalid, "element_missing"))?;
+
+                if element.id.is_empty() {
+                    return Err((RejectReason::Invalid, "element_id_empty"));
+                }
+                if self.elements.contains_key(&element.id) {
+                    return Err((RejectReason::AlreadyExists, "element_already_exists"));
+                }
+                if !element.parent.is_empty() && !self.elements.contains_key(&element.parent) {
+                    return Err((RejectReason::NotFound, "parent_not_found"));
+                }
+                if element
+                    .properties
+                    .values()
+                    .any(|value| value.kind.is_none())
+                {
+                    return Err((RejectReason::Invalid, "property_value_unset"));
+                }
+
+                self.elements.insert(element.id.clone(), element.clone());
+                undo.push(Undo::Created(element.id.clone()));
+            }
+            Some(Op::Update(update)) => {
+                if update.properties.values().any(|value| value.kind.is_none()) {
+                    return Err((RejectReason::Invalid, "property_value_unset"));
+                }
+
+                let element = self
+                    .elements
+                    .get_mut(&update.id)
+                    .ok_or((RejectReason::NotFound, "element_not_found"))?;
+
+                undo.push(Undo::Replaced(element.clone()));
+                element.properties.extend(update.properties.clone());
+            }
+            Some(Op::Delete(delete)) => {
+                if !self.elements.contains_key(&delete.id) {
+                    return Err((RejectReason::NotFound, "element_not_found"));
+                }
+
+                let removed = self.remove_subtree(&delete.id);
+                undo.push(Undo::Deleted(removed));
+            }
+            Some(Op::SetProperty(set)) => {
+                if set.key.is_empty() {
+                    return Err((RejectReason::Invalid, "property_key_empty"));
+                }
+
+                let element = self
+                    .elements
+                    .get_mut(&set.id)
+                    .ok_or((RejectReason::NotFound, "element_not_found"))?;
+
+                undo.push(Undo::Replaced(element.clone()));
+                match set.value.as_ref().filter(|value| value.kind.is_some()) {
+                    Some(value) => {
+                        element.properties.insert(set.key.clone(), value.clone());
+                    }
+                    None => {
+                        element.properties.remove(&set.key);
+                    }
+                }
+            }
+            None => return Err((RejectReason::Invalid, "operation_unset")),
+        }
+
+        Ok(())
+    }
+
+    /// Remove the element and all its descendants.
+    fn remove_subtree(&mut self, id: &str) -> Vec<Element> {
+        let mut removed = Vec::new();
+        let mut pending = vec![id.to_string()];
+
+        while let Some(id) = pending.pop() {
+            if let Some(element) = self.elements.remove(&id) {
+                pending.extend(
+                    self.elements
+                        .values()
+                        .filter(|child| child.parent == id)
+                        .map(|child| child.id.clone()),
+                );
+                removed.push(element);
+            }
+        }
+
+        removed
+    }
+
+    fn rollback(&mut self, undo: Vec<Undo>) {
+        for record in undo.into_iter().rev() {
+            match record {
+                Undo::Created(id) => {
+                    self.elements.remove(&id);
+                }
+                Undo::Replaced(element) => {
+                    self.elements.insert(element.id.clone(), element);
+                }
+                Undo::Deleted(elements) => {
+                    for element in elements {
+                        self.elements.insert(element.id.clone(), element);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reject(&self, reason: RejectReason, op_index: usize, message: &str) -> Reject {
+        Reject {
+            reason: reason as i32,
+            op_index: op_index as u32,
+            message: message.to_string(),
+            version: self.version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fasttravel_rt_proto::realtime::model::{
+        value, CreateElement, DeleteElement, SetProperty, UpdateElement, Value,
+    };
+
+    fn element(id: &str, parent: &str) -> Element {
+        Element {
+            id: id.to_string(),
+            kind: "node".to_string(),
+            parent: parent.to_string(),
+            properties: HashMap::new(),
+        }
+    }
+
+    fn value(v: i64) -> Value {
+        Value {
+            kind: Some(value::Kind::IntValue(v)),
+        }
+    }
+
+    fn create(id: &str, parent: &str) -> Operation {
+        Operation {
+            op: Some(Op::Create(CreateElement {
+                element: Some(element(id, parent)),
+            })),
+        }
+    }
+
+    fn set(id: &str, key: &str, v: i64) -> Operation {
+        Operation {
+            op: Some(Op::SetProperty(SetProperty {
+                id: id.to_string(),
+                key: key.to_string(),
+                value: Some(value(v)),
+            })),
+        }
+    }
+
+    fn update(id: &str, key: &str, v: i64) -> Operation {
+        Operation {
+            op: Some(Op::Update(UpdateElement {
+                id: id.to_string(),
+                properties: HashMap::from([(key.to_string(), value(v))]),
+            })),
+        }
+    }
+
+    fn delete(id: &str) -> Operation {
+        Operation {
+            op: Some(Op::Delete(DeleteElement { id: id.to_string() })),
+        }
+    }
+
+    fn transaction(base_version: u64, ops: Vec<Operation>) -> Transaction {
+        Transaction { base_version, ops }
+    }
+
+    fn sorted(mut elements: Vec<Element>) -> Vec<Element> {
+        elements.sort_by(|a, b| a.id.cmp(&b.id));
+        elements
+    }
+
+    #[test]
+    fn rollback_on_failed_op() {
+        let mut store = ModelStore::default();
+        let setup = transaction(0, vec![create("a", ""), create("b", "a"), set("a", "x", 1)]);
+        assert_eq!(store.apply(&setup), Ok(2));
+        let before = sorted(store.elements());
+
+        // every kind of op is undone when a later op fails.
+        let ops = vec![
+            create("c", "a"),
+            update("a", "x", 2),
+            set("b", "y", 3),
+            delete("a"),
+            set("missing", "x", 4),
+        ];
+        let reject = store.apply(&transaction(0, ops)).unwrap_err();
+
+        assert_eq!(reject.reason, RejectReason::NotFound as i32);
+        assert_eq!(reject.op_index, 4);
+        assert_eq!(reject.version, 2);
+        assert_eq!(store.version(), 2);
+        assert_eq!(sorted(store.elements()), before);
+    }
+
+    #[test]
+    fn stale_base_version_conflicts() {
+        let mut store = ModelStore::default();
+
+        // a transaction built against the empty model.
+        assert_eq!(store.version(), 1);
+        assert_eq!(store.apply(&transaction(1, vec![create("a", "")])), Ok(2));
+
+        // a second transaction built against the empty model is stale.
+        let reject = store
+            .apply(&transaction(1, vec![create("b", "")]))
+            .unwrap_err();
+        assert_eq!(reject.reason, RejectReason::Conflict as i32);
+        assert_eq!(reject.version, 2);
+        assert_eq!(sorted(store.elements()).len(), 1);
+
+        // the current version and the blind writes are applied.
+        assert_eq!(store.apply(&transaction(2, vec![create("b", "")])), Ok(3));
+        assert_eq!(store.apply(&transaction(0, vec![create("c", "")])), Ok(4));
+    }
+}
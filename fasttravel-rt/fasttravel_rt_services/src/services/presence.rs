@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use fasttravel_rt_proto::{
+    helpers::presence as proto_presence,
+    realtime::presence::{self as proto, message::Payload},
+};
+
+use crate::*;
+
+/// Presence roster of a single cospace, indexed by the client id.
+#[derive(Default)]
+struct Roster {
+    participants: HashMap<u32, proto::State>,
+}
+
+impl Roster {
+    fn participant(&self, client_id: u32) -> Option<proto::Participant> {
+        self.participants
+            .get(&client_id)
+            .map(|state| proto::Participant {
+                client_id,
+                state: Some(state.clone()),
+            })
+    }
+
+    fn snapshot(&self) -> Vec<proto::Participant> {
+        self.participants
+            .iter()
+            .map(|(client_id, state)| proto::Participant {
+                client_id: *client_id,
+                state: Some(state.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Service managing the presence of the participants in a cospace.
+///
+/// The service keeps a roster for every cospace it serves (a service could
+/// be shared by multiple cospaces), hands the roster snapshot to every
+/// participant on join and broadcasts the join, leave and update deltas
+/// to the cospace.
+pub struct ServicePresence {
+    dispatcher: ExecutionContextObj,
+    rosters: HashMap<Uuid, Roster>,
+}
+
+impl ServicePresence {
+    /// Broadcast the join or leave of the participant, the pending updates of
+    /// the participant are superseded so that they never follow its leave.
+    fn broadcast(&self, cospace: &CospaceId, client_id: u32, bytes: &ProtoBytes) {
+        let recipient = ServiceMessageRecipient::Broadcast(ClientTopics::Cospace(cospace.clone()));
+        let delivery = Delivery::ReliableKeyed(client_id.to_string());
+        self.dispatcher
+            .tell_delivery_encoded(recipient, delivery, bytes);
+    }
+
+    /// Broadcast the latest state of the participant, clients not keeping up
+    /// with the updates only receive the latest state.
+    fn broadcast_latest(&self, cospace: &CospaceId, client_id: u32, bytes: &ProtoBytes) {
+        let recipient = ServiceMessageRecipient::Broadcast(ClientTopics::Cospace(cospace.clone()));
+        let delivery = Delivery::Latest(client_id.to_string());
+        self.dispatcher
+            .tell_delivery_encoded(recipient, delivery, bytes);
+    }
+
+    fn snapshot(&self, cospace: &CospaceId) -> Vec<u8> {
+        let participants = self
+            .rosters
+            .get(&cospace.uuid)
+            .map(|roster| roster.snapshot())
+            .unwrap_or_default();
+
+        proto_presence::create_presence_snapshot(participants)
+    }
+
+    fn update(&mut self, client: ClientId, state: proto::State) {
+        let roster = match self.rosters.get_mut(&client.cospace.uuid) {
+            Some(roster) => roster,
+            None => return,
+        };
+
+        // updates from clients not in the roster are dropped, the display
+        // name stays the verified name of the user.
+        if let Some(current) = roster.participants.get_mut(&client.id) {
+            *current = proto::State {
+                display_name: client.identity.display_name.clone(),
+                ..state
+            };
+        }
+
+        if let Some(participant) = roster.participant(client.id) {
+            let updated = proto_presence::create_presence_updated(participant);
+            self.broadcast_latest(&client.cospace, client.id, &updated);
+        }
+    }
+}
+
+impl Service for ServicePresence {
+    fn new(dispatcher: ExecutionContextObj) -> Self {
+        Self {
+            dispatcher,
+            rosters: HashMap::new(),
+        }
+    }
+
+    fn recv_connect(&mut self, client: ClientId) {
+        // seed the state with the verified display name of the user.
+        let state = proto::State {
+            display_name: client.identity.display_name.clone(),
+            ..Default::default()
+        };
+
+        // late joiners receive the roster of the other participants, then
+        // everyone (the joiner included) receives the delta.
+        let snapshot = self.snapshot(&client.cospace);
+        self.dispatcher
+            .tell_encoded(ServiceMessageRecipient::Client(client.clone()), &snapshot);
+
+        let roster = self.rosters.entry(client.cospace.uuid).or_default();
+        roster.participants.insert(client.id, state);
+        let participant = roster.participant(client.id);
+
+        if let Some(participant) = participant {
+            let joined = proto_presence::create_presence_joined(participant);
+            self.broadcast(&client.cospace, client.id, &joined);
+        }
+    }
+
+    fn recv_disconnect(&mut self, client: ClientId) {
+        let removed = self
+            .rosters
+            .get_mut(&client.cospace.uuid)
+            .map(|roster| roster.participants.remove(&client.id).is_some())
+            .unwrap_or(false);
+
+        if !removed {
+            return;
+        }
+
+        let left = proto_presence::create_presence_left(client.id);
+        self.broadcast(&client.cospace, client.id, &left);
+
+        // drop the roster once the cospace is empty.
+        let empty = self
+            .rosters
+            .get(&client.cospace.uuid)
+            .map(|roster| roster.participants.is_empty())
+            .unwrap_or(false);
+        if empty {
+            self.rosters.remove(&client.cospace.uuid);
+        }
+    }
+
+    fn recv_text(&mut self, _client: ClientId, _text: &str) {
+        // text messages are only used for traces, no functionality.
+    }
+
+    fn recv_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) {
+        match proto_presence::decode_presence_message_and_extract_payload(bytes) {
+            Ok(Payload::Update(update)) => {
+                self.update(client, update.state.unwrap_or_default());
+            }
+            Ok(Payload::SnapshotReq(_)) => {
+                let snapshot = self.snapshot(&client.cospace);
+                self.dispatcher
+                    .tell_encoded(ServiceMessageRecipient::Client(client), &snapshot);
+            }
+            _ => {}
+        }
+    }
+
+    fn answer_encoded(&mut self, client: ClientId, bytes: &ProtoBytes) -> ProtoResponse {
+        match proto_presence::decode_presence_message_and_extract_payload(bytes) {
+            Ok(Payload::SnapshotReq(_)) => {
+                let snapshot = self.snapshot(&client.cospace);
+                Box::pin(async move { Ok(snapshot) })
+            }
+            _ => Box::pin(async { Err(ProtoResponseError::Failed) }),
+        }
+    }
+}
+
+impl ServicePolicy for ServicePresence {
+    fn message_kind(bytes: &ProtoBytes) -> Option<MessageKind> {
+        match proto_presence::decode_presence_message_and_extract_payload(bytes).ok()? {
+            Payload::SnapshotReq(_) => Some(MessageKind::Read),
+            Payload::Update(_) => Some(MessageKind::Write),
+            _ => None,
+        }
+    }
+
+    fn senders(kind: MessageKind) -> &'static [ClientRole] {
+        match kind {
+            // spectators share their presence as well.
+            MessageKind::Read | MessageKind::Write => ALL_ROLES,
+            MessageKind::Manage => MANAGER_ROLES,
+        }
+    }
+
+    fn receivers(_topic: &ClientTopics) -> &'static [ClientRole] {
+        // spectators follow the roster of the cospace.
+        ALL_ROLES
+    }
+}